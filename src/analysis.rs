@@ -1,11 +1,16 @@
 //! Models and functions for analytic processing of recognised/parsed headers and other data
 //! structures.
 
-use cogset::{Euclid, Euclidean, KmeansBuilder, Point};
+use cogset::{BruteScan, Dbscan, Euclid, Euclidean, Point};
 
+use std::cmp;
+use std::f64;
 use std::fmt::Debug;
 use std::iter::{FromIterator, IntoIterator};
 use std::marker::Sized;
+use std::ops::Index;
+
+use chunks::ZipFile;
 
 #[derive(Debug)]
 /// A Euclidean Vector (point) generated from each potentially idiosyncratic feature found in zip
@@ -45,6 +50,77 @@ pub trait Instance {
     fn cluster(data: &[Self], k: usize) -> Result<Vec<Cluster<Self>>, ClusteringError>
     where
         Self: Sized;
+
+    /// Cluster a slice of `Vectorizable` `Instance`s with the given kmeans parameters, producing
+    /// a collection of `k` Clusters and the number of iterations it took to converge.
+    fn cluster_with(
+        data: &[Self],
+        k: usize,
+        params: &ClusterParams,
+    ) -> Result<(Vec<Cluster<Self>>, usize), ClusteringError>
+    where
+        Self: Sized;
+}
+
+#[derive(Clone, Copy, Debug)]
+/// Tuning knobs for the kmeans clustering
+///
+/// The defaults match cogset's. If a large or noisy set of headers won't converge, raising
+/// `max_iter` is the first thing to try.
+pub struct ClusterParams {
+    /// Maximum number of iterations before giving up on convergence
+    pub max_iter: usize,
+    /// Distance the cluster centres may move between iterations and still count as converged
+    pub tolerance: f64,
+    /// Seed for picking the initial cluster centres, or `None` for `DEFAULT_SEED`.
+    ///
+    /// The same headers clustered with the same seed always come out in the same clusters, so
+    /// two runs over a dump give the same archives. Trying a few different seeds is a way to see
+    /// how much a partition is down to luck.
+    pub seed: Option<u64>,
+    /// Whether to scale each dimension to zero mean and unit variance before clustering.
+    ///
+    /// Timestamps run into the billions where methods and flags are single figures, so without
+    /// this the timestamp is all kmeans ever sees. Turn it off to compare against how things used
+    /// to be clustered.
+    pub normalise: bool,
+}
+
+impl Default for ClusterParams {
+    fn default() -> Self {
+        ClusterParams {
+            max_iter: 100,
+            tolerance: 1e-6,
+            seed: None,
+            normalise: true,
+        }
+    }
+}
+
+/// Seed for the initial cluster centres when `ClusterParams` doesn't give one
+pub const DEFAULT_SEED: u64 = 0x2017_0a11_dec0_de5e;
+
+/// A tiny xorshift PRNG, which is all the randomness we need and saves dragging in a dependency
+/// for it.
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    /// A generator for `seed`, with zero nudged off the xorshift fixed point
+    pub(crate) fn new(seed: u64) -> Self {
+        XorShift(seed | 1)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
 }
 
 #[derive(Debug)]
@@ -106,6 +182,33 @@ impl<T: Instance> Cluster<T> {
             pos: 0,
         }
     }
+
+    /// Number of instances in the cluster
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether the cluster has no instances at all
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<T: Instance> Index<usize> for Cluster<T> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        &self.0[idx]
+    }
+}
+
+impl<'a, T: Instance> IntoIterator for &'a Cluster<T> {
+    type Item = &'a T;
+    type IntoIter = IterCluster<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
 }
 
 /// Cluster a collection of `Vectorizable` header chunk using the kmeans algorithm according to
@@ -124,39 +227,577 @@ impl<T: Instance> Cluster<T> {
 ///
 ///   A `Result` type containing wrapping either a `ClusteringError`, or better yet, a Vec of
 ///   clusters of pointers.
+///
+///   Clusters are ordered by the lowest dump pointer amongst their members, so archive numbering
+///   stays stable between runs and versions. The initial centres come from `DEFAULT_SEED`, so
+///   identical inputs always give identical clusters.
 pub fn cluster<T, W>(data: &[T], k: usize) -> Result<Vec<Cluster<T>>, ClusteringError>
+where
+    T: Instance + Clone,
+    Euclid<W>: Point + Clone + Euclidean,
+    W: Debug + AsRef<[f64]>,
+    Vec<Euclid<W>>: FromIterator<<<T as Instance>::Item as Vectorizable>::Output>,
+{
+    cluster_with(data, k, &ClusterParams::default()).map(|(clusters, _)| clusters)
+}
+
+/// As `cluster`, but with control over the kmeans parameters, seed included.
+///
+/// On success the number of iterations taken to converge is returned alongside the clusters,
+/// which is handy for working out how much headroom `params.max_iter` has.
+pub fn cluster_with<T, W>(
+    data: &[T],
+    k: usize,
+    params: &ClusterParams,
+) -> Result<(Vec<Cluster<T>>, usize), ClusteringError>
+where
+    T: Instance + Clone,
+    Euclid<W>: Point + Clone + Euclidean,
+    W: Debug + AsRef<[f64]>,
+    Vec<Euclid<W>>: FromIterator<<<T as Instance>::Item as Vectorizable>::Output>,
+{
+    if k == 0 || k > data.len() {
+        return Err(ClusteringError::Descriptive(format!(
+            "can't make {} clusters from {} headers",
+            k,
+            data.len()
+        )));
+    }
+    let d: Vec<Euclid<W>> = data.iter()
+        .map(|datum| datum.header().to_euclidean())
+        .collect();
+    let mut points = d.iter().map(|point| point.0.as_ref().to_vec()).collect::<Vec<_>>();
+    if params.normalise {
+        standardise(&mut points);
+    }
+
+    match kmeans(&points, k, params) {
+        Ok((groups, iterations)) => {
+            debug!("Clustering converged after {} iterations", iterations);
+            let mut clusters = groups
+                .iter()
+                .map(|idxes_for_cluster| {
+                    Cluster(
+                        // Map clustered data indexes back to pointers using data
+                        idxes_for_cluster
+                            .iter()
+                            .map(|&x| data[x].clone())
+                            .collect::<Vec<T>>(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            clusters.sort_by_key(|cluster| cluster.0.iter().map(T::ptr).min());
+            Ok((clusters, iterations))
+        }
+        Err(iterations) => {
+            error!("Clustering failed to converge after {} iterations", iterations);
+            Err(ClusteringError::Descriptive(format!(
+                "failed to converge within {} iterations",
+                iterations
+            )))
+        }
+    }
+}
+
+/// Z-score each dimension of `points` in place, so they all count for as much as each other. A
+/// dimension which is the same throughout is just centred, there being no spread to scale by.
+fn standardise(points: &mut [Vec<f64>]) {
+    let n = points.len() as f64;
+    let dims = points.first().map_or(0, |point| point.len());
+    for dim in 0..dims {
+        let mean = points.iter().map(|point| point[dim]).sum::<f64>() / n;
+        let sd = (points.iter().map(|point| (point[dim] - mean).powi(2)).sum::<f64>() / n).sqrt();
+        for point in points.iter_mut() {
+            point[dim] -= mean;
+            if sd > 0.0 {
+                point[dim] /= sd;
+            }
+        }
+    }
+}
+
+/// Squared distance between two points
+fn dist2(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// Index of the centre nearest to `point`
+fn nearest(point: &[f64], centres: &[Vec<f64>]) -> usize {
+    centres
+        .iter()
+        .map(|centre| dist2(point, centre))
+        .enumerate()
+        .fold((0, f64::INFINITY), |best, (i, d)| if d < best.1 { (i, d) } else { best })
+        .0
+}
+
+/// Pick `k` initial centres from `points` kmeans++ style: the first at random, and each after
+/// that with probability proportional to its squared distance from the nearest centre so far, so
+/// they start out spread across the groups rather than bunched in one.
+fn initial_centres(points: &[Vec<f64>], k: usize, rng: &mut XorShift) -> Vec<Vec<f64>> {
+    let mut centres = vec![points[(rng.next() % points.len() as u64) as usize].clone()];
+    while centres.len() < k {
+        let weights = points
+            .iter()
+            .map(|point| dist2(point, &centres[nearest(point, &centres)]))
+            .collect::<Vec<_>>();
+        let total: f64 = weights.iter().sum();
+        let idx = if total > 0.0 {
+            let mut target = rng.next_f64() * total;
+            weights
+                .iter()
+                .position(|&weight| if target < weight {
+                    true
+                } else {
+                    target -= weight;
+                    false
+                })
+                .unwrap_or(points.len() - 1)
+        } else {
+            // Everything's sat on a centre already, so it doesn't much matter
+            (rng.next() % points.len() as u64) as usize
+        };
+        centres.push(points[idx].clone());
+    }
+    centres
+}
+
+/// Lloyd's algorithm over `points` from seeded kmeans++ centres, returning the indexes of the
+/// points in each of the `k` clusters (some possibly empty) and the iterations it took, or the
+/// iterations given up after if it didn't converge.
+///
+/// We do this ourselves rather than leave it to cogset because cogset seeds its initial centres
+/// from the thread RNG with no way to pass one in, which had archives coming out differently from
+/// one run to the next.
+fn kmeans(points: &[Vec<f64>], k: usize, params: &ClusterParams)
+    -> Result<(Vec<Vec<usize>>, usize), usize>
+{
+    let mut rng = XorShift::new(params.seed.unwrap_or(DEFAULT_SEED));
+    let centres = initial_centres(points, k, &mut rng);
+    lloyd(points, centres, params)
+}
+
+/// Lloyd's algorithm from `centres`, returning the clusters and the iterations it took, or
+/// `params.max_iter` if it didn't converge in that many.
+fn lloyd(points: &[Vec<f64>], mut centres: Vec<Vec<f64>>, params: &ClusterParams)
+    -> Result<(Vec<Vec<usize>>, usize), usize>
+{
+    for iteration in 1..params.max_iter + 1 {
+        let mut groups = vec![vec![]; centres.len()];
+        for (i, point) in points.iter().enumerate() {
+            groups[nearest(point, &centres)].push(i);
+        }
+
+        let mut moved = 0.0f64;
+        for (centre, group) in centres.iter_mut().zip(&groups) {
+            // An empty cluster keeps its centre, in case something wanders back to it
+            if group.is_empty() {
+                continue;
+            }
+            let mut mean = vec![0.0; centre.len()];
+            for &i in group {
+                for (sum, x) in mean.iter_mut().zip(&points[i]) {
+                    *sum += *x;
+                }
+            }
+            for sum in &mut mean {
+                *sum /= group.len() as f64;
+            }
+            moved = moved.max(dist2(&mean, centre).sqrt());
+            *centre = mean;
+        }
+        if moved <= params.tolerance {
+            return Ok((groups, iteration));
+        }
+    }
+    Err(params.max_iter)
+}
+
+/// Cluster a collection of `Vectorizable` header chunks by density with DBSCAN, for when we don't
+/// trust the number of EOCDs to tell us how many clusters there ought to be.
+///
+/// Anything within `epsilon` of at least `min_points` others (itself included) is a core point,
+/// and clusters grow out from the core points. What doesn't end up in any cluster is noise, and
+/// comes back separately in dump order rather than being shoehorned in somewhere. Clusters are
+/// ordered by lowest dump pointer, as with `cluster`.
+///
+/// Picking `epsilon` takes some thought, since distances are in the units of the vectors: the
+/// timestamp dimension is in seconds, so an hour's slack is an `epsilon` of 3600.
+pub fn cluster_dbscan<T, W>(
+    data: &[T],
+    epsilon: f64,
+    min_points: usize,
+) -> Result<(Vec<Cluster<T>>, Vec<T>), ClusteringError>
 where
     T: Instance + Clone,
     Euclid<W>: Point + Clone + Euclidean,
     W: Debug,
     Vec<Euclid<W>>: FromIterator<<<T as Instance>::Item as Vectorizable>::Output>,
 {
+    if epsilon.is_nan() || epsilon <= 0.0 || min_points == 0 {
+        return Err(ClusteringError::Descriptive(format!(
+            "DBSCAN needs a positive epsilon and min_points, not {} and {}",
+            epsilon,
+            min_points
+        )));
+    }
     let d: Vec<Euclid<W>> = data.iter()
         .map(|datum| datum.header().to_euclidean())
         .collect();
 
-    let kmeans = KmeansBuilder::new().kmeans(&d, k);
-    match kmeans.converged() {
-        Ok(_) => {
-            Ok(
-                kmeans
-                    .clusters()
-                    .iter()
-                    .map(|&(_, ref idxes_for_cluster)| {
-                        Cluster(
-                            // Map clustered data indexes back to pointers using data
-                            idxes_for_cluster
-                                .iter()
-                                .map(|&x| data[x].clone())
-                                .collect::<Vec<T>>(),
-                        )
-                    })
-                    .collect(),
-            )
+    let mut dbscan = Dbscan::new(BruteScan::new(&d), epsilon, min_points);
+    let mut clusters = dbscan
+        .by_ref()
+        .map(|idxes_for_cluster| {
+            Cluster(idxes_for_cluster.iter().map(|&x| data[x].clone()).collect::<Vec<T>>())
+        })
+        .collect::<Vec<_>>();
+    clusters.sort_by_key(|cluster| cluster.0.iter().map(T::ptr).min());
+
+    let mut noise = dbscan.noise_points().iter().cloned().collect::<Vec<_>>();
+    noise.sort();
+    let noise = noise.into_iter().map(|x| data[x].clone()).collect::<Vec<T>>();
+    debug!("DBSCAN found {} clusters and {} noise points", clusters.len(), noise.len());
+    Ok((clusters, noise))
+}
+
+/// Match clusters of CDs up with the archives they most likely belong to, by how many entries each
+/// archive expects to find against how many are in each cluster, returning `(cluster, zip)` index
+/// pairs in cluster order.
+///
+/// Every cluster goes to a different archive, with the total squared difference in counts as
+/// small as it can be. Where there are more clusters than archives (or vice versa) the odd ones
+/// out are left off.
+pub fn match_clusters_to_zips<T: Instance>(clusters: &[Cluster<T>], zips: &[ZipFile])
+    -> Vec<(usize, usize)>
+{
+    let sizes = clusters.iter().map(|cluster| cluster.len() as u64).collect::<Vec<_>>();
+    let expected = zips.iter().map(|zip| zip.expected_cd_count()).collect::<Vec<_>>();
+    assign_by_count(&sizes, &expected)
+}
+
+/// Total squared difference between the size of each cluster and the entry count of the archive
+/// `match_clusters_to_zips` pairs it with.
+///
+/// Clusters and archives left unpaired count as if paired with an empty archive or cluster, i.e.
+/// by their squared size, or splitting awkward CDs off into a cluster of their own would score
+/// well while quietly losing them.
+fn count_mismatch<T: Instance>(clusters: &[Cluster<T>], zips: &[ZipFile]) -> u64 {
+    let pairs = match_clusters_to_zips(clusters, zips);
+    let square = |x: u64| x.saturating_mul(x);
+    let paired = pairs
+        .iter()
+        .map(|&(c, z)| {
+            let (size, expected) = (clusters[c].len() as u64, zips[z].expected_cd_count());
+            square(if size > expected { size - expected } else { expected - size })
+        })
+        .fold(0, u64::saturating_add);
+    let unpaired_clusters = (0..clusters.len())
+        .filter(|&c| !pairs.iter().any(|&(paired, _)| paired == c))
+        .map(|c| square(clusters[c].len() as u64))
+        .fold(0, u64::saturating_add);
+    let unpaired_zips = (0..zips.len())
+        .filter(|&z| !pairs.iter().any(|&(_, paired)| paired == z))
+        .map(|z| square(zips[z].expected_cd_count()))
+        .fold(0, u64::saturating_add);
+    paired.saturating_add(unpaired_clusters).saturating_add(unpaired_zips)
+}
+
+/// Cluster `data` with kmeans for each k within `spread` of the number of `zips`, keeping
+/// whichever k gives clusters whose sizes best match the entry counts the EOCDs are expecting.
+///
+/// For when EOCD detection has found one archive too many (a stray EOCD inside another) or too
+/// few (one lost to a missing page), which leaves the k clustering would otherwise use out by
+/// one and the partitions it gives nonsense. A k leaving clusters or archives unpaired has to make
+/// up for them, so it's only chosen where it makes the pairing better by more than that. Ties go
+/// to the k nearest the number of EOCDs, then the smaller, so a `spread` of 0 is just clustering
+/// with as many clusters as there are EOCDs.
+/// Returns the chosen k along with its clusters, or the last error if no k worked at all.
+pub fn choose_cluster_k<T, W>(
+    data: &[T],
+    zips: &[ZipFile],
+    spread: usize,
+    params: &ClusterParams,
+) -> Result<(usize, Vec<Cluster<T>>), ClusteringError>
+where
+    T: Instance + Clone,
+    Euclid<W>: Point + Clone + Euclidean,
+    W: Debug + AsRef<[f64]>,
+    Vec<Euclid<W>>: FromIterator<<<T as Instance>::Item as Vectorizable>::Output>,
+{
+    let eocds = zips.len();
+    let mut candidates = (eocds.saturating_sub(spread)..eocds + spread + 1)
+        .filter(|&k| k > 0)
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|&k| (if k > eocds { k - eocds } else { eocds - k }, k));
+
+    let mut best: Option<(usize, Vec<Cluster<T>>, u64)> = None;
+    let mut last_err = ClusteringError::Descriptive(format!("no k to try for {} EOCDs", eocds));
+    for k in candidates {
+        match cluster_with(data, k, params) {
+            Ok((clusters, _)) => {
+                let mismatch = count_mismatch(&clusters, zips);
+                debug!("Clustering with k = {} is out by {} on entry counts", k, mismatch);
+                if best.as_ref().map_or(true, |&(_, _, best_mismatch)| mismatch < best_mismatch) {
+                    best = Some((k, clusters, mismatch));
+                }
+            }
+            Err(e) => {
+                debug!("Clustering with k = {} failed: {:?}", k, e);
+                last_err = e;
+            }
         }
-        Err(e) => {
-            error!("Clustering failed to converge: {:?}", e);
-            Err(ClusteringError::Plain)
+    }
+    match best {
+        Some((k, clusters, _)) => Ok((k, clusters)),
+        None => Err(last_err),
+    }
+}
+
+/// Pair each of `sizes` up with a different one of `targets` (of which there must be at least as
+/// many, or the roles get swapped), minimising the total squared difference, as `(size, target)`
+/// index pairs in size order.
+///
+/// With a convex cost like this there's always a best pairing which doesn't cross over once both
+/// sides are sorted, so it's a matter of working out which targets to skip, which is a quick
+/// dynamic programme rather than anything Hungarian.
+fn assign_by_count(sizes: &[u64], targets: &[u64]) -> Vec<(usize, usize)> {
+    if sizes.len() > targets.len() {
+        let mut pairs = assign_by_count(targets, sizes)
+            .into_iter()
+            .map(|(target, size)| (size, target))
+            .collect::<Vec<_>>();
+        pairs.sort();
+        return pairs;
+    }
+
+    let mut by_size = (0..sizes.len()).collect::<Vec<_>>();
+    by_size.sort_by_key(|&i| (sizes[i], i));
+    let mut by_target = (0..targets.len()).collect::<Vec<_>>();
+    by_target.sort_by_key(|&j| (targets[j], j));
+    let cost = |i: usize, j: usize| {
+        let (size, target) = (sizes[by_size[i]], targets[by_target[j]]);
+        let diff = if size > target { size - target } else { target - size };
+        diff.saturating_mul(diff)
+    };
+
+    // best[i][j] is the least cost of pairing the first i sizes amongst the first j targets,
+    // where there are enough of them to go round
+    let (n, m) = (sizes.len(), targets.len());
+    let mut best = vec![vec![None; m + 1]; n + 1];
+    for j in 0..m + 1 {
+        best[0][j] = Some(0u64);
+    }
+    for i in 1..n + 1 {
+        for j in i..m + 1 {
+            let skip = best[i][j - 1];
+            let take = best[i - 1][j - 1]
+                .map(|so_far: u64| so_far.saturating_add(cost(i - 1, j - 1)));
+            best[i][j] = match (skip, take) {
+                (Some(skip), Some(take)) => Some(cmp::min(skip, take)),
+                (skip, take) => skip.or(take),
+            };
+        }
+    }
+
+    // Walk back through whichever choices got us there
+    let mut pairs = Vec::with_capacity(n);
+    let (mut i, mut j) = (n, m);
+    while i > 0 {
+        if best[i][j - 1].is_some() && best[i][j - 1] == best[i][j] {
+            j -= 1;
+        } else {
+            pairs.push((by_size[i - 1], by_target[j - 1]));
+            i -= 1;
+            j -= 1;
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+/// Shannon entropy of `bytes` in bits per byte, from 0.0 (all the same byte) to 8.0 (uniformly
+/// random, or near enough: compressed or encrypted data).
+pub fn entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use analysis::*;
+    use chunks::FragSys;
+
+    #[test]
+    fn clusters_matched_one_to_one() {
+        // Archives of 2 and 10 entries, then clusters of 3 and 4 CDs, both of which are nearer 2
+        // than 10
+        let small = (0..2).map(|i| (format!("s{}", i), vec![i as u8; 8])).collect::<Vec<_>>();
+        let big = (0..10).map(|i| (format!("b{}", i), vec![i as u8; 8])).collect::<Vec<_>>();
+        let files = |entries: &[(String, Vec<u8>)]| {
+            entries.iter().map(|&(ref name, ref data)| (&name[..], &data[..])).collect::<Vec<_>>()
+        };
+        let mut dump = vec![0u8; 0x10];
+        dump.extend(::testgen::stored_archive(&files(&small), 1_500_000_000));
+        dump.extend(::testgen::stored_archive(&files(&big), 1_600_000_000));
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zips = fs.find_zips();
+        let cds = fs.find_cds();
+        assert_eq!((zips.len(), cds.len()), (2, 12));
+        let expected = zips.iter().map(|zip| zip.expected_cd_count()).collect::<Vec<_>>();
+        assert_eq!(expected, vec![2, 10]);
+
+        let clusters = vec![Cluster::new(&cds[..3]), Cluster::new(&cds[3..7])];
+        assert_eq!(match_clusters_to_zips(&clusters, &zips), vec![(0, 0), (1, 1)]);
+        let clusters = vec![Cluster::new(&cds[3..7]), Cluster::new(&cds[..3])];
+        assert_eq!(match_clusters_to_zips(&clusters, &zips), vec![(0, 1), (1, 0)]);
+
+        // A third cluster has nowhere to go
+        let clusters = vec![Cluster::new(&cds[..2]), Cluster::new(&cds[2..3]),
+                            Cluster::new(&cds[3..12])];
+        assert_eq!(match_clusters_to_zips(&clusters, &zips), vec![(0, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn kmeans_recovers_separable_clusters() {
+        // Three tight groups a long way apart, which every seed ought to find
+        let points = vec![vec![0.0, 0.0], vec![0.1, 0.0], vec![0.0, 0.1],
+                          vec![10.0, 10.0], vec![10.1, 10.0], vec![10.0, 10.1],
+                          vec![-10.0, 10.0], vec![-10.0, 10.1]];
+        for seed in 0..16 {
+            let params = ClusterParams { seed: Some(seed), ..ClusterParams::default() };
+            let (mut groups, _) = kmeans(&points, 3, &params).unwrap();
+            for group in &mut groups {
+                group.sort();
+            }
+            groups.sort();
+            assert_eq!(groups, vec![vec![0, 1, 2], vec![3, 4, 5], vec![6, 7]], "seed {}", seed);
         }
     }
+
+    #[test]
+    fn cluster_k_out_of_range() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let mut fs = FragSys::from_bytes(::testgen::stored_archive(&files, 1_500_000_000), 0x400);
+        let cds = fs.find_cds();
+        let params = ClusterParams::default();
+        assert!(cluster_with(&cds, 0, &params).is_err());
+        assert!(cluster_with(&cds, cds.len() + 1, &params).is_err());
+        assert_eq!(cluster_with(&cds, cds.len(), &params).unwrap().0.len(), cds.len());
+    }
+
+    #[test]
+    fn cluster_k_one_more_than_eocds() {
+        // Two archives, unlike in every dimension but the offsets, one of which has lost its EOCD
+        let three = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..]), ("c.txt", &b"!"[..])];
+        let two = [("dd.txt", &b"hello"[..]), ("ee.txt", &b"world"[..])];
+        let mut dump = vec![0u8; 0x10];
+        dump.extend(::testgen::stored_archive(&three, 1_500_000_000));
+        let lost_eocd = dump.len() - 22;
+        dump[lost_eocd] = 0;
+        dump.extend(::testgen::deflated_archive(&two, 1_600_000_000));
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zips = fs.find_zips();
+        let cds = fs.find_cds();
+        assert_eq!((zips.len(), cds.len()), (1, 5));
+
+        // Without any spread it's as many clusters as EOCDs, everything in the one
+        let (k, clusters) = choose_cluster_k(&cds, &zips, 0, &ClusterParams::default()).unwrap();
+        assert_eq!((k, clusters.len(), clusters[0].len()), (1, 1, 5));
+        assert_eq!(count_mismatch(&clusters, &zips), 9);
+
+        // Splitting off the orphans pairs the rest up exactly, but the three left without an
+        // archive cost as much as they saved, so the tie goes to the k we started with rather
+        // than losing them
+        let (split, _) = cluster_with(&cds, 2, &ClusterParams::default()).unwrap();
+        assert_eq!(split.iter().map(Cluster::len).collect::<Vec<_>>(), vec![3, 2]);
+        assert_eq!(match_clusters_to_zips(&split, &zips), vec![(1, 0)]);
+        assert_eq!(count_mismatch(&split, &zips), 9);
+        let (k, clusters) = choose_cluster_k(&cds, &zips, 1, &ClusterParams::default()).unwrap();
+        assert_eq!((k, clusters.len(), clusters[0].len()), (1, 1, 5));
+    }
+
+    #[test]
+    fn cluster_k_one_fewer_than_eocds() {
+        // An archive of four entries, the last two a day later than the first two, then a stray
+        // EOCD expecting one entry whose CD has been lost
+        let four = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..]), ("c.txt", &b"!"[..]),
+                    ("d.txt", &b"?"[..])];
+        let mut archive = ::testgen::stored_archive(&four, 1_500_000_000);
+        let records = (0..archive.len() - 3)
+            .filter(|&i| &archive[i..i + 4] == b"PK\x01\x02")
+            .collect::<Vec<_>>();
+        for &record in &records[2..] {
+            archive[record + 14] += 1;
+        }
+        let mut stray = ::testgen::stored_archive(&[("x.txt", &b"x"[..])], 1_600_000_000);
+        let lost_cd = (0..stray.len() - 3)
+            .find(|&i| &stray[i..i + 4] == b"PK\x01\x02")
+            .unwrap();
+        stray[lost_cd] = 0;
+        let mut dump = vec![0u8; 0x10];
+        dump.extend(archive);
+        dump.extend(stray);
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zips = fs.find_zips();
+        let cds = fs.find_cds();
+        assert_eq!((zips.len(), cds.len()), (2, 4));
+        let expected = zips.iter().map(|zip| zip.expected_cd_count()).collect::<Vec<_>>();
+        assert_eq!(expected, vec![4, 1]);
+
+        // As many clusters as EOCDs splits the archive down the middle, which fits neither
+        let (split, _) = cluster_with(&cds, 2, &ClusterParams::default()).unwrap();
+        assert_eq!(split.iter().map(Cluster::len).collect::<Vec<_>>(), vec![2, 2]);
+        assert_eq!(count_mismatch(&split, &zips), 5);
+
+        // Whereas one cluster fits the archive exactly, leaving only the stray EOCD's one entry
+        // unaccounted for
+        let (k, clusters) = choose_cluster_k(&cds, &zips, 1, &ClusterParams::default()).unwrap();
+        assert_eq!((k, clusters.len(), clusters[0].len()), (1, 1, 4));
+        assert_eq!(count_mismatch(&clusters, &zips), 1);
+        assert_eq!(match_clusters_to_zips(&clusters, &zips), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn cluster_by_reference() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let mut fs = FragSys::from_bytes(::testgen::stored_archive(&files, 1_500_000_000), 0x400);
+        let cds = fs.find_cds();
+        let cluster = Cluster::new(&cds);
+
+        let mut sum = 0;
+        for instance in &cluster {
+            sum += instance.ptr();
+        }
+        assert_eq!(sum, cds.iter().map(Instance::ptr).sum::<usize>());
+        assert_eq!(cluster.iter().count(), 2);
+    }
+
+    #[test]
+    fn cluster_len_and_index() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let mut fs = FragSys::from_bytes(::testgen::stored_archive(&files, 1_500_000_000), 0x400);
+        let cds = fs.find_cds();
+        let cluster = Cluster::new(&cds);
+
+        assert_eq!(cluster.len(), cds.len());
+        assert!(!cluster.is_empty());
+        assert_eq!(cluster[1], cds[1]);
+        assert_eq!(cluster[1].header().filename, "b.txt");
+        assert!(Cluster::new(&cds[..0]).is_empty());
+    }
 }
@@ -1,26 +1,76 @@
 //! A range of data models for zip file chunks as well as fragmented file systems, pages and a
 //! model zip file to be fleshed out with data as it's recognised and parsed.
 
-use std::ops::Range;
-use std::io::{BufReader, Error, ErrorKind};
+use std::cmp;
+use std::fmt;
+use std::collections::HashMap;
+use std::ops::{Deref, Range};
+use std::io::{Error, ErrorKind};
 use std::io::prelude::*;
 use std::iter::repeat;
+#[cfg(feature = "std")]
 use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::BufReader;
+#[cfg(feature = "std")]
+use std::path::Path;
 
-use analysis::{Cluster, ClusteringError, Instance, Vectorizable};
-use parser::{parse_eocd, parse_cd};
+use error::ZipDefragError;
+use analysis::{entropy, Cluster, ClusterParams, ClusteringError, Instance, Vectorizable};
+use parser::{parse_eocd_with, parse_eocd64, parse_eocd64_locator, parse_cd_with, parse_cd_signature, parse_lf_with,
+             parse_trailing_dd_with, parse_unix1};
 
 use cogset::Euclid;
+use flate2::Crc;
+use memchr::memchr;
+#[cfg(feature = "std")]
+use memmap2::Mmap;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use nom;
 use nom::IResult::Done;
 
+#[derive(Clone, Debug, PartialEq)]
+/// Why `FragSys::get_pg_for_addr` didn't come back with a page
+pub enum PageLookupError {
+    /// No page left in the pool holds the address, most likely because it's already been taken
+    NotFound,
+    /// Several pages in the pool hold the address, at these indexes into the pool
+    Ambiguous(Vec<usize>),
+}
+
+#[derive(Debug)]
+/// Where the bytes of a dump actually live. Either way it derefs to a plain `[u8]`, so nothing
+/// looking at the data needs to care which.
+pub enum DumpData {
+    /// Read into memory
+    Owned(Vec<u8>),
+    /// Memory mapped from a file, for dumps too big to read in
+    #[cfg(feature = "std")]
+    Mapped(Mmap),
+}
+
+impl Deref for DumpData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            DumpData::Owned(ref bytes) => bytes,
+            #[cfg(feature = "std")]
+            DumpData::Mapped(ref map) => map,
+        }
+    }
+}
+
 #[derive(Debug)]
 /// A Fragmented, paged File System model
 pub struct FragSys {
     /// Raw byte stream we're recovering data from.
-    pub data: Vec<u8>,
+    pub data: DumpData,
     /// Page size
     page_sz: usize,
+    /// Offset into `data` at which the first page starts
+    start_offset: usize,
     /// Stack of pages to sift through
     ///
     /// Implemented to start with as a Vec but is used more like a book or a hashmap with missing
@@ -28,16 +78,51 @@ pub struct FragSys {
     ///
     /// Maybe we ought to use a newtype interface for this but trying to minimise boilerplate a
     /// little.
-    pages: Vec<Page>,
+    pub(crate) pages: Vec<Page>,
+    /// Header signatures to search for and parse with
+    magics: Magics,
+    /// Local file header pointers keyed by `LfKey`, built on the first `find_lf`
+    lf_index: Option<HashMap<LfKey, Vec<usize>>>,
+    /// Local file headers along with their pointers, keyed by filename, built alongside `lf_index`
+    lf_names: Option<HashMap<String, Vec<(usize, LF)>>>,
+    /// How far off an EOCD magic may be and still get a look in, if at all
+    fuzzy_eocd: Option<FuzzyEocd>,
+    /// Whether `match_lf` may settle for a local header agreeing by `LF::fuzzy_score`
+    fuzzy_lf: bool,
+}
+
+/// Key for looking up local file headers: timestamp and the CRC32 as written in the header
+/// (zeroed where the sizes went in a data descriptor instead)
+type LfKey = (u32, u32);
+
+/// The `LfKey` for a local file header
+fn lf_key(lf: &LF) -> LfKey {
+    if lf.gp_flags.contains(DATA_DESCRIPTOR) {
+        (lf.timestamp, 0)
+    } else {
+        (lf.timestamp, lf.dd.crc32)
+    }
+}
+
+/// Bytes an entry takes up in its archive by its CD: local header, compressed data and any data
+/// descriptor. Saturates rather than overflowing on a corrupt Zip64 size.
+fn entry_extent(cd: &CD) -> u64 {
+    let header = LF_FIXED_SZ + cd.fn_len as usize + cd.ef_len as usize;
+    let dd = if cd.gp_flags.contains(DATA_DESCRIPTOR) { DD_SZ } else { 0 };
+    cd.effective_sizes().0.saturating_add((header + dd) as u64)
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 /// A `Page` on a `FragSys`
 pub enum Page {
     /// Page with an associated data range
     Assigned(Range<usize>),
     /// Unassigned page - a placeholder within a collection of assigned pages to be replaced later
     Unassigned,
+    /// Page rebuilt from header data recovered elsewhere rather than found in the dump (e.g. a
+    /// local file header synthesized from its central directory record)
+    Synthesized(Vec<u8>),
 }
 
 impl Page {
@@ -45,16 +130,52 @@ impl Page {
     pub fn contains(&self, addr: usize) -> bool {
         match *self {
             Page::Assigned(ref x) => x.contains(&addr),
-            Page::Unassigned => false,
+            Page::Unassigned | Page::Synthesized(_) => false,
+        }
+    }
+
+    /// Shannon entropy of the page's bytes in bits per byte, or 0.0 if there aren't any.
+    ///
+    /// Compressed data comes out close to 8.0, so this is a decent way of telling which pages
+    /// could fill a gap in an entry's compressed data and which couldn't.
+    pub fn entropy(&self, data: &[u8]) -> f64 {
+        match *self {
+            Page::Assigned(ref range) => {
+                let end = cmp::min(range.end, data.len());
+                entropy(&data[cmp::min(range.start, end)..end])
+            }
+            Page::Synthesized(ref bytes) => entropy(bytes),
+            Page::Unassigned => 0.0,
         }
     }
 }
 
-/// Implements a Paged interface.
+/// Implements a Paged interface, for code which wants to work on anything with a pagebook.
 pub trait Paged {
+    /// The pagebook, in order
+    fn pages(&self) -> &[Page];
+
+    /// Assign pages to this item's pagebook starting at `insertion_pt`, returning any pages from
+    /// the dump they displace
+    fn assign_pages(&mut self, insertion_pt: usize, content: Vec<Page>) -> Vec<Page>;
+
+    /// Assign a page into slot `idx`, returning the page it displaces if that was one from the
+    /// dump
+    fn assign_page(&mut self, idx: usize, page: Page) -> Option<Page>;
+}
+
+impl Paged for ZipFile {
+    fn pages(&self) -> &[Page] {
+        &self.pages
+    }
+
+    fn assign_pages(&mut self, insertion_pt: usize, content: Vec<Page>) -> Vec<Page> {
+        ZipFile::assign_pages(self, insertion_pt, content)
+    }
 
-    /// Assign pages to this item's pagebook
-    fn assign_pages(&self, insertion_pt: usize, content: Vec<Page>);
+    fn assign_page(&mut self, idx: usize, page: Page) -> Option<Page> {
+        ZipFile::assign_page(self, idx, page)
+    }
 }
 
 #[derive(Debug)]
@@ -62,36 +183,297 @@ pub trait Paged {
 pub struct ZipFile {
     /// Offset into the first page at which the file starts
     init_offs: usize,
+    /// Length of any container header wrapping the archive ahead of its first local file header
+    prefix_len: usize,
     /// End of Central Directory Header
     pub eocd: EOCD,
     /// Orderly collection of pages
-    pages: Vec<Page>,
+    pub(crate) pages: Vec<Page>,
+    /// `lf_offset`s of local file headers which were synthesized rather than recovered
+    synthesized: Vec<u32>,
+    /// Central directory entries as of the last reparse of the CD pages
+    entries: Vec<CD>,
+    /// Location of the EOCD in the dump
+    eocd_ptr: usize,
+    /// Index of the page where the central directory starts, as of the last layout computation
+    cd_start_pg: usize,
+    /// Range of page indexes spanned by each of `entries` (local header through compressed data),
+    /// as of the last layout computation
+    entry_pages: Vec<Range<usize>>,
+    /// Header signatures of the `FragSys` the archive was found on
+    magics: Magics,
+    /// Length of the central directory digital signature record, if there is one
+    cd_sig_len: usize,
+    /// The Zip64 EOCD record, for archives whose EOCD only holds sentinels
+    eocd64: Option<EOCD64>,
+    /// Length of the Zip64 EOCD record and locator sat between the central directory (or its
+    /// signature) and the EOCD, if there are any
+    eocd64_len: usize,
+    /// The Zip64 EOCD locator immediately ahead of the EOCD, if there is one
+    locator: Option<EOCD64Locator>,
+    /// Offset into the dump at which the page grid of the `FragSys` starts
+    page_origin: usize,
+    /// Number of pages in the dump, which no archive can span more of
+    dump_pages: usize,
+}
+
+/// One line summing up where the archive is and how much of it we've got, offsets in hex
+impl fmt::Display for ZipFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let coverage = self.coverage();
+        write!(f, "ZIP @ {:#010x}: {}/{} entries, {}/{} pages, starting {:#x} into its first page",
+               self.eocd_ptr, self.entries.len(), self.tot_entries(), coverage.assigned_pages,
+               coverage.total_pages, self.init_offs)
+    }
+}
+
+/// Pairs of indexes of `zips` which would claim some of the same pages of the dump, were each laid
+/// out in one piece ending at its EOCD (see `ZipFile::dump_span`).
+///
+/// That's mostly a false positive EOCD sat inside another archive (a zip stored in a zip, say),
+/// but archives butting up against each other mid-page count too, since only one of them can have
+/// the page they share.
+pub fn detect_overlaps(zips: &[ZipFile], page_sz: usize) -> Vec<(usize, usize)> {
+    let pages = zips.iter()
+        .map(|zip| {
+            let span = zip.dump_span();
+            let start = span.start.saturating_sub(zip.page_origin) / page_sz;
+            let end = (span.end.saturating_sub(zip.page_origin) + page_sz - 1) / page_sz;
+            start..end
+        })
+        .collect::<Vec<_>>();
+    let mut overlaps = vec![];
+    for (i, a) in pages.iter().enumerate() {
+        for (j, b) in pages.iter().enumerate().skip(i + 1) {
+            if a.start < b.end && b.start < a.end {
+                overlaps.push((i, j));
+            }
+        }
+    }
+    overlaps
+}
+
+/// Length of the central directory digital signature record (`PK\x05\x05`) ending right where
+/// the EOCD at `eocd_ptr` starts, or 0 if there isn't one.
+fn cd_signature_len(data: &[u8], eocd_ptr: usize) -> usize {
+    let earliest = eocd_ptr.saturating_sub(CD_SIG_FIXED_SZ + 0xffff);
+    for start in (earliest..eocd_ptr.saturating_sub(CD_SIG_FIXED_SZ - 1)).rev() {
+        if let Done(_, sig) = parse_cd_signature(&data[start..eocd_ptr]) {
+            if start + CD_SIG_FIXED_SZ + sig.len() == eocd_ptr {
+                return eocd_ptr - start;
+            }
+        }
+    }
+    0
+}
+
+/// Find the Zip64 EOCD record ending right where the Zip64 locator ahead of the EOCD at
+/// `eocd_ptr` starts, returning its length along with it.
+fn find_eocd64(data: &[u8], eocd_ptr: usize) -> Option<(usize, EOCD64)> {
+    let end = match eocd_ptr.checked_sub(EOCD64_LOCATOR_SZ) {
+        Some(end) if end <= data.len() => end,
+        _ => return None,
+    };
+    let earliest = end.saturating_sub(EOCD64_FIXED_SZ + 0xffff);
+    for start in (earliest..end.saturating_sub(EOCD64_FIXED_SZ - 1)).rev() {
+        if let Done(_, eocd64) = parse_eocd64(&data[start..end]) {
+            if (start + 12) as u64 + eocd64.rec_sz == end as u64 {
+                return Some((end - start, eocd64));
+            }
+        }
+    }
+    None
+}
+
+/// The bytes to write for a filename or comment: `raw` verbatim if we have it, otherwise `decoded`
+/// encoded the way `gp_flags` says it ought to be, UTF-8 if flagged and CP437 if not.
+fn name_bytes(raw: &Option<Vec<u8>>, decoded: &str, gp_flags: ZipFlags) -> Vec<u8> {
+    match *raw {
+        Some(ref raw) => raw.clone(),
+        None if gp_flags.contains(UTF) => decoded.as_bytes().to_vec(),
+        None => ::cp437::encode(decoded),
+    }
+}
+
+/// Offset of the EOCD within a container `prefix_len` bytes longer than the archive: the end of
+/// the central directory plus the `trailer_len` bytes of records between it and the EOCD.
+///
+/// Worked out in 64 bits as Zip64 offsets are, with `None` for anything that won't fit a `usize`.
+fn eocd_offset(cd_offset: u64, cd_sz: u64, trailer_len: usize, prefix_len: usize)
+    -> Option<usize>
+{
+    let offs = cd_offset
+        .checked_add(cd_sz)?
+        .checked_add(trailer_len as u64)?
+        .checked_add(prefix_len as u64)?;
+    if offs > usize::max_value() as u64 {
+        None
+    } else {
+        Some(offs as usize)
+    }
+}
+
+/// Offset of the EOCD within a container `prefix_len` bytes longer than the archive, going by
+/// where a Zip64 `locator` says the `eocd64_len` bytes of Zip64 records ahead of the EOCD start.
+///
+/// This is preferred over the central directory's offset and size where there's a locator, since
+/// it's one field to get right rather than two.
+fn zip64_eocd_offset(locator: Option<&EOCD64Locator>, eocd64_len: usize, prefix_len: usize)
+    -> Option<usize>
+{
+    locator.and_then(|locator| eocd_offset(locator.eocd64_offset, 0, eocd64_len, prefix_len))
+}
+
+/// Work out the offset of the start of an archive within its first page and the number of pages up
+/// to and including the one holding the EOCD, from where the EOCD sits in the dump
+/// (`eocd_ptr`) and within the archive (`eocd_offs`).
+///
+/// The start offset is whatever lines the EOCD up with where it sits in its page, so an archive
+/// can start anywhere (including in the EOCD's own page) without any of this going negative.
+/// Comes back `None` if the sums overflow, which only a corrupt EOCD could manage.
+fn page_layout(eocd_ptr: usize, eocd_offs: usize, ps: usize) -> Option<(usize, usize)> {
+    // offset of eocd into page located
+    let eocd_pg_offs = eocd_ptr % ps;
+
+    // offset of start of zip file within the first page of the file
+    let init_offs = (eocd_pg_offs + ps - eocd_offs % ps) % ps;
+    debug_assert!(init_offs < ps);
+
+    // offset of eocd from the start of the first page, which had better land it in the same
+    // place within its page as in the dump
+    let eocd_adj = init_offs.checked_add(eocd_offs)?;
+    debug_assert_eq!(eocd_adj % ps, eocd_pg_offs);
+
+    // pages ahead of the one holding the eocd, plus that one
+    let pg_count = eocd_adj / ps + 1;
+    debug_assert!(pg_count > 0);
+    Some((init_offs, pg_count))
 }
 
+/// Size of the fixed portion of a local file header
+pub(crate) const LF_FIXED_SZ: usize = 30;
+
+/// Size of the fixed portion of a central directory header
+const CD_FIXED_SZ: usize = 46;
+
+/// Size of a data descriptor chunk with its signature
+const DD_SZ: usize = 16;
+
+/// Size of the fixed portion of an end of central directory header
+const EOCD_FIXED_SZ: usize = 22;
+
+/// Size of the fixed portion of a Zip64 end of central directory record
+const EOCD64_FIXED_SZ: usize = 56;
+
+/// Size of a Zip64 end of central directory locator
+const EOCD64_LOCATOR_SZ: usize = 20;
+
+/// Size of the fixed portion of a central directory digital signature record
+const CD_SIG_FIXED_SZ: usize = 6;
+
+/// Signature of a central directory digital signature record
+const CD_SIG_MAGIC: &[u8] = b"PK\x05\x05";
+
+/// Fraction of an archive's page span by which its declared sizes may miss before we consider them
+/// inconsistent with it
+const SIZE_BUDGET_TOLERANCE: f64 = 0.05;
+
 impl ZipFile {
     /// Generate a new ZipFile model from data identified within a FragSys with a given pointer
     /// to an EOCD value.
-    pub fn new(fs: &mut FragSys, ptr: usize) -> Result<Self, Error> {
+    pub fn new(fs: &mut FragSys, ptr: usize) -> Result<Self, ZipDefragError> {
+        Self::new_with_prefix(fs, ptr, 0)
+    }
+
+    /// Generate a new ZipFile model for an archive wrapped in a container header `prefix_len`
+    /// bytes long.
+    ///
+    /// Offsets recorded in the archive are relative to the start of the archive itself, whereas
+    /// the pages hold the whole container, so every offset is shifted by `prefix_len` before
+    /// working out which page it lands in. The first page of the model holds the start of the
+    /// container rather than of the archive.
+    pub fn new_with_prefix(fs: &mut FragSys, ptr: usize, prefix_len: usize)
+        -> Result<Self, ZipDefragError>
+    {
         info!("Parsing EOCD ptr: {}", ptr);
-        match parse_eocd(&fs.data[ptr..]) {
+        if ptr < fs.start_offset || ptr > fs.data.len() {
+            warn!("EOCD {} lies outside the page grid", ptr);
+            return Err(ZipDefragError::EocdParse);
+        }
+        match parse_eocd_with(&fs.data[ptr..], fs.eocd_magic_at(ptr)) {
             Done(_, result) => {
                 info!("Parsing Done: {:?}", &result);
                 let ps = fs.page_sz();
 
-                // offset of eocd into page located
-                let eocd_pg_offs = ptr % ps;
+                // Sentinels mean the real values are in the Zip64 record ahead of the EOCD, and
+                // taking them literally makes for a multi-gigabyte archive. Some writers put the
+                // Zip64 records in regardless, which the locator gives away.
+                let locator = ptr.checked_sub(EOCD64_LOCATOR_SZ)
+                    .and_then(|start| match parse_eocd64_locator(&fs.data[start..ptr]) {
+                        Done(_, locator) => Some(locator),
+                        _ => None,
+                    });
+                let (eocd64, eocd64_len) = if result.has_zip64_sentinels() || locator.is_some() {
+                    match find_eocd64(&fs.data, ptr) {
+                        Some((len, eocd64)) => {
+                            info!("Found Zip64 EOCD record ahead of EOCD {}: {:?}", ptr, eocd64);
+                            (Some(eocd64), len + EOCD64_LOCATOR_SZ)
+                        }
+                        None if result.has_zip64_sentinels() => {
+                            warn!("EOCD {} has Zip64 sentinels but no Zip64 record ahead of it",
+                                  ptr);
+                            return Err(ZipDefragError::Incomplete);
+                        }
+                        // The EOCD's own fields will do, but the records still take up room, if
+                        // there's room for them to take up
+                        None => {
+                            warn!("Zip64 locator ahead of EOCD {} but no record to go with it", ptr);
+                            let len = EOCD64_FIXED_SZ + EOCD64_LOCATOR_SZ;
+                            (None, if ptr >= len { len } else { 0 })
+                        }
+                    }
+                } else {
+                    (None, 0)
+                };
 
-                // offset of eocd within original zip file
-                let eocd_offs = (result.cd_sz + result.cd_offset) as usize;
+                // A digital signature record between the CD and EOCD pushes the EOCD along
+                let cd_sig_len = ptr.checked_sub(eocd64_len)
+                    .map_or(0, |end| cd_signature_len(&fs.data, end));
+                if cd_sig_len > 0 {
+                    info!("Found a {} byte CD digital signature ahead of EOCD {}", cd_sig_len, ptr);
+                }
 
-                // offset of start of zip file within the first page of the file
-                let init_offs = ps - ((eocd_offs - eocd_pg_offs) % ps);
+                // offset of eocd within original zip file (and any container around it)
+                let (cd_offset, cd_sz) = match eocd64 {
+                    Some(ref eocd64) => (eocd64.cd_offset, eocd64.cd_sz),
+                    None => (u64::from(result.cd_offset), u64::from(result.cd_sz)),
+                };
+                let eocd_offs = match zip64_eocd_offset(locator.as_ref(), eocd64_len, prefix_len)
+                    .or_else(|| eocd_offset(cd_offset, cd_sz, cd_sig_len + eocd64_len, prefix_len))
+                {
+                    Some(offs) => offs,
+                    None => {
+                        warn!("EOCD {} has offsets which overflow", ptr);
+                        return Err(ZipDefragError::EocdParse);
+                    }
+                };
 
-                let pg_count = {
-                    // Ugly-casting bools to additional page counts
-                    (if eocd_pg_offs > 0 { 1 } else { 0 }) + (if init_offs > 0 { 1 } else { 0 }) +
-                        (eocd_offs - eocd_pg_offs - init_offs) / ps
+                let (init_offs, pg_count) = match page_layout(ptr - fs.start_offset, eocd_offs, ps)
+                {
+                    Some(layout) => layout,
+                    None => {
+                        warn!("EOCD {} has offsets too big to lay out in pages", ptr);
+                        return Err(ZipDefragError::EocdParse);
+                    }
                 };
+                // Every page of the archive is a different page of the dump, so an archive
+                // spanning more pages than the dump has is down to a corrupt EOCD
+                let dump_pages = (fs.data.len() - fs.start_offset) / ps + 1;
+                if pg_count > dump_pages {
+                    warn!("EOCD {} puts its archive at {} pages, more than the dump holds", ptr,
+                          pg_count);
+                    return Err(ZipDefragError::EocdParse);
+                }
 
                 // cute idiom:
                 // https://stackoverflow.com/a/28208182
@@ -100,463 +482,751 @@ impl ZipFile {
                     .collect::<Vec<Page>>();
 
 
-                if let Some(page) = fs.get_pg_for_addr(ptr) {
+                if let Ok(page) = fs.get_pg_for_addr(ptr) {
                     pages[pg_count - 1] = page;
                 }
 
-                Ok(Self {
+                let mut zf = Self {
                     init_offs: init_offs,
+                    prefix_len: prefix_len,
                     eocd: result,
                     pages: pages,
-                })
+                    synthesized: Vec::new(),
+                    entries: Vec::new(),
+                    eocd_ptr: ptr,
+                    cd_start_pg: 0,
+                    entry_pages: Vec::new(),
+                    magics: fs.magics.clone(),
+                    cd_sig_len: cd_sig_len,
+                    eocd64: eocd64,
+                    eocd64_len: eocd64_len,
+                    locator: locator,
+                    page_origin: fs.start_offset,
+                    dump_pages: dump_pages,
+                };
+                zf.recompute_layout(ps);
+                Ok(zf)
             }
-            _ => Err(Error::new(ErrorKind::Other, "Error parsing EOCD")),
+            _ => Err(ZipDefragError::EocdParse),
         }
     }
 
+    /// Where in the dump the archive's EOCD was found
+    pub fn eocd_ptr(&self) -> usize {
+        self.eocd_ptr
+    }
+
+    /// The bytes of the dump the archive (container and all) would take up if it were all in one
+    /// piece, going back from its EOCD by however far into the archive the EOCD says it is.
+    ///
+    /// In a fragmented dump most of that range will be other things' pages, but it's what to go
+    /// on for telling whether two EOCDs are making claims on each other's bytes.
+    pub fn dump_span(&self) -> Range<usize> {
+        let eocd_offs = self.adj_offs(self.cd_offset() as usize + self.cd_sz() as usize +
+                                      self.trailer_len()) - self.init_offs;
+        self.eocd_ptr.saturating_sub(eocd_offs)..
+            self.eocd_ptr + EOCD_FIXED_SZ + self.eocd.cmt_len as usize
+    }
+
+    /// Offset into the first page at which the archive (or its container) starts
+    pub fn init_offset(&self) -> usize {
+        self.init_offs
+    }
+
+    /// Each page slot's index along with the range of the dump assigned to it, if any, for
+    /// poking at a reconstruction from outside. Synthesized pages have no range in the dump, so
+    /// come out as `None` along with the unassigned ones.
+    pub fn layout(&self) -> Vec<(usize, Option<Range<usize>>)> {
+        self.pages
+            .iter()
+            .enumerate()
+            .map(|(i, page)| match *page {
+                Page::Assigned(ref range) => (i, Some(range.clone())),
+                Page::Unassigned | Page::Synthesized(_) => (i, None),
+            })
+            .collect()
+    }
+
     /// Return the page index for a particular Zip file offset
     pub fn get_pg_idx_for_offs(&self, offs: usize, pg_sz: usize) -> usize {
-        let adj_offs = offs + self.init_offs;
-        adj_offs / pg_sz
+        self.adj_offs(offs) / pg_sz
+    }
+
+    /// Convert an offset within the zip file to an offset from the start of its first page
+    fn adj_offs(&self, offs: usize) -> usize {
+        offs + self.prefix_len + self.init_offs
     }
 
     /// Return the index of the page where Central Directory section starts
     pub fn get_cd_start_pg_idx(&self, pg_sz: usize) -> usize {
-        self.get_pg_idx_for_offs(self.eocd.cd_offset as usize, pg_sz)
+        self.get_pg_idx_for_offs(self.cd_offset() as usize, pg_sz)
     }
 
-    /// Assign a collection of pages into a ZipFile starting at `insertion_pt`
-    pub fn assign_pages(&mut self, insertion_pt: usize, content: Vec<Page>) {
-        let end = insertion_pt + content.len();
-        info!("insertion_pt: {}, content len: {}, self.pages.len(): {}", &insertion_pt, content.len(), self.pages.len());
-        self.pages.splice(insertion_pt..end, content);
+    /// Offset of the central directory within the archive, from the Zip64 record if there is one
+    pub fn cd_offset(&self) -> u64 {
+        match self.eocd64 {
+            Some(ref eocd64) => eocd64.cd_offset,
+            None => u64::from(self.eocd.cd_offset),
+        }
     }
 
-    pub fn assign_page(&mut self, idx: usize, page: Page) {
-        if idx <= self.pages.len() {
-            self.pages[idx] = page
+    /// Size of the central directory, from the Zip64 record if there is one
+    pub fn cd_sz(&self) -> u64 {
+        match self.eocd64 {
+            Some(ref eocd64) => eocd64.cd_sz,
+            None => u64::from(self.eocd.cd_sz),
         }
     }
 
-    pub fn render_pages(&self, data: &[u8], pagesz: usize) -> Vec<u8> {
-        let mut rendered = Vec::with_capacity(pagesz * self.pages.len());
-        for page in &self.pages {
-            if let Page::Assigned(bytes) = page {
-                rendered.extend_from_slice(&data[bytes.clone()]);
-            } else {
-                rendered.extend_from_slice(&[0u8;1024]);
-            }
+    /// Number of entries in the archive, from the Zip64 record if there is one
+    pub fn tot_entries(&self) -> u64 {
+        match self.eocd64 {
+            Some(ref eocd64) => eocd64.tot_entries,
+            None => u64::from(self.eocd.tot_entries),
         }
-        rendered
     }
 
+    /// Number of entries on the disk the EOCD is on, from the Zip64 record if there is one
+    pub fn dsk_entries(&self) -> u64 {
+        match self.eocd64 {
+            Some(ref eocd64) => eocd64.dsk_entries,
+            None => u64::from(self.eocd.dsk_entries),
+        }
+    }
 
-    pub fn find_cds(&self, data: &[u8]) -> Vec<CDInstance> {
-        let rendered = self.render_pages(data, 1024);
-
-        let cd_ptrs = find_bytes(&rendered, b"PK\x01\x02");
+    /// Whether the archive is one disk of a spanned (multi-disk) set, going by the EOCD putting
+    /// itself or the central directory on some other disk than the first, or not having all the
+    /// entries on this one.
+    pub fn is_spanned(&self) -> bool {
+        let (dsk_no, dsk_w_cd) = match self.eocd64 {
+            Some(ref eocd64) => (eocd64.dsk_no, eocd64.dsk_w_cd),
+            None => (u32::from(self.eocd.dsk_no), u32::from(self.eocd.dsk_w_cd)),
+        };
+        dsk_no != dsk_w_cd || dsk_no != 0 || self.dsk_entries() != self.tot_entries()
+    }
 
-        let mut results = Vec::with_capacity(cd_ptrs.len());
-        for ptr in cd_ptrs {
-            match CD::from_data(&rendered, ptr) {
-                Ok(cd) => results.push(CDInstance(ptr, cd)),
-                Err(e) => {
-                    error!("Error: {}", e);
-                }
-            }
+    /// Number of central directory entries we can expect to find for the archive in this dump.
+    ///
+    /// That's all of them, unless it's one disk of a spanned set, in which case it's only those
+    /// on this disk: the rest are in some other disk image.
+    pub fn expected_cd_count(&self) -> u64 {
+        if self.dsk_entries() != self.tot_entries() {
+            self.dsk_entries()
+        } else {
+            self.tot_entries()
         }
-        results
     }
-}
 
-#[derive(Debug)]
-/// A skeleton bytestream.
-pub struct Skeleton {
-    /// Inner element
-    inner: Range<usize>,
-}
+    /// The Zip64 EOCD record, if the archive has one
+    pub fn eocd64(&self) -> Option<&EOCD64> {
+        self.eocd64.as_ref()
+    }
 
-#[derive(Debug, PartialEq)]
-/// An End of Central Directory header
-pub struct EOCD {
-    /// Current disk number within zip disk set
-    pub dsk_no: u16,
-    /// Disk number containing the central directory record
-    pub dsk_w_cd: u16,
-    /// Total entries on current disk
-    pub dsk_entries: u16,
-    /// Total file entries in zip file
-    pub tot_entries: u16,
-    /// Size of central directory
-    pub cd_sz: u32,
-    /// Index within file where Central Directory starts
-    pub cd_offset: u32,
-    /// Comment length
-    pub cmt_len: u16,
-    /// Zip File comment field
-    pub zip_cmt: String,
-}
+    /// The Zip64 EOCD locator, if the archive has one
+    pub fn locator(&self) -> Option<&EOCD64Locator> {
+        self.locator.as_ref()
+    }
 
-bitflags! {
-    /// General Purpose PKZip bitflags field
-    pub struct ZipFlags: u16 {
-        /// Zip file is encrypted
-        const ENCRYPTED =         0b_0000_0000_0000_0001;
-        /// Maximum compression
-        const MAXIMUM =           0b_0000_0000_0000_0010; // \
-        /// Fast compression                              //  \
-        const FAST =              0b_0000_0000_0000_0100; //   |--- Only apply under deflation.
-        /// Super fast compression                        //  /
-        const SUPER_FAST =        0b_0000_0000_0000_0110; // /
-        /// Uses separate data descriptor chunk
-        const DATA_DESCRIPTOR =   0b_0000_0000_0000_1000;
-        /// Uses enhanced deflate
-        const ENHANCED_DEFLATE =  0b_0000_0000_0001_0000;
-        /// Uses patch data extension
-        const PATCH_DATA =        0b_0000_0000_0010_0000;
-        /// Uses "strong encryption" extension
-        const STRONG_ENCRYPTION = 0b_0000_0000_0100_0000;
-        /// UTF filenames
-        const UTF =               0b_0000_1000_0000_0000;
-        /// CD Records are scrubbed of data to harden encryption
-        const MASKED_CD_RECORDS = 0b_0010_0000_0000_0000; // Only applies under encryption
+    /// Length of everything between the end of the central directory and the EOCD
+    fn trailer_len(&self) -> usize {
+        self.cd_sig_len + self.eocd64_len
     }
-}
 
-#[derive(Clone, Debug, PartialEq)]
-/// A Central Directory Header
-pub struct CD {
-    /// Version used to produce (can include OS flags and other metadata)
-    pub v_made_by: u16,
-    /// Version needed to decompress
-    pub v_needed: u16,
-    /// General Purpose flags
-    pub gp_flags: ZipFlags,
-    /// Compression Method (generally speaking this will be deflate/0x8 but not necessarily).
-    pub method: u16,
-    /// Timestamp (stored here in unix epoch)
-    pub timestamp: u32,
-    /// Data Descriptor chunk (contains file size and checksum)
-    pub dd: DD,
-    /// Filename Length
-    pub fn_len: u16,
-    /// Extra Field Length
-    pub ef_len: u16,
-    /// File comment length
-    pub fc_len: u16,
-    /// Disk number start (zip files can be spread across multiple "disks").
-    pub dsk_no_s: u16,
-    /// Internal Attributes
-    pub int_attr: u16,
-    /// External attributes
+    /// Byte range of an entry's compressed data within the `rendered` archive
     ///
-    /// Haven't seen a use for these.
-    pub ext_attr: u32,
-    /// Local File Header Offset
+    /// The filename and extra field lengths are read from the local header itself where we have
+    /// it, since they needn't agree with the central directory's: `zipalign` and friends pad the
+    /// local extra field (and only that one) to align stored data. Where the local header is
+    /// missing we have to take the central directory's word for it.
+    pub fn entry_data_range(&self, cd: &CD, rendered: &[u8]) -> Range<usize> {
+        let header = self.adj_offs(cd.lf_offset as usize);
+        let lengths = rendered
+            .get(header..header + LF_FIXED_SZ)
+            .and_then(|lf| if lf.starts_with(&self.magics.lf) {
+                Some((u16::from(lf[26]) | u16::from(lf[27]) << 8,
+                      u16::from(lf[28]) | u16::from(lf[29]) << 8))
+            } else {
+                None
+            });
+        let (fn_len, ef_len) = match lengths {
+            Some((fn_len, ef_len)) => {
+                if ef_len != cd.ef_len {
+                    debug!("Local extra field for {} is {} bytes to the CD's {}", cd.filename,
+                           ef_len, cd.ef_len);
+                }
+                (fn_len, ef_len)
+            }
+            None => (cd.fn_len, cd.ef_len),
+        };
+        let start = header + LF_FIXED_SZ + fn_len as usize + ef_len as usize;
+        let z_sz = self.infer_z_sz(cd, &self.entries).unwrap_or_else(|| cd.effective_sizes().0);
+        start..start + z_sz as usize
+    }
+
+    /// The compressed size of `cd`'s data: as declared if the CD has it, or otherwise the gap
+    /// between the end of its local header and the next of `cds`' local headers (or the central
+    /// directory, for the last entry).
     ///
-    /// A pointer relative to the start of the zip file for the corresponding LF header. This
-    /// helps us locate other pages with the corresponding headers.
-    pub lf_offset: u32,
-    /// Filename
-    pub filename: String,
-    //ef: Sometype,
-    //filecomment: SomeType,
-}
+    /// Streaming writers leave the sizes zero until the data descriptor, so if that's not been
+    /// recovered this is the best we can do. A descriptor is assumed to have its signature, and
+    /// the local header the same fields as the CD's. `None` if there's no making it add up.
+    pub fn infer_z_sz(&self, cd: &CD, cds: &[CD]) -> Option<u64> {
+        let declared = cd.effective_sizes().0;
+        // A sentinel without the Zip64 extra field that ought to go with it is as good as nothing
+        let sentinel = declared == u64::from(u32::max_value());
+        let unavailable = sentinel || (declared == 0 && cd.gp_flags.contains(DATA_DESCRIPTOR));
+        if !unavailable {
+            return Some(declared);
+        }
 
-#[derive(Clone, Debug, PartialEq)]
-/// An instance of a CD Header found and parsed (wrapping the location in the original dataset with
-/// the header object).
-pub struct CDInstance(usize, CD);
+        let next = cds.iter()
+            .map(|other| other.lf_offset)
+            .filter(|&offset| offset > cd.lf_offset)
+            .min()
+            .map(u64::from)
+            .unwrap_or_else(|| self.cd_offset());
+        let header = LF_FIXED_SZ + cd.fn_len as usize + cd.ef_len as usize;
+        let descriptor = match (cd.gp_flags.contains(DATA_DESCRIPTOR), cd.is_zip64()) {
+            (false, _) => 0,
+            (true, false) => DD_SZ,
+            // Zip64 descriptors have 8 byte sizes
+            (true, true) => DD_SZ + 8,
+        };
+        let inferred = next.checked_sub((cd.lf_offset as usize + header + descriptor) as u64)?;
+        debug!("Inferred a compressed size of {} for {}", inferred, cd.filename);
+        Some(inferred)
+    }
 
-impl CD {
-    /// From an existing FragSys with a given pointer to a CD magic spawn a CD model
-    fn new(fs: &mut FragSys, ptr: usize) -> Result<Self, Error> {
-        // opportunistically parse, or alternatively don't panic if fail.
-        match parse_cd(&fs.data[ptr..]) {
-            Done(_, cd) => {
-                debug!("Successfully parsed CD: {:?}",cd);
-                Ok(cd)
+    /// Range of page indexes spanned by an entry, from its local header to the end of its
+    /// compressed data
+    fn entry_slots(&self, cd: &CD, pagesz: usize) -> Range<usize> {
+        let start = cd.lf_offset as usize;
+        let z_sz = self.infer_z_sz(cd, &self.entries).unwrap_or_else(|| cd.effective_sizes().0);
+        let end = start + LF_FIXED_SZ + cd.fn_len as usize + cd.ef_len as usize + z_sz as usize;
+        self.get_pg_idx_for_offs(start, pagesz)..(self.adj_offs(end) - 1) / pagesz + 1
+    }
+
+    /// Re-derive everything we've worked out from offsets against the current page list and
+    /// EOCD: the start offset within the first page, the central directory start page and the
+    /// pages spanned by each cached entry.
+    ///
+    /// Call this after any pass which reassigns pages so the offset helpers don't go on answering
+    /// for a layout that no longer exists.
+    pub fn recompute_layout(&mut self, pagesz: usize) {
+        let eocd_offs = zip64_eocd_offset(self.locator.as_ref(), self.eocd64_len, self.prefix_len)
+            .or_else(|| {
+                eocd_offset(self.cd_offset(), self.cd_sz(), self.trailer_len(), self.prefix_len)
+            });
+        let eocd_offs = match eocd_offs {
+            Some(offs) => offs,
+            None => return,
+        };
+        let (init_offs, pg_count) = match page_layout(self.eocd_ptr - self.page_origin,
+                                                      eocd_offs, pagesz) {
+            Some(layout) => layout,
+            None => {
+                warn!("Layout of {} no longer adds up, leaving it be", self.eocd_ptr);
+                return;
             }
-            nom::IResult::Error(_) => Err(Error::new(
-                ErrorKind::Other,
-                format!("Failed to parse cd at {}", ptr),
-            )),
-            _ => Err(Error::new(ErrorKind::Other, "Incomplete")),
+        };
+        if init_offs != self.init_offs {
+            warn!("Start offset moved from {} to {}", self.init_offs, init_offs);
+            self.init_offs = init_offs;
+        }
+        let mut pg_count = pg_count;
+        if !self.entries.is_empty() {
+            // With only some of the CDs recovered the estimate can only come up short, which
+            // tells us nothing
+            let complete = self.entries.len() as u64 >= self.tot_entries();
+            let estimate = self.estimate_page_span(&self.entries, pagesz);
+            if estimate > pg_count || (complete && estimate != pg_count) {
+                warn!("EOCD puts the archive at {} pages but its CDs put it at {}", pg_count,
+                      estimate);
+                if estimate > self.dump_pages {
+                    warn!("That's more than the {} pages in the dump, so going with the EOCD",
+                          self.dump_pages);
+                } else {
+                    pg_count = cmp::max(pg_count, estimate);
+                }
+            }
+        }
+        if self.pages.len() < pg_count + 1 {
+            // The EOCD ends the archive, so its page and the one it might run into move along to
+            // the end, past the new slots
+            let missing = pg_count + 1 - self.pages.len();
+            let eocd_slot = self.pages.len().saturating_sub(2);
+            let tail = self.pages.split_off(eocd_slot);
+            self.pages.extend(repeat(Page::Unassigned).take(missing));
+            self.pages.extend(tail);
         }
+
+        self.cd_start_pg = self.get_cd_start_pg_idx(pagesz);
+        let entry_pages = self.entries
+            .iter()
+            .map(|cd| self.entry_slots(cd, pagesz))
+            .collect();
+        self.entry_pages = entry_pages;
     }
 
-    fn from_data(data: &[u8], ptr: usize) -> Result<Self, Error> {
-        match parse_cd(&data[ptr..]) {
-            Done(_,cd) => {
-                debug!("Successfully parsed CD: {:?}",cd);
-                Ok(cd)
-            }
-            nom::IResult::Error(_) => Err(Error::new(
-                ErrorKind::Other,
-                format!("Failed to parse cd at {}", ptr),
-            )),
-            _ => Err(Error::new(ErrorKind::Other, "Incomplete")),
+    /// Estimate the number of pages spanned by the archive, up to and including the one the EOCD
+    /// starts in (as `page_layout` counts them), from the furthest extent of the entries in `cds`
+    /// plus the size of the central directory.
+    ///
+    /// This is a second opinion on the count derived from the EOCD's offsets which doesn't depend
+    /// on `cd_offset`, so it can be used to catch a (slightly) corrupt EOCD. When the two disagree
+    /// `recompute_layout` goes with the larger (so long as it fits in the dump), since a page
+    /// list that's too long only costs us some `Unassigned` slots whereas one that's too short
+    /// truncates the archive.
+    ///
+    /// Sizes come from the CDs, which may be corrupt, so the sums saturate rather than overflow.
+    pub fn estimate_page_span(&self, cds: &[CD], pagesz: usize) -> usize {
+        let entries_end = cds.iter()
+            .map(|cd| u64::from(cd.lf_offset).saturating_add(entry_extent(cd)))
+            .max()
+            .unwrap_or(0);
+        let eocd_offs = entries_end
+            .saturating_add(self.cd_sz())
+            .saturating_add((self.trailer_len() + self.prefix_len + self.init_offs) as u64);
+        let pages = eocd_offs / pagesz as u64 + 1;
+        if pages > usize::max_value() as u64 {
+            usize::max_value()
+        } else {
+            pages as usize
         }
     }
 
-    fn to_lf(&self) -> LF {
-        LF{dd: self.dd, ef_len: self.ef_len, fn_len: self.fn_len, method: self.method,
-            v_needed: self.v_needed, timestamp: self.timestamp, filename: self.filename.clone(), gp_flags: self.gp_flags}
+    /// Index of the page where the central directory starts, as of the last `recompute_layout`
+    pub fn cd_start_page(&self) -> usize {
+        self.cd_start_pg
     }
-}
 
-impl Vectorizable for CD {
-    type Output = Euclid<[f64; 5]>;
+    /// Page indexes spanned by each cached entry, as of the last `recompute_layout`
+    pub fn entry_page_ranges(&self) -> &[Range<usize>] {
+        &self.entry_pages
+    }
 
-    fn to_euclidean(&self) -> Self::Output {
-        // Time
-        // method
-        // z_ver
-        // z_ver_needed
-        // utf
-        // datadescriptor
-        Euclid(
-            [f64::from(self.timestamp),          // Time/date
-                f64::from(self.method),             // Method
-                f64::from(self.v_made_by),          // Version used
-                f64::from(self.v_needed),           // Version Needed
-                f64::from(self.gp_flags.bits()),    // Flags cast to number, could be better as
-                                                    // individual GF dimensions
-        ],
-        )
+    /// The ordered page list as reconstructed so far
+    pub fn pages(&self) -> &[Page] {
+        &self.pages
     }
-}
 
-impl Instance for CDInstance {
-    type Item = CD;
+    /// Number of pages the archive spans
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
 
-    fn ptr(&self) -> usize {
-        self.0
+    /// Fraction of the archive's bytes sitting on pages we've recovered from the dump.
+    ///
+    /// Unlike counting pages this doesn't pretend a short final page is as valuable as a full one,
+    /// and synthesized pages don't count, since apart from the header we wrote into them they're
+    /// just as lost as an `Unassigned` one.
+    pub fn byte_completion(&self, pagesz: usize) -> f64 {
+        let total = self.pages.len() * pagesz;
+        if total == 0 {
+            return 0.0;
+        }
+        let recovered: usize = self.pages
+            .iter()
+            .map(|page| match *page {
+                Page::Assigned(ref range) => range.len(),
+                Page::Unassigned | Page::Synthesized(_) => 0,
+            })
+            .sum();
+        recovered as f64 / total as f64
     }
 
-    fn header(&self) -> &Self::Item {
-        &self.1
+    /// How much we believe the reconstruction, from 0.0 to 1.0, as the mean of four signals which
+    /// each back it up in a different way:
+    ///
+    /// * the fraction of the entries the EOCD expects which reparse from the assigned pages
+    /// * the fraction of those whose local header lands on a page we found in the dump
+    /// * whether the EOCD turns up where the central directory says it ends
+    /// * whether the first CD we reparse starts where the EOCD says the central directory does
+    ///
+    /// None of them need any decompression, so it's cheap enough to call on every candidate.
+    pub fn confidence(&self, data: &[u8], pagesz: usize) -> f64 {
+        let rendered = self.render_pages(data, pagesz);
+        let cds = self.find_cds(data, pagesz);
+
+        let expected = self.expected_cd_count();
+        let reparsed = if expected == 0 {
+            1.0
+        } else {
+            (cds.len() as f64 / expected as f64).min(1.0)
+        };
+
+        let located = if cds.is_empty() {
+            0.0
+        } else {
+            cds.iter()
+                .filter(|instance| {
+                    let slot = self.get_pg_idx_for_offs(instance.header().lf_offset as usize,
+                                                        pagesz);
+                    match self.pages.get(slot) {
+                        Some(&Page::Assigned(_)) => true,
+                        _ => false,
+                    }
+                })
+                .count() as f64 / cds.len() as f64
+        };
+
+        let tail = self.adj_offs(self.cd_offset() as usize + self.cd_sz() as usize +
+                                 self.trailer_len());
+        let eocd_at_tail = rendered.get(tail..).map_or(false, |rest| {
+            rest.starts_with(&self.magics.eocd)
+        });
+
+        let cd_start = self.adj_offs(self.cd_offset() as usize);
+        let first_cd_aligned = cds.iter().map(Instance::ptr).min() == Some(cd_start);
+
+        let signals = [reparsed, located, if eocd_at_tail { 1.0 } else { 0.0 },
+                       if first_cd_aligned { 1.0 } else { 0.0 }];
+        debug!("Confidence signals for {}: {:?}", self.eocd_ptr, signals);
+        signals.iter().sum::<f64>() / signals.len() as f64
     }
 
-    fn cluster(data: &[Self], k: usize) -> Result<Vec<Cluster<Self>>, ClusteringError> {
-        ::analysis::cluster(data, k)
+    /// Number of cached entries whose every page, from local header to the end of the compressed
+    /// data, has been recovered from the dump, i.e. how many files we could pull out right now.
+    ///
+    /// Page spans are as of the last `recompute_layout`.
+    pub fn recoverable_entries(&self) -> usize {
+        self.entry_pages
+            .iter()
+            .filter(|slots| {
+                slots.clone().all(|slot| match self.pages.get(slot) {
+                    Some(&Page::Assigned(_)) => true,
+                    _ => false,
+                })
+            })
+            .count()
     }
-}
 
-#[derive(Debug, PartialEq)]
-/// A Local File Header
-pub struct LF {
-    /// Version needed to decompress
+    /// Build a standalone archive holding only the entries we could recover in full (as counted
+    /// by `recoverable_entries`), with a fresh central directory and EOCD describing just those.
     ///
-    /// (In practical terms this is often in practical terms used as a flag for the type of
-    /// compressed object, so as to determine whether the file is a folder, or a file, or some
-    /// other marker within the zip file)
-    pub v_needed: u16,
-    /// General purpose zip flags
-    pub gp_flags: ZipFlags,
-    /// Compression method
-    pub method: u16,
-    /// Timestamp (stored here in unix epoch)
-    pub timestamp: u32,
-    /// Data Descriptor chunk
-    pub dd: DD,
-    /// Filename Length
-    pub fn_len: u16,
-    /// Extra Field Length
-    pub ef_len: u16,
-    /// Filename
-    pub filename: String,
-    // ef_len:  Sometype,
-}
+    /// Standard unzip tools tend to give up on the whole archive at the first corrupt entry, so
+    /// this gets at what was recovered without any manual repair. Entries are written with the
+    /// standard signatures whatever the dump uses.
+    pub fn to_partial_archive(&self, data: &[u8], pagesz: usize) -> Vec<u8> {
+        let rendered = self.render_pages(data, pagesz);
+        let mut archive = Vec::new();
+        let mut directory = Vec::new();
+        let mut count: u16 = 0;
 
-fn u16_to_le(u: u16) -> [u8;2] {
-    return [(u&0xff) as u8, (u>>8) as u8]
-}
+        for (cd, slots) in self.entries.iter().zip(self.entry_pages.iter()) {
+            let complete = slots.clone().all(|slot| match self.pages.get(slot) {
+                Some(&Page::Assigned(_)) => true,
+                _ => false,
+            });
+            let start = self.adj_offs(cd.lf_offset as usize);
+            let end = self.entry_data_range(cd, &rendered).end;
+            if !complete || end > rendered.len() {
+                continue;
+            }
 
-fn u32_to_le(mut u: u32) -> [u8;4] {
-    let mut x = [0u8;4];
-    for i in 0..4 {
-        x[i] = (u & 0xff) as u8;
-        u >>= 8;
-    }
-    x
-}
+            let mut entry = cd.clone();
+            entry.lf_offset = archive.len() as u32;
+            archive.extend_from_slice(Magic::LF.bytes());
+            archive.extend_from_slice(&rendered[start + MAGIC_LEN..end]);
+            if cd.gp_flags.contains(DATA_DESCRIPTOR) {
+                archive.extend_from_slice(Magic::DD.bytes());
+                archive.extend_from_slice(&cd.dd.unparse());
+            }
+            directory.extend_from_slice(&entry.unparse());
+            count += 1;
+        }
 
-fn dostime_to_bytes(ts: u32) -> [u8;4] {
-    use chrono::{NaiveDate, NaiveDateTime, Timelike, Datelike};
-    let mut res = [0u8;4];
-    let datetime = NaiveDateTime::from_timestamp(ts as i64, 0);
-    let time = ((datetime.hour() << 11) | (datetime.minute() <<5) | (datetime.second()/2)) as u16;
-    for (i,v) in u16_to_le(time).iter().enumerate() {
-        res[i] = *v;
+        let eocd = EOCD {
+            dsk_no: 0,
+            dsk_w_cd: 0,
+            dsk_entries: count,
+            tot_entries: count,
+            cd_sz: directory.len() as u32,
+            cd_offset: archive.len() as u32,
+            cmt_len: self.eocd.cmt_len,
+            zip_cmt: self.eocd.zip_cmt.clone(),
+        };
+        debug!("Partial archive holds {} of {} entries", count, self.tot_entries());
+        archive.extend_from_slice(&directory);
+        archive.extend_from_slice(&eocd.unparse());
+        archive
     }
-    let date = ((((datetime.year() - 1980) << 9) as u32) | (datetime.month() << 5) | datetime.day()) as u16;
-    for (i,v) in u16_to_le(date).iter().enumerate() {
-        res[i+2] = *v;
+
+    /// Write out the page order we settled on as CSV, one `logical_page,source_offset` row per
+    /// `pagesz` page of the archive, with `HOLE` for the offset of any page we didn't find.
+    ///
+    /// This is for carvers which would rather do their own extraction and only want the answer to
+    /// the puzzle. Synthesized pages come out as holes too, as there's nowhere in the dump to
+    /// point at for them. Coalesced extents are split back into pages.
+    pub fn export_remap<W: Write>(&self, pagesz: usize, out: &mut W) -> Result<(), Error> {
+        writeln!(out, "logical_page,source_offset")?;
+        let mut logical = 0;
+        for page in &self.pages {
+            match *page {
+                Page::Assigned(ref range) => {
+                    let mut offset = range.start;
+                    while offset < range.end {
+                        writeln!(out, "{},{:#x}", logical, offset)?;
+                        logical += 1;
+                        offset += pagesz;
+                    }
+                }
+                Page::Unassigned | Page::Synthesized(_) => {
+                    writeln!(out, "{},HOLE", logical)?;
+                    logical += 1;
+                }
+            }
+        }
+        Ok(())
     }
-    res
-}
 
-impl LF {
-    pub fn from(cd: &CD) -> Self {
-        cd.to_lf()
+    /// Assign a collection of pages into a ZipFile starting at `insertion_pt`, returning any
+    /// assigned pages they displace so they can go back in the pool.
+    pub fn assign_pages(&mut self, insertion_pt: usize, content: Vec<Page>) -> Vec<Page> {
+        let end = insertion_pt + content.len();
+        info!("insertion_pt: {}, content len: {}, self.pages.len(): {}", &insertion_pt, content.len(), self.pages.len());
+        self.pages
+            .splice(insertion_pt..end, content)
+            .filter(|page| match *page {
+                Page::Assigned(_) => true,
+                _ => false,
+            })
+            .collect()
     }
 
-    pub fn unparse(&self) -> Vec<u8> {
-        use std::mem::transmute;
-        let mut res = Vec::new();
-        res.extend_from_slice(b"PK\x03\x04");
-        res.extend_from_slice(&u16_to_le(self.v_needed));
-        res.extend_from_slice(&u16_to_le(self.gp_flags.bits));
-        res.extend_from_slice(&u16_to_le(self.method));
-        res.extend_from_slice(&dostime_to_bytes(self.timestamp));
-        if (self.gp_flags.contains(DATA_DESCRIPTOR)) {
-            res.extend_from_slice(&[0u8;12]);
+    /// Assign a page into slot `idx`, returning the page it displaces if that was one from the
+    /// dump, so it can go back in the pool.
+    pub fn assign_page(&mut self, idx: usize, page: Page) -> Option<Page> {
+        if idx < self.pages.len() {
+            match ::std::mem::replace(&mut self.pages[idx], page) {
+                displaced @ Page::Assigned(_) => Some(displaced),
+                _ => None,
+            }
         } else {
-            res.extend_from_slice(&self.dd.unparse());
+            None
         }
-        res.extend_from_slice(&u16_to_le(self.fn_len));
-        res.extend_from_slice(&u16_to_le(self.ef_len));
-        res.extend_from_slice(self.filename.as_bytes());
-        //debug!("Unparsed to {:?}", res);
-        res
     }
-}
 
-/// A Data Descriptor Chunk
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub struct DD {
-    /// CRC32 checksum over zipped value.
-    pub crc32: u32,
-    /// Compressed size
-    pub z_sz: u32,
-    /// Uncompressed size
-    pub u_sz: u32,
-}
+    /// How many of the local headers in `cluster` belong to entries cached on this archive.
+    ///
+    /// Used for working out which archive a cluster of LFs came from.
+    pub fn lf_affinity(&self, cluster: &Cluster<LFInstance>) -> usize {
+        cluster
+            .iter()
+            .filter(|instance| {
+                self.entries.iter().any(|cd| cd.lf_mismatches(instance.header()).is_empty())
+            })
+            .count()
+    }
 
-impl DD {
-    pub fn unparse(&self) -> [u8;12] {
-        let mut res = [0u8;12];
-        for (i,v) in u32_to_le(self.crc32).iter().enumerate() {
-            res[i] = *v;
-        }
-        for (i,v) in u32_to_le(self.z_sz).iter().enumerate() {
-            res[i+4] = *v;
-        }
-        for (i,v) in u32_to_le(self.u_sz).iter().enumerate() {
-            res[i+8] = *v;
+    /// Place the pages holding the local headers of `cluster` which belong to entries cached on
+    /// this archive, pulling them from the `FragSys` pool. Returns the number of pages placed.
+    ///
+    /// This is the head start for dumps where two archives are interleaved page by page: the
+    /// cluster tells us which copy of a header shared between them is ours, where matching on
+    /// the header alone would just take the first one found.
+    pub fn seed_from_lfs(&mut self, fs: &mut FragSys, cluster: &Cluster<LFInstance>, pagesz: usize)
+        -> usize
+    {
+        let mut placed = 0;
+        for instance in cluster.iter() {
+            let lf_offset = match self.entries
+                .iter()
+                .find(|cd| cd.lf_mismatches(instance.header()).is_empty()) {
+                Some(cd) => cd.lf_offset as usize,
+                None => continue,
+            };
+            let idx = self.get_pg_idx_for_offs(lf_offset, pagesz);
+            match self.pages.get(idx) {
+                Some(&Page::Unassigned) => {}
+                _ => continue,
+            }
+            if let Ok(page) = fs.get_pg_for_addr(instance.ptr()) {
+                debug!("Seeding page {} with {:?} for {}", idx, page, instance.header().filename);
+                self.pages[idx] = page;
+                placed += 1;
+            }
         }
-        debug!("Unparsed DD: ({},{},{}) to {:?}", self.crc32, self.z_sz, self.u_sz, res);
-        res
+        placed
     }
-}
 
-
-impl FragSys {
-    /// Create a model for a fragmented FS from a `File`
-    pub fn from_file(file: &mut File, page_sz: usize) -> Result<Self, Error> {
-        let len = file.metadata()?.len() as usize;
-        let mut reader = BufReader::new(file);
-        let mut bytes = Vec::with_capacity(len);
-        let len_read = reader.read_to_end(&mut bytes)?;
-        if len_read != len {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "length read doesn't match length available",
-            ));
+    /// Merge runs of assigned pages which are contiguous in the dump into single extents.
+    ///
+    /// Whole runs of an archive often survive intact, so this makes for far fewer slices to copy
+    /// when rendering and a page list a human can actually read. It does mean slots stop being
+    /// one page each, which everything working out page indexes from offsets relies on, so this
+    /// is strictly a last step before rendering or exporting.
+    pub fn coalesce_pages(&mut self) {
+        let mut coalesced: Vec<Page> = Vec::with_capacity(self.pages.len());
+        for page in self.pages.drain(..) {
+            if let Page::Assigned(ref next) = page {
+                if let Some(&mut Page::Assigned(ref mut last)) = coalesced.last_mut() {
+                    if last.end == next.start {
+                        last.end = next.end;
+                        continue;
+                    }
+                }
+            }
+            coalesced.push(page);
         }
+        debug!("Coalesced pages into {} extents", coalesced.len());
+        self.pages = coalesced;
+    }
 
-        // Check dat uglycast
-        let pg_count = len / page_sz + (if len % page_sz > 0 { 1 } else { 0 });
+    /// Length of the archive (and any container around it) according to the EOCD: from its start
+    /// up to the end of its comment.
+    pub fn implied_len(&self) -> usize {
+        self.prefix_len + self.cd_offset() as usize + self.cd_sz() as usize + self.trailer_len() +
+            EOCD_FIXED_SZ +
+            self.eocd.cmt_len as usize
+    }
 
-        // Initialize Big Ole Page Map
-        let pages = (0..pg_count)
-            .map(|pg| {
-                let start = pg * page_sz;
-                let stop = page_sz * (pg + 1);
-                Page::Assigned(start..stop)
-            })
-            .collect();
+    /// Render the pages from the start of the archive (or its container) rather than of its first
+    /// page, then truncate or zero-extend the output to exactly `total_len` bytes (e.g.
+    /// `implied_len()`), so neither whatever came before it in the first page nor trailing fill
+    /// from a partial final page gets in the way of diffing against a reference copy.
+    pub fn render_padded(&self, data: &[u8], pagesz: usize, total_len: usize) -> Vec<u8> {
+        let mut rendered = self.render_pages(data, pagesz);
+        let lead = cmp::min(self.init_offs, rendered.len());
+        rendered.drain(..lead);
+        rendered.resize(total_len, 0);
+        rendered
+    }
 
-        Ok(Self {
-            data: bytes,
-            page_sz: page_sz,
-            pages: pages,
-        })
+    pub fn render_pages(&self, data: &[u8], pagesz: usize) -> Vec<u8> {
+        self.render_pages_with_fill(data, pagesz, 0)
     }
 
-    /// Search FragSys for a given page, and if found, pull the page from the FS.
-    pub fn get_pg_for_addr(&mut self, address: usize) -> Option<Page> {
-        let matches: Vec<usize> = self.pages
-            .iter()
-            .enumerate()
-            .filter_map(|(i, page)|
-                        if page.contains(address) {
-                            Some(i)
-                        } else {
-                            None
-                        })
-            .collect();
-        match matches.len() {
-            count if count == 1 => {
-                let pg = self.pages.swap_remove(matches[0]);
-                Some(pg)
+    /// As `render_pages`, with `Unassigned` pages filled with `fill` rather than zeros, e.g. to
+    /// make the holes stand out in a hex editor. Anything reparsing the output wants zeros.
+    pub fn render_pages_with_fill(&self, data: &[u8], pagesz: usize, fill: u8) -> Vec<u8> {
+        let mut rendered = Vec::with_capacity(pagesz * self.pages.len());
+        for page in &self.pages {
+            match *page {
+                Page::Assigned(ref bytes) => {
+                    // The final page of a dump often runs past its end
+                    let end = cmp::min(bytes.end, data.len());
+                    let start = cmp::min(bytes.start, end);
+                    rendered.extend_from_slice(&data[start..end]);
+                    rendered.extend(repeat(0u8).take(bytes.len() - (end - start)));
+                }
+                Page::Synthesized(ref bytes) => rendered.extend_from_slice(bytes),
+                Page::Unassigned => rendered.extend(repeat(fill).take(pagesz)),
             }
-            _ => None,
         }
+        rendered
     }
 
-    /// Return Page Size for FS
-    pub fn page_sz(&self) -> usize {
-        self.page_sz
+    /// Render the pages straight into `out`, returning how many bytes went into it.
+    pub fn write_to<W: Write>(&self, data: &[u8], pagesz: usize, out: &mut W)
+        -> Result<usize, Error>
+    {
+        self.write_to_with_fill(data, pagesz, 0, out)
     }
 
-    //    /// Update `FragSys` with fresh page size
-    //    pub fn with_page_sz(&mut self, page_sz: usize) {
-    //        self.page_sz = page_sz;
-    //    }
+    /// As `write_to`, with `Unassigned` pages filled with `fill` as in `render_pages_with_fill`.
+    pub fn write_to_with_fill<W: Write>(&self, data: &[u8], pagesz: usize, fill: u8, out: &mut W)
+        -> Result<usize, Error>
+    {
+        let rendered = self.render_pages_with_fill(data, pagesz, fill);
+        out.write_all(&rendered)?;
+        Ok(rendered.len())
+    }
 
-    /// A currently somewhat inefficient function for searching for Zip header magic values
-    fn find_bytes(&self, pattern: &[u8]) -> Vec<usize> {
-        let range = self.data.len() - pattern.len();
-        let mut findings = Vec::new();
-        for i in 0..range {
-            if self.data[i..(i + 4)] == *pattern {
-                findings.push(i);
+    /// Rebuild the local file header for `cd` from its central directory record and write it
+    /// into the page slot(s) where it belongs, for entries whose data survived but whose header
+    /// page was lost.
+    ///
+    /// Only `Unassigned` (or already synthesized) slots are written to, so recovered data always
+    /// wins over a synthesized header. Returns whether the header was placed.
+    pub fn synthesize_missing_lf(&mut self, cd: &CD, pagesz: usize) -> bool {
+        let mut bytes = self.magics.lf.clone();
+        bytes.extend_from_slice(&LF::from(cd).unparse()[MAGIC_LEN..]);
+        let start = self.adj_offs(cd.lf_offset as usize);
+        let first = start / pagesz;
+        let last = (start + bytes.len() - 1) / pagesz;
+
+        if last >= self.pages.len() {
+            return false;
+        }
+        if self.pages[first..last + 1].iter().any(|page| match *page {
+            Page::Assigned(_) => true,
+            _ => false,
+        })
+        {
+            return false;
+        }
+
+        for (i, byte) in bytes.iter().enumerate() {
+            let slot = (start + i) / pagesz;
+            let fresh = match self.pages[slot] {
+                Page::Unassigned => true,
+                _ => false,
+            };
+            if fresh {
+                self.pages[slot] = Page::Synthesized(vec![0u8; pagesz]);
+            }
+            if let Page::Synthesized(ref mut buf) = self.pages[slot] {
+                buf[(start + i) % pagesz] = *byte;
             }
         }
-        findings
+
+        info!("Synthesized local file header for {} at page {}", cd.filename, first);
+        self.synthesized.push(cd.lf_offset);
+        true
     }
 
-    /// Find all identifiable EOCD magics, returning a collection of pointers.
-    fn find_eocds(&self) -> Vec<usize> {
-        self.find_bytes(b"PK\x05\x06")
+    /// `lf_offset`s of the local file headers that were synthesized rather than recovered
+    pub fn synthesized_lfs(&self) -> &[u32] {
+        &self.synthesized
     }
 
-    /// Find and return a collection of ZipFile instances
-    ///
-    /// This is performed by searching for EOCD magic values and then parsing them with nom.
-    pub fn find_zips(&mut self) -> Vec<ZipFile> {
-        let eocd_list = self.find_eocds();
-        let mut zips = Vec::with_capacity(eocd_list.len());
-        for ptr in eocd_list {
-            match ZipFile::new(self, ptr) {
-                Ok(zf) => zips.push(zf),
-                Err(e) => {
-                    error!("Error: {}", e);
+    /// Byte ranges of the rendered archive which are zero fill standing in for `Unassigned`
+    /// pages.
+    fn unassigned_spans(&self, pagesz: usize) -> Vec<Range<usize>> {
+        let mut spans = vec![];
+        let mut offs = 0;
+        for page in &self.pages {
+            let len = match *page {
+                Page::Assigned(ref bytes) => bytes.len(),
+                Page::Synthesized(ref bytes) => bytes.len(),
+                Page::Unassigned => {
+                    spans.push(offs..offs + pagesz);
+                    pagesz
                 }
             };
+            offs += len;
         }
-        zips
+        spans
     }
 
-    /// Return a collection of instances of CD Headers recognised and parsed with nom.
-    pub fn find_cds(&mut self) -> Vec<CDInstance> {
-        let cd_ptrs = find_bytes(&self.data, b"PK\x01\x02");
+    pub fn find_cds(&self, data: &[u8], pagesz: usize) -> Vec<CDInstance> {
+        let rendered = self.render_pages(data, pagesz);
+        let gaps = self.unassigned_spans(pagesz);
+
+        let cd_ptrs = find_bytes(&rendered, &self.magics.cd);
+
+        // Signature data is as good as random, so can have anything in it, CD magics included
+        let signatures = find_bytes(&rendered, CD_SIG_MAGIC)
+            .into_iter()
+            .filter_map(|ptr| match parse_cd_signature(&rendered[ptr..]) {
+                Done(_, sig) => Some(ptr..ptr + CD_SIG_FIXED_SZ + sig.len()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
         let mut results = Vec::with_capacity(cd_ptrs.len());
         for ptr in cd_ptrs {
-            match CD::new(self, ptr) {
+            if let Some(sig) = signatures.iter().find(|sig| sig.contains(&ptr)) {
+                debug!("Skipping CD magic at {} in the digital signature at {}", ptr, sig.start);
+                continue;
+            }
+            match CD::from_data(&rendered, ptr, &self.magics.cd, &gaps) {
                 Ok(cd) => results.push(CDInstance(ptr, cd)),
+                // Not wrong so much as waiting on a page we've not placed yet
+                Err(ZipDefragError::Incomplete) => {
+                    info!("CD at {} runs into a missing page", ptr);
+                }
                 Err(e) => {
                     error!("Error: {}", e);
                 }
@@ -565,30 +1235,3104 @@ impl FragSys {
         results
     }
 
-    /// Return a collection of pointers to instances of Local File Header magics.
-    pub fn find_lfs(&self) -> Vec<usize> {
-        self.find_bytes(b"PK\x03\x04")
-    }
+    /// Walk the central directory from where the EOCD says it starts, parsing each record in turn
+    /// and stepping over its filename, extra field and comment to get to the next, until we run
+    /// out of central directory or into something that won't parse.
+    ///
+    /// Unlike `find_cds`, which takes every CD magic at its word, this can't be fooled by a magic
+    /// turning up inside a filename or extra field, and gives the entries in directory order. On
+    /// the other hand it stops dead at the first page we've not placed.
+    pub fn walk_central_directory(&self, data: &[u8], pagesz: usize) -> Vec<CDInstance> {
+        let rendered = self.render_pages(data, pagesz);
+        let gaps = self.unassigned_spans(pagesz);
+        let end = cmp::min(self.adj_offs(self.cd_offset() as usize + self.cd_sz() as usize),
+                           rendered.len());
 
-    pub fn find_lf(&self, lf: &LF, lfp: &[usize]) -> Option<usize> {
-        let bytes = lf.unparse();
-        for i in lfp {
-            if &self.data[*i..(*i+bytes.len())] == bytes.as_slice() {
-                return Some(*i)
+        let mut results = vec![];
+        let mut ptr = self.adj_offs(self.cd_offset() as usize);
+        while ptr + CD_FIXED_SZ <= end {
+            match CD::from_data(&rendered, ptr, &self.magics.cd, &gaps) {
+                Ok(cd) => {
+                    let next = ptr + CD_FIXED_SZ + cd.fn_len as usize + cd.ef_len as usize +
+                        cd.fc_len as usize;
+                    results.push(CDInstance(ptr, cd));
+                    ptr = next;
+                }
+                Err(e) => {
+                    info!("Central directory walk stopped at {}: {}", ptr, e);
+                    break;
+                }
             }
         }
-        None
+        results
     }
-}
 
-/// A currently somewhat inefficient function for searching for Zip header magic values
-fn find_bytes(data: &[u8], pattern: &[u8]) -> Vec<usize> {
-    let mut cursor = 0;
-    let mut findings = Vec::new();
-    while let Some(ptr) = data[cursor..].windows(pattern.len()).position(|window| window == pattern) {
-        findings.push(ptr + cursor);
-        //debug!("Cursor moving to: {}", cursor + ptr + pattern.len());
-        cursor = cursor + ptr + pattern.len();
+    /// Reparse the central directory from the currently assigned pages, caching the recovered
+    /// entries on the `ZipFile`.
+    pub fn refresh_entries(&mut self, data: &[u8], pagesz: usize) -> Vec<CDInstance> {
+        let instances = self.find_cds(data, pagesz);
+        self.entries = instances.iter().map(|instance| instance.header().clone()).collect();
+        instances
+    }
+
+    /// The archive's central directory entries as parsed from its currently assigned pages, in
+    /// `lf_offset` order (i.e. the order their data's in, which needn't be the order the central
+    /// directory lists them in).
+    ///
+    /// Unlike `refresh_entries` this leaves the cache alone, so it's safe to call on a finished
+    /// reconstruction just to see what's in it.
+    pub fn entries(&self, data: &[u8], pagesz: usize) -> Vec<CD> {
+        let mut entries = self.find_cds(data, pagesz)
+            .into_iter()
+            .map(|CDInstance(_, cd)| cd)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|cd| cd.lf_offset);
+        entries
+    }
+
+    /// Number of central directory entries parseable from the archive's assigned pages
+    pub fn entry_count(&self, data: &[u8], pagesz: usize) -> usize {
+        self.find_cds(data, pagesz).len()
+    }
+
+    /// Central directory entries cached by the last `refresh_entries`
+    pub fn cached_entries(&self) -> &[CD] {
+        &self.entries
+    }
+
+    /// Parse the data descriptor trailing an entry's compressed data in the `rendered` archive,
+    /// reading Zip64 sizes if the entry is a Zip64 one.
+    pub fn trailing_dd(&self, cd: &CD, rendered: &[u8]) -> Option<DD64> {
+        let end = self.entry_data_range(cd, rendered).end;
+        if end > rendered.len() {
+            return None;
+        }
+        match parse_trailing_dd_with(&rendered[end..], &self.magics.dd, cd.is_zip64()) {
+            Done(_, dd) => Some(dd),
+            _ => None,
+        }
+    }
+
+    /// Scan the archive's assigned pages for local file headers which none of the cached central
+    /// directory entries point at.
+    ///
+    /// When only part of the central directory survives, the entries whose CDs were lost are
+    /// still sat there in the archive with their local headers, which carry enough to extract
+    /// them directly.
+    pub fn discover_orphan_entries(&self, data: &[u8], pagesz: usize) -> Vec<LF> {
+        let rendered = self.render_pages(data, pagesz);
+        let base = self.adj_offs(0);
+        let mut orphans = vec![];
+        for ptr in find_bytes(&rendered, &self.magics.lf) {
+            if ptr < base {
+                continue;
+            }
+            let offs = (ptr - base) as u32;
+            if self.entries.iter().any(|cd| cd.lf_offset == offs) {
+                continue;
+            }
+            if let Done(_, lf) = parse_lf_with(&rendered[ptr..], &self.magics.lf) {
+                debug!("Found orphaned local header for {} at offset {}", lf.filename, offs);
+                orphans.push(lf);
+            }
+        }
+        orphans
+    }
+
+    /// Cheap sanity check of whether the sizes declared by the central directory fit in the page
+    /// span computed from the EOCD.
+    ///
+    /// Sums the compressed size and local header overhead of each cached entry (plus its data
+    /// descriptor where flagged) with the size of the central directory and EOCD. If that
+    /// overshoots the page span, or if the whole directory is known and it falls well short, the
+    /// EOCD or CDs are corrupt and the archive isn't worth the effort of reconstructing.
+    pub fn size_budget_consistent(&self, pagesz: usize) -> bool {
+        let entries = self.entries
+            .iter()
+            .fold(0u64, |total, cd| total.saturating_add(entry_extent(cd)));
+        let overhead = self.prefix_len + self.trailer_len() + EOCD_FIXED_SZ +
+            self.eocd.cmt_len as usize;
+        let required = entries
+            .saturating_add(self.cd_sz())
+            .saturating_add(overhead as u64) as f64;
+
+        // Up to a page either end of the span is just padding around the archive
+        let budget = (self.pages.len() * pagesz) as f64;
+        let slack = budget * SIZE_BUDGET_TOLERANCE + (2 * pagesz) as f64;
+        let complete = self.entries.len() as u64 >= self.tot_entries();
+
+        if required > budget + slack {
+            debug!("Declared sizes ({}) overshoot page budget ({})", required, budget);
+            false
+        } else if complete && required + slack < budget {
+            debug!("Declared sizes ({}) fall well short of page budget ({})", required, budget);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A skeleton bytestream.
+pub struct Skeleton {
+    /// Inner element
+    inner: Range<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A Zip64 End of Central Directory record, holding the real values for an archive too big for
+/// the ordinary EOCD's fields
+pub struct EOCD64 {
+    /// Size of the rest of the record, i.e. less its magic and this field
+    pub rec_sz: u64,
+    /// Version made by
+    pub v_made_by: u16,
+    /// Version needed to extract
+    pub v_needed: u16,
+    /// Current disk number within zip disk set
+    pub dsk_no: u32,
+    /// Disk number containing the central directory record
+    pub dsk_w_cd: u32,
+    /// Total entries on current disk
+    pub dsk_entries: u64,
+    /// Total file entries in zip file
+    pub tot_entries: u64,
+    /// Size of central directory
+    pub cd_sz: u64,
+    /// Index within file where Central Directory starts
+    pub cd_offset: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A Zip64 End of Central Directory locator, which sits between the Zip64 record and the EOCD
+/// saying where the former is
+pub struct EOCD64Locator {
+    /// Number of the disk holding the Zip64 EOCD record
+    pub disk_with_eocd64: u32,
+    /// Offset of the Zip64 EOCD record within the archive
+    pub eocd64_offset: u64,
+    /// Total number of disks in the set
+    pub total_disks: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+/// An End of Central Directory header
+pub struct EOCD {
+    /// Current disk number within zip disk set
+    pub dsk_no: u16,
+    /// Disk number containing the central directory record
+    pub dsk_w_cd: u16,
+    /// Total entries on current disk
+    pub dsk_entries: u16,
+    /// Total file entries in zip file
+    pub tot_entries: u16,
+    /// Size of central directory
+    pub cd_sz: u32,
+    /// Index within file where Central Directory starts
+    pub cd_offset: u32,
+    /// Comment length
+    pub cmt_len: u16,
+    /// Zip File comment field
+    pub zip_cmt: String,
+}
+
+bitflags! {
+    /// General Purpose PKZip bitflags field
+    pub struct ZipFlags: u16 {
+        /// Zip file is encrypted
+        const ENCRYPTED =         0b_0000_0000_0000_0001;
+        /// Maximum compression
+        const MAXIMUM =           0b_0000_0000_0000_0010; // \
+        /// Fast compression                              //  \
+        const FAST =              0b_0000_0000_0000_0100; //   |--- Only apply under deflation.
+        /// Super fast compression                        //  /
+        const SUPER_FAST =        0b_0000_0000_0000_0110; // /
+        /// Uses separate data descriptor chunk
+        const DATA_DESCRIPTOR =   0b_0000_0000_0000_1000;
+        /// Uses enhanced deflate
+        const ENHANCED_DEFLATE =  0b_0000_0000_0001_0000;
+        /// Uses patch data extension
+        const PATCH_DATA =        0b_0000_0000_0010_0000;
+        /// Uses "strong encryption" extension
+        const STRONG_ENCRYPTION = 0b_0000_0000_0100_0000;
+        /// UTF filenames
+        const UTF =               0b_0000_1000_0000_0000;
+        /// CD Records are scrubbed of data to harden encryption
+        const MASKED_CD_RECORDS = 0b_0010_0000_0000_0000; // Only applies under encryption
+    }
+}
+
+/// Serialising `ZipFlags` as the raw field, since bitflags won't do it for us. Bits it doesn't
+/// know the meaning of are dropped on the way back in.
+#[cfg(feature = "serialize")]
+mod zip_flags_serde {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::ZipFlags;
+
+    pub fn serialize<S: Serializer>(flags: &ZipFlags, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(flags.bits())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ZipFlags, D::Error> {
+        Ok(ZipFlags::from_bits_truncate(u16::deserialize(deserializer)?))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Compression methods we know by name
+pub enum CompressionMethod {
+    /// No compression (0)
+    Stored,
+    /// Deflate (8)
+    Deflated,
+    /// bzip2 (12)
+    Bzip2,
+    /// LZMA (14)
+    Lzma,
+    /// Zstandard (93)
+    Zstd,
+    /// PPMd version I, rev 1 (98)
+    Ppmd,
+    /// Anything else, with the raw method number
+    Other(u16),
+}
+
+impl From<u16> for CompressionMethod {
+    fn from(method: u16) -> Self {
+        match method {
+            0 => CompressionMethod::Stored,
+            8 => CompressionMethod::Deflated,
+            12 => CompressionMethod::Bzip2,
+            14 => CompressionMethod::Lzma,
+            93 => CompressionMethod::Zstd,
+            98 => CompressionMethod::Ppmd,
+            other => CompressionMethod::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for CompressionMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompressionMethod::Stored => write!(f, "stored"),
+            CompressionMethod::Deflated => write!(f, "deflate"),
+            CompressionMethod::Bzip2 => write!(f, "bzip2"),
+            CompressionMethod::Lzma => write!(f, "LZMA"),
+            CompressionMethod::Zstd => write!(f, "zstd"),
+            CompressionMethod::Ppmd => write!(f, "PPMd"),
+            CompressionMethod::Other(method) => write!(f, "unknown ({})", method),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+/// A Central Directory Header
+pub struct CD {
+    /// Version used to produce (can include OS flags and other metadata)
+    pub v_made_by: u16,
+    /// Version needed to decompress
+    pub v_needed: u16,
+    /// General Purpose flags
+    #[cfg_attr(feature = "serialize", serde(with = "zip_flags_serde"))]
+    pub gp_flags: ZipFlags,
+    /// Compression Method (generally speaking this will be deflate/0x8 but not necessarily).
+    pub method: u16,
+    /// Timestamp (stored here in unix epoch)
+    pub timestamp: u32,
+    /// Whether the DOS timestamp was a real date and time. If not, `timestamp` is 0.
+    pub timestamp_valid: bool,
+    /// The DOS date and time exactly as we found them, which `unparse` writes back verbatim rather
+    /// than trusting to the round trip through `timestamp`. Clear it if you change `timestamp`.
+    pub raw_datetime: Option<[u8; 4]>,
+    /// Data Descriptor chunk (contains file size and checksum)
+    pub dd: DD,
+    /// Filename Length
+    pub fn_len: u16,
+    /// Extra Field Length
+    pub ef_len: u16,
+    /// File comment length
+    pub fc_len: u16,
+    /// Disk number start (zip files can be spread across multiple "disks").
+    pub dsk_no_s: u16,
+    /// Internal Attributes
+    pub int_attr: u16,
+    /// External attributes
+    ///
+    /// Haven't seen a use for these.
+    pub ext_attr: u32,
+    /// Local File Header Offset
+    ///
+    /// A pointer relative to the start of the zip file for the corresponding LF header. This
+    /// helps us locate other pages with the corresponding headers.
+    pub lf_offset: u32,
+    /// Filename
+    pub filename: String,
+    /// The filename bytes exactly as we found them, which `unparse` writes back verbatim: a name
+    /// flagged as UTF-8 which isn't gets decoded lossily, and wouldn't survive the round trip.
+    /// Clear it if you change `filename`.
+    pub raw_filename: Option<Vec<u8>>,
+    /// Extra field records
+    pub extra: Vec<ExtraField>,
+    /// File comment, decoded the same way as the filename
+    pub file_comment: String,
+    /// The file comment bytes exactly as we found them, as with `raw_filename`
+    pub raw_comment: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A field on which a `CD` and the `LF` matched up with it disagree
+pub enum LfMismatch {
+    /// File name
+    Filename,
+    /// Modification timestamp
+    Timestamp,
+    /// Compression method
+    Method,
+    /// General purpose flags
+    Flags,
+    /// CRC32 of the uncompressed data
+    Crc32,
+    /// Compressed size
+    CompressedSize,
+    /// Uncompressed size
+    UncompressedSize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// An instance of a CD Header found and parsed (wrapping the location in the original dataset with
+/// the header object).
+pub struct CDInstance(usize, CD);
+
+#[derive(Clone, Debug, PartialEq)]
+/// An instance of a LF Header found and parsed, wrapping its location in the dump as with
+/// `CDInstance`.
+pub struct LFInstance(usize, LF);
+
+impl EOCD {
+    /// Whether any of the counts or offsets are maxed out, meaning the real values are in the
+    /// Zip64 record
+    pub fn has_zip64_sentinels(&self) -> bool {
+        self.dsk_entries == u16::max_value() || self.tot_entries == u16::max_value() ||
+            self.cd_sz == u32::max_value() || self.cd_offset == u32::max_value()
+    }
+
+    pub fn unparse(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(Magic::EOCD.bytes());
+        res.extend_from_slice(&u16_to_le(self.dsk_no));
+        res.extend_from_slice(&u16_to_le(self.dsk_w_cd));
+        res.extend_from_slice(&u16_to_le(self.dsk_entries));
+        res.extend_from_slice(&u16_to_le(self.tot_entries));
+        res.extend_from_slice(&u32_to_le(self.cd_sz));
+        res.extend_from_slice(&u32_to_le(self.cd_offset));
+        res.extend_from_slice(&u16_to_le(self.zip_cmt.len() as u16));
+        res.extend_from_slice(self.zip_cmt.as_bytes());
+        res
+    }
+}
+
+impl CD {
+    /// Whether the entry's data is encrypted, traditionally or with the strong encryption
+    /// extension. There's no decompressing or CRC checking it either way, so the raw bytes are
+    /// all anyone's getting.
+    pub fn is_encrypted(&self) -> bool {
+        self.gp_flags.intersects(ENCRYPTED | STRONG_ENCRYPTION)
+    }
+
+    /// Whether the writer scrubbed the local headers (and so this record may not be all it
+    /// seems either) to hide what's in the archive, as strong encryption can.
+    pub fn has_masked_records(&self) -> bool {
+        self.gp_flags.contains(MASKED_CD_RECORDS)
+    }
+
+    /// Serialise the header back to bytes.
+    pub fn unparse(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(Magic::CD.bytes());
+        res.extend_from_slice(&u16_to_le(self.v_made_by));
+        res.extend_from_slice(&u16_to_le(self.v_needed));
+        res.extend_from_slice(&u16_to_le(self.gp_flags.bits));
+        res.extend_from_slice(&u16_to_le(self.method));
+        let datetime = self.raw_datetime.unwrap_or_else(|| dostime_to_bytes(self.timestamp));
+        res.extend_from_slice(&datetime);
+        res.extend_from_slice(&self.dd.unparse());
+        let filename = name_bytes(&self.raw_filename, &self.filename, self.gp_flags);
+        res.extend_from_slice(&u16_to_le(filename.len() as u16));
+        let extra = self.extra.iter().fold(Vec::new(), |mut extra, field| {
+            extra.extend_from_slice(&u16_to_le(field.id));
+            extra.extend_from_slice(&u16_to_le(field.data.len() as u16));
+            extra.extend_from_slice(&field.data);
+            extra
+        });
+        let comment = name_bytes(&self.raw_comment, &self.file_comment, self.gp_flags);
+        res.extend_from_slice(&u16_to_le(extra.len() as u16));
+        res.extend_from_slice(&u16_to_le(comment.len() as u16));
+        res.extend_from_slice(&u16_to_le(self.dsk_no_s));
+        res.extend_from_slice(&u16_to_le(self.int_attr));
+        res.extend_from_slice(&u32_to_le(self.ext_attr));
+        res.extend_from_slice(&u32_to_le(self.lf_offset));
+        res.extend_from_slice(&filename);
+        res.extend_from_slice(&extra);
+        res.extend_from_slice(&comment);
+        res
+    }
+
+    /// From an existing FragSys with a given pointer to a CD magic spawn a CD model
+    fn new(fs: &mut FragSys, ptr: usize) -> Result<Self, ZipDefragError> {
+        // opportunistically parse, or alternatively don't panic if fail.
+        Self::from_data(&fs.data, ptr, &fs.magics.cd, &[])
+    }
+
+    /// Parse the CD at `ptr` in `data`, where `gaps` are the ranges of `data` which are only
+    /// zero fill for pages we haven't got.
+    ///
+    /// A header running off the end of `data` or into one of the `gaps` fails as `Incomplete`,
+    /// as opposed to `CdParse` for one that's just not a CD: the fields read out of the zero fill
+    /// would parse happily enough, they'd just be nonsense. Either way the entry is one more page
+    /// away from being recovered rather than lost.
+    fn from_data(data: &[u8], ptr: usize, magic: &[u8], gaps: &[Range<usize>])
+        -> Result<Self, ZipDefragError>
+    {
+        let input = match data.get(ptr..) {
+            Some(input) => input,
+            None => return Err(ZipDefragError::CdParse { offset: ptr }),
+        };
+        match parse_cd_with(input, magic) {
+            Done(rest, cd) => {
+                let end = ptr + input.len() - rest.len();
+                if let Some(gap) = gaps.iter().find(|gap| gap.start < end && ptr < gap.end) {
+                    debug!("CD at {} runs into the missing page at {}", ptr, gap.start);
+                    return Err(ZipDefragError::Incomplete);
+                }
+                debug!("Successfully parsed CD: {:?}",cd);
+                Ok(cd)
+            }
+            nom::IResult::Error(_) => Err(ZipDefragError::CdParse { offset: ptr }),
+            nom::IResult::Incomplete(_) => Err(ZipDefragError::Incomplete),
+        }
+    }
+
+    /// The entry's compression method
+    pub fn compression_method(&self) -> CompressionMethod {
+        CompressionMethod::from(self.method)
+    }
+
+    /// The fields on which this entry and a supposedly matching local file header disagree.
+    ///
+    /// CRC and sizes are only compared when the entry doesn't defer them to a data descriptor,
+    /// since the local header holds zeroes for them otherwise.
+    pub fn lf_mismatches(&self, lf: &LF) -> Vec<LfMismatch> {
+        let mut mismatches = vec![];
+        if self.filename != lf.filename {
+            mismatches.push(LfMismatch::Filename);
+        }
+        if self.timestamp != lf.timestamp {
+            mismatches.push(LfMismatch::Timestamp);
+        }
+        if self.method != lf.method {
+            mismatches.push(LfMismatch::Method);
+        }
+        if self.gp_flags != lf.gp_flags {
+            mismatches.push(LfMismatch::Flags);
+        }
+        if !self.gp_flags.contains(DATA_DESCRIPTOR) {
+            if self.dd.crc32 != lf.dd.crc32 {
+                mismatches.push(LfMismatch::Crc32);
+            }
+            if self.dd.z_sz != lf.dd.z_sz {
+                mismatches.push(LfMismatch::CompressedSize);
+            }
+            if self.dd.u_sz != lf.dd.u_sz {
+                mismatches.push(LfMismatch::UncompressedSize);
+            }
+        }
+        mismatches
+    }
+
+    /// Whether a local file header agrees with this entry on everything they ought to agree on,
+    /// i.e. whether it can really be this entry's local header.
+    pub fn consistent_with_lf(&self, lf: &LF) -> bool {
+        self.lf_mismatches(lf).is_empty()
+    }
+
+    /// Whether the entry uses Zip64 extensions, going by its version needed to extract or the
+    /// presence of a Zip64 extra field
+    pub fn is_zip64(&self) -> bool {
+        self.v_needed & 0xff >= ZIP64_VERSION || self.extra.iter().any(|field| field.id == EF_ZIP64)
+    }
+
+    /// The entry's compressed size, uncompressed size and local header offset, with any of them
+    /// written as `0xFFFFFFFF` sentinels replaced by the real 64 bit values from the Zip64 extra
+    /// field.
+    ///
+    /// The extra field only holds the values which are sentinels, in that order. A sentinel size
+    /// with no value to replace it is left as it is, but a sentinel offset comes back `None`, as
+    /// there's nowhere sensible to go looking for the local header.
+    pub fn effective_sizes(&self) -> (u64, u64, Option<u64>) {
+        let sentinel = u32::max_value();
+        let mut values = self.extra
+            .iter()
+            .find(|field| field.id == EF_ZIP64)
+            .map(|field| {
+                field.data
+                    .chunks(8)
+                    .filter(|chunk| chunk.len() == 8)
+                    .map(|chunk| chunk.iter().rev().fold(0u64, |acc, &b| acc << 8 | u64::from(b)))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+            .into_iter();
+        let mut overlay = |value: u32| if value == sentinel {
+            values.next()
+        } else {
+            Some(u64::from(value))
+        };
+
+        let u_sz = overlay(self.dd.u_sz).unwrap_or_else(|| u64::from(sentinel));
+        let z_sz = overlay(self.dd.z_sz).unwrap_or_else(|| u64::from(sentinel));
+        let lf_offset = overlay(self.lf_offset);
+        (z_sz, u_sz, lf_offset)
+    }
+
+    /// Whether the entry's local header lives on the disk of a spanned set holding the central
+    /// directory, which is the only one we can expect to have in the dump.
+    ///
+    /// Entries on other disks can never be located, so there's no sense searching for them.
+    pub fn is_on_this_disk(&self, eocd: &EOCD) -> bool {
+        self.dsk_no_s == eocd.dsk_w_cd
+    }
+
+    fn to_lf(&self) -> LF {
+        LF{dd: self.dd, ef_len: self.ef_len, fn_len: self.fn_len, method: self.method,
+            v_needed: self.v_needed, timestamp: self.timestamp, filename: self.filename.clone(), gp_flags: self.gp_flags,
+            raw_datetime: self.raw_datetime, raw_filename: self.raw_filename.clone(),
+            extra: Vec::new()}
+    }
+
+    /// The entry's Unix1 extra field, if it has one
+    pub fn unix1(&self) -> Option<Unix1> {
+        find_unix1(&self.extra)
+    }
+
+    /// Unix epoch modification time from the extra fields, if there is one.
+    ///
+    /// This is a higher resolution alternative to `timestamp` for clustering and for restoring
+    /// mtimes on extraction.
+    pub fn unix_mtime(&self) -> Option<u32> {
+        self.unix1().map(|unix1| unix1.mtime)
+    }
+}
+
+impl Vectorizable for CD {
+    type Output = Euclid<[f64; 5]>;
+
+    fn to_euclidean(&self) -> Self::Output {
+        // Time
+        // method
+        // z_ver
+        // z_ver_needed
+        // utf
+        // datadescriptor
+        //
+        // Not the lf_offset: it's relative to the start of its archive, so every archive's
+        // entries start out near zero and it says nothing about which archive is whose
+        Euclid(
+            [f64::from(self.timestamp),          // Time/date
+                f64::from(self.method),             // Method
+                f64::from(self.v_made_by),          // Version used
+                f64::from(self.v_needed),           // Version Needed
+                f64::from(self.gp_flags.bits()),    // Flags cast to number, could be better as
+                                                    // individual GF dimensions
+        ],
+        )
+    }
+}
+
+impl CDInstance {
+    /// Where the header is in the dump
+    pub fn offset(&self) -> usize {
+        self.0
+    }
+
+    /// The header itself
+    pub fn cd(&self) -> &CD {
+        &self.1
+    }
+}
+
+/// One line, with the offsets in hex for looking up in a hex editor
+impl fmt::Display for CDInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CD @ {:#010x}: {} (method {}, lf@{:#x})", self.0, self.1.filename,
+               self.1.method, self.1.lf_offset)
+    }
+}
+
+impl Instance for CDInstance {
+    type Item = CD;
+
+    fn ptr(&self) -> usize {
+        self.0
+    }
+
+    fn header(&self) -> &Self::Item {
+        &self.1
+    }
+
+    fn cluster(data: &[Self], k: usize) -> Result<Vec<Cluster<Self>>, ClusteringError> {
+        ::analysis::cluster(data, k)
+    }
+
+    fn cluster_with(
+        data: &[Self],
+        k: usize,
+        params: &ClusterParams,
+    ) -> Result<(Vec<Cluster<Self>>, usize), ClusteringError> {
+        ::analysis::cluster_with(data, k, params)
+    }
+}
+
+impl Vectorizable for LF {
+    type Output = Euclid<[f64; 4]>;
+
+    fn to_euclidean(&self) -> Self::Output {
+        // Same as for `CD`, minus the version made by which local headers don't carry
+        Euclid(
+            [f64::from(self.timestamp),          // Time/date
+                f64::from(self.method),             // Method
+                f64::from(self.v_needed),           // Version Needed
+                f64::from(self.gp_flags.bits()),    // Flags
+        ],
+        )
+    }
+}
+
+impl Instance for LFInstance {
+    type Item = LF;
+
+    fn ptr(&self) -> usize {
+        self.0
+    }
+
+    fn header(&self) -> &Self::Item {
+        &self.1
+    }
+
+    fn cluster(data: &[Self], k: usize) -> Result<Vec<Cluster<Self>>, ClusteringError> {
+        ::analysis::cluster(data, k)
+    }
+
+    fn cluster_with(
+        data: &[Self],
+        k: usize,
+        params: &ClusterParams,
+    ) -> Result<(Vec<Cluster<Self>>, usize), ClusteringError> {
+        ::analysis::cluster_with(data, k, params)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+/// A Local File Header
+pub struct LF {
+    /// Version needed to decompress
+    ///
+    /// (In practical terms this is often in practical terms used as a flag for the type of
+    /// compressed object, so as to determine whether the file is a folder, or a file, or some
+    /// other marker within the zip file)
+    pub v_needed: u16,
+    /// General purpose zip flags
+    #[cfg_attr(feature = "serialize", serde(with = "zip_flags_serde"))]
+    pub gp_flags: ZipFlags,
+    /// Compression method
+    pub method: u16,
+    /// Timestamp (stored here in unix epoch)
+    pub timestamp: u32,
+    /// The DOS date and time exactly as we found them (or as the CD had them), as with `CD`
+    pub raw_datetime: Option<[u8; 4]>,
+    /// Data Descriptor chunk
+    pub dd: DD,
+    /// Filename Length
+    pub fn_len: u16,
+    /// Extra Field Length
+    pub ef_len: u16,
+    /// Filename
+    pub filename: String,
+    /// The filename bytes exactly as we found them (or as the CD had them), as with `CD`
+    pub raw_filename: Option<Vec<u8>>,
+    /// Extra field records
+    ///
+    /// These aren't rebuilt when converting from a `CD`, as the local and central copies of an
+    /// entry's extra fields often differ.
+    pub extra: Vec<ExtraField>,
+}
+
+/// Header ID of the Info-ZIP Unix1 extra field
+pub const EF_UNIX1: u16 = 0x5855;
+
+/// Header ID of the extended timestamp extra field, which the central directory copy only carries
+/// the modification time in
+pub const EF_EXTENDED_TIMESTAMP: u16 = 0x5455;
+
+/// Header ID of the Zip64 extended information extra field
+pub const EF_ZIP64: u16 = 0x0001;
+
+/// Version needed to extract for archives using Zip64 extensions
+const ZIP64_VERSION: u16 = 45;
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+/// A raw extra field record, as attached to `CD` and `LF` headers
+pub struct ExtraField {
+    /// Header ID identifying the kind of record
+    pub id: u16,
+    /// Payload length
+    pub len: u16,
+    /// Raw payload
+    pub data: Vec<u8>,
+}
+
+impl ExtraField {
+    /// Decode this record as an Info-ZIP Unix1 field, if that's what it is.
+    pub fn unix1(&self) -> Option<Unix1> {
+        if self.id != EF_UNIX1 {
+            return None;
+        }
+        match parse_unix1(&self.data) {
+            Done(_, unix1) => Some(unix1),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The old Info-ZIP Unix extra field (`0x5855`), carrying unix epoch access and modification
+/// times in place of the extended timestamp field.
+///
+/// Unlike the DOS timestamp these are UTC with one second resolution, so make for a sharper
+/// clustering feature and the right thing to restore as an mtime on extraction.
+pub struct Unix1 {
+    /// Last access time
+    pub atime: u32,
+    /// Last modification time
+    pub mtime: u32,
+    /// Raw uid/gid bytes, only present in local headers
+    pub ids: Option<Vec<u8>>,
+}
+
+/// First Unix1 extra field in a collection, if any
+fn find_unix1(extra: &[ExtraField]) -> Option<Unix1> {
+    extra.iter().filter_map(ExtraField::unix1).next()
+}
+
+fn u16_to_le(u: u16) -> [u8;2] {
+    return [(u&0xff) as u8, (u>>8) as u8]
+}
+
+fn u32_to_le(mut u: u32) -> [u8;4] {
+    let mut x = [0u8;4];
+    for i in 0..4 {
+        x[i] = (u & 0xff) as u8;
+        u >>= 8;
+    }
+    x
+}
+
+/// Earliest timestamp representable as an MS-DOS datetime (1980-01-01 00:00:00)
+const DOS_EPOCH: u32 = 315_532_800;
+
+/// Convert a unix epoch timestamp back to MS-DOS datetime bytes, the inverse of
+/// `parser::parse_dosdatetime`.
+///
+/// DOS time only has two second granularity, so odd seconds are truncated exactly as the
+/// original zip writer would have done, and anything before 1980 is clamped to the DOS epoch.
+/// Anything that came out of `parse_dosdatetime` round-trips to the bytes it was parsed from,
+/// which matters because `LF::unparse` feeds an exact byte comparison in `FragSys::find_lf`.
+pub(crate) fn dostime_to_bytes(ts: u32) -> [u8;4] {
+    use chrono::{NaiveDate, NaiveDateTime, Timelike, Datelike};
+    let mut res = [0u8;4];
+    let ts = cmp::max(ts, DOS_EPOCH) & !1;
+    let datetime = NaiveDateTime::from_timestamp(ts as i64, 0);
+    let time = ((datetime.hour() << 11) | (datetime.minute() <<5) | (datetime.second()/2)) as u16;
+    for (i,v) in u16_to_le(time).iter().enumerate() {
+        res[i] = *v;
+    }
+    let date = ((((datetime.year() - 1980) << 9) as u32) | (datetime.month() << 5) | datetime.day()) as u16;
+    for (i,v) in u16_to_le(date).iter().enumerate() {
+        res[i+2] = *v;
+    }
+    res
+}
+
+/// Points a fuzzy local header match gets for agreeing on the filename
+const LF_SCORE_FILENAME: u32 = 2;
+/// Points a fuzzy local header match gets for agreeing on the filename length
+const LF_SCORE_FN_LEN: u32 = 1;
+/// Points a fuzzy local header match gets for agreeing on the compression method
+const LF_SCORE_METHOD: u32 = 1;
+/// Points a fuzzy local header match gets for agreeing on the CRC32, where both headers have one
+const LF_SCORE_CRC32: u32 = 2;
+/// Score a fuzzy local header match needs before we'll believe it: the filename and CRC32 and one
+/// more besides. A CRC32 in a data descriptor scores nothing, so those entries never get there on
+/// a shared name alone (every JAR has a `META-INF/MANIFEST.MF`).
+const LF_FUZZY_THRESHOLD: u32 = 5;
+
+impl LF {
+    pub fn from(cd: &CD) -> Self {
+        cd.to_lf()
+    }
+
+    /// How well `other` agrees with this header on the fields which tell one entry's local
+    /// header from another's, out of 6.
+    ///
+    /// The timestamp, versions and extra field are left out, being the usual suspects when a
+    /// header we rebuilt from a CD doesn't match the real thing byte for byte. The CRC32 only
+    /// counts where both headers actually have one rather than leaving it to a data descriptor.
+    pub fn fuzzy_score(&self, other: &LF) -> u32 {
+        let mut score = 0;
+        if self.filename == other.filename {
+            score += LF_SCORE_FILENAME;
+        }
+        if self.fn_len == other.fn_len {
+            score += LF_SCORE_FN_LEN;
+        }
+        if self.method == other.method {
+            score += LF_SCORE_METHOD;
+        }
+        if !self.gp_flags.contains(DATA_DESCRIPTOR) && !other.gp_flags.contains(DATA_DESCRIPTOR) &&
+            self.dd.crc32 == other.dd.crc32
+        {
+            score += LF_SCORE_CRC32;
+        }
+        score
+    }
+
+    /// Whether the entry uses Zip64 extensions, going by its version needed to extract or the
+    /// presence of a Zip64 extra field, as with `CD::is_zip64`
+    pub fn is_zip64(&self) -> bool {
+        self.v_needed & 0xff >= ZIP64_VERSION || self.extra.iter().any(|field| field.id == EF_ZIP64)
+    }
+
+    /// The entry's Unix1 extra field, if it has one
+    pub fn unix1(&self) -> Option<Unix1> {
+        find_unix1(&self.extra)
+    }
+
+    pub fn unparse(&self) -> Vec<u8> {
+        use std::mem::transmute;
+        let mut res = Vec::new();
+        res.extend_from_slice(b"PK\x03\x04");
+        res.extend_from_slice(&u16_to_le(self.v_needed));
+        res.extend_from_slice(&u16_to_le(self.gp_flags.bits));
+        res.extend_from_slice(&u16_to_le(self.method));
+        let datetime = self.raw_datetime.unwrap_or_else(|| dostime_to_bytes(self.timestamp));
+        res.extend_from_slice(&datetime);
+        if (self.gp_flags.contains(DATA_DESCRIPTOR)) {
+            res.extend_from_slice(&[0u8;12]);
+        } else {
+            res.extend_from_slice(&self.dd.unparse());
+        }
+        let filename = name_bytes(&self.raw_filename, &self.filename, self.gp_flags);
+        res.extend_from_slice(&u16_to_le(filename.len() as u16));
+        res.extend_from_slice(&u16_to_le(self.ef_len));
+        res.extend_from_slice(&filename);
+        //debug!("Unparsed to {:?}", res);
+        res
+    }
+}
+
+/// A Data Descriptor Chunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct DD {
+    /// CRC32 checksum over zipped value.
+    pub crc32: u32,
+    /// Compressed size
+    pub z_sz: u32,
+    /// Uncompressed size
+    pub u_sz: u32,
+}
+
+impl DD {
+    pub fn unparse(&self) -> [u8;12] {
+        let mut res = [0u8;12];
+        for (i,v) in u32_to_le(self.crc32).iter().enumerate() {
+            res[i] = *v;
+        }
+        for (i,v) in u32_to_le(self.z_sz).iter().enumerate() {
+            res[i+4] = *v;
+        }
+        for (i,v) in u32_to_le(self.u_sz).iter().enumerate() {
+            res[i+8] = *v;
+        }
+        debug!("Unparsed DD: ({},{},{}) to {:?}", self.crc32, self.z_sz, self.u_sz, res);
+        res
+    }
+}
+
+/// A Data Descriptor Chunk widened to Zip64 sizes
+///
+/// Streamed Zip64 entries are followed by a descriptor with 8 byte sizes, which is otherwise the
+/// same as the usual one. Ordinary descriptors widen losslessly into these.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DD64 {
+    /// CRC32 checksum over zipped value.
+    pub crc32: u32,
+    /// Compressed size
+    pub z_sz: u64,
+    /// Uncompressed size
+    pub u_sz: u64,
+}
+
+impl From<DD> for DD64 {
+    fn from(dd: DD) -> Self {
+        DD64 {
+            crc32: dd.crc32,
+            z_sz: u64::from(dd.z_sz),
+            u_sz: u64::from(dd.u_sz),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Kinds of header magic we go looking for in a dump
+pub enum Magic {
+    /// End of Central Directory
+    EOCD,
+    /// Central Directory
+    CD,
+    /// Local File Header
+    LF,
+    /// Data Descriptor
+    DD,
+}
+
+impl Magic {
+    /// The signature bytes for this kind of header
+    pub fn bytes(&self) -> &'static [u8] {
+        match *self {
+            Magic::EOCD => b"PK\x05\x06",
+            Magic::CD => b"PK\x01\x02",
+            Magic::LF => b"PK\x03\x04",
+            Magic::DD => b"PK\x07\x08",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The set of header signatures to search a dump for.
+///
+/// Some vendors ship "zips" with the PK signatures swapped for their own, presumably to keep
+/// `unzip` from getting its hands on them. The structures behind the signatures are otherwise
+/// untouched, so all we need to do to handle them is go looking for the right bytes.
+pub struct Magics {
+    /// End of Central Directory signature
+    pub eocd: Vec<u8>,
+    /// Central Directory signature
+    pub cd: Vec<u8>,
+    /// Local File Header signature
+    pub lf: Vec<u8>,
+    /// Data Descriptor signature
+    pub dd: Vec<u8>,
+}
+
+impl Default for Magics {
+    /// The standard PKZip signatures
+    fn default() -> Self {
+        Magics {
+            eocd: Magic::EOCD.bytes().to_vec(),
+            cd: Magic::CD.bytes().to_vec(),
+            lf: Magic::LF.bytes().to_vec(),
+            dd: Magic::DD.bytes().to_vec(),
+        }
+    }
+}
+
+impl Magics {
+    /// The configured signature for a given kind of header
+    pub fn get(&self, magic: Magic) -> &[u8] {
+        match magic {
+            Magic::EOCD => &self.eocd,
+            Magic::CD => &self.cd,
+            Magic::LF => &self.lf,
+            Magic::DD => &self.dd,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Settings for accepting EOCDs whose magic has suffered a few flipped bits.
+///
+/// Bit rot in flash doesn't care that it's hit a signature, but a near-miss on four bytes turns
+/// up by chance often enough in a big dump that a near-miss magic alone isn't enough to go on.
+/// So anything found this way has to have plausible fields too before we'll believe in it.
+pub struct FuzzyEocd {
+    /// Most bits of the magic which may differ from the configured one
+    pub max_bit_errors: u32,
+    /// Most entries a plausible archive may claim to have
+    pub max_entries: u16,
+    /// Whether the whole of the comment the EOCD declares must be there in the dump after it
+    pub require_whole_comment: bool,
+}
+
+impl Default for FuzzyEocd {
+    fn default() -> Self {
+        FuzzyEocd {
+            max_bit_errors: 2,
+            max_entries: 0x4000,
+            require_whole_comment: true,
+        }
+    }
+}
+
+impl FuzzyEocd {
+    /// Check the fields of an EOCD found by a fuzzy magic match, giving the reason for rejecting
+    /// it if they don't add up.
+    ///
+    /// This is on top of the checks every EOCD gets (see `FragSys::find_zips_with_prefix`), so
+    /// whether it fits in the dump is left to those.
+    pub fn check(&self, eocd: &EOCD) -> Result<(), &'static str> {
+        if eocd.tot_entries == 0 || eocd.tot_entries > self.max_entries {
+            return Err("implausible entry count");
+        }
+        if eocd.dsk_entries > eocd.tot_entries {
+            return Err("more entries on this disk than in total");
+        }
+        if (eocd.cd_sz as usize) < eocd.tot_entries as usize * CD_FIXED_SZ {
+            return Err("central directory too small for its entries");
+        }
+        if self.require_whole_comment && eocd.zip_cmt.len() != eocd.cmt_len as usize {
+            return Err("comment cut short");
+        }
+        Ok(())
+    }
+}
+
+/// Number of bits differing between two byte strings, over the length of the shorter
+fn bit_errors(a: &[u8], b: &[u8]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Outcome of trying to match a central directory entry up with its local file header
+pub enum LfMatchResult {
+    /// Found it at this pointer, with its page still in the pool
+    Matched(usize),
+    /// Found a header at this pointer matching on everything but the filename, with its page
+    /// still in the pool
+    FilenameMismatch(usize),
+    /// Nothing matched exactly, but the header at `ptr` agrees with the entry by
+    /// `LF::fuzzy_score`, with its page still in the pool
+    Fuzzy {
+        /// Where the header is
+        ptr: usize,
+        /// How well it agreed, out of 6
+        score: u32,
+    },
+    /// No parseable local header in the dump shares the entry's timestamp and CRC32
+    MagicNotFound,
+    /// Some local headers share the entry's timestamp and CRC32 but none match it exactly (a
+    /// timestamp or flags round-trip issue, or just a different file)
+    MagicFoundNoByteMatch {
+        /// Number of headers compared
+        candidates: usize,
+    },
+    /// Found it, but its page has already been taken out of the pool
+    PageUnavailable,
+}
+
+/// Length of a header magic
+const MAGIC_LEN: usize = 4;
+
+/// Overlap kept between windows of a streaming scan: the largest header we might want to parse
+/// (a CD with maximal filename, extra field and comment).
+const STREAM_OVERLAP: usize = 46 + 3 * 0xffff;
+
+/// Pages read per window of a streaming scan
+const STREAM_WINDOW_PAGES: usize = 0x1000;
+
+impl FragSys {
+    /// Scan a dump for EOCD, CD and LF magics without loading the whole thing into memory.
+    ///
+    /// The input is read in windows of whole pages, each overlapping the last by the size of the
+    /// largest possible header, so a header straddling a window boundary is still found (and
+    /// found exactly once). For each magic, in dump order, `handler` is called with the kind of
+    /// magic, its absolute offset in the dump and the data from the magic to the end of the
+    /// window, which is long enough to parse the header unless the dump ends first.
+    ///
+    /// Returns the total length of the dump.
+    pub fn scan_streaming<R, F>(mut reader: R, page_sz: usize, mut handler: F) -> Result<usize, Error>
+    where
+        R: Read,
+        F: FnMut(Magic, usize, &[u8]),
+    {
+        let window = cmp::max(page_sz * STREAM_WINDOW_PAGES, 4 * STREAM_OVERLAP);
+        let mut buf: Vec<u8> = Vec::with_capacity(window);
+        let mut base = 0;
+        let mut eof = false;
+
+        loop {
+            while !eof && buf.len() < window {
+                let start = buf.len();
+                buf.resize(window, 0);
+                let result = reader.read(&mut buf[start..]);
+                let read = match result {
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => {
+                        buf.truncate(start);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                buf.truncate(start + read);
+                eof = read == 0;
+            }
+
+            // Magics starting in the overlap get reported with the next window instead
+            let committed = if eof { buf.len() } else { buf.len() - STREAM_OVERLAP };
+            let limit = cmp::min(buf.len(), committed + MAGIC_LEN - 1);
+
+            let mut found = vec![];
+            for &magic in &[Magic::EOCD, Magic::CD, Magic::LF] {
+                for ptr in find_bytes(&buf[..limit], magic.bytes()) {
+                    found.push((ptr, magic));
+                }
+            }
+            found.sort_by_key(|&(ptr, _)| ptr);
+            for (ptr, magic) in found {
+                handler(magic, base + ptr, &buf[ptr..]);
+            }
+
+            if eof {
+                return Ok(base + buf.len());
+            }
+            buf.drain(..committed);
+            base += committed;
+        }
+    }
+
+    /// Create a model for a fragmented FS from anything readable, e.g. a pipe or a decompressor,
+    /// reading it through to the end.
+    ///
+    /// Unlike `from_file` there's no length to check against up front, so the page map is built
+    /// from however many bytes turn up.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: Read>(reader: R, page_sz: usize) -> Result<Self, Error> {
+        let mut bytes = vec![];
+        BufReader::new(reader).read_to_end(&mut bytes)?;
+        Ok(Self::from_bytes(bytes, page_sz))
+    }
+
+    /// Create a model for a fragmented FS from a `File`, pre-sizing the buffer from its metadata
+    /// and making sure we read all we were promised
+    #[cfg(feature = "std")]
+    pub fn from_file(file: &mut File, page_sz: usize) -> Result<Self, Error> {
+        Self::from_file_aligned(file, page_sz, 0)
+    }
+
+    /// Create a model for a fragmented FS from a `File` whose page grid starts `start_offset`
+    /// bytes in, e.g. after a partition header of some non-page-multiple size.
+    ///
+    /// Bytes before `start_offset` are kept in `data` (so pointers are still dump offsets) but
+    /// don't belong to any page.
+    #[cfg(feature = "std")]
+    pub fn from_file_aligned(file: &mut File, page_sz: usize, start_offset: usize)
+        -> Result<Self, Error>
+    {
+        let len = file.metadata()?.len() as usize;
+        let mut reader = BufReader::new(file);
+        let mut bytes = Vec::with_capacity(len);
+        let len_read = reader.read_to_end(&mut bytes)?;
+        if len_read != len {
+            return Err(Error::new(
+                ErrorKind::Other,
+                "length read doesn't match length available",
+            ));
+        }
+        Ok(Self::from_bytes_aligned(bytes, page_sz, start_offset))
+    }
+
+    /// Create a model for a fragmented FS by memory mapping the dump at `path` rather than reading
+    /// it in, so it can be bigger than the memory we've got.
+    ///
+    /// The file mustn't be modified or truncated while the `FragSys` is around; if it is, all
+    /// bets are off (truncation can take the process down with it).
+    #[cfg(feature = "std")]
+    pub fn from_mmap(path: &Path, page_sz: usize) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // Safe as long as nobody else changes the file under us, which is on the caller
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self::from_data_aligned(DumpData::Mapped(map), page_sz, 0))
+    }
+
+    /// Create a model for a fragmented FS from a dump already in memory
+    pub fn from_bytes(bytes: Vec<u8>, page_sz: usize) -> Self {
+        Self::from_bytes_aligned(bytes, page_sz, 0)
+    }
+
+    /// Create a model for a fragmented FS from a dump already in memory, with the page grid
+    /// starting `start_offset` bytes in
+    pub fn from_bytes_aligned(bytes: Vec<u8>, page_sz: usize, start_offset: usize) -> Self {
+        Self::from_data_aligned(DumpData::Owned(bytes), page_sz, start_offset)
+    }
+
+    /// Create a model for a fragmented FS over `data` wherever it lives, with the page grid
+    /// starting `start_offset` bytes in
+    fn from_data_aligned(data: DumpData, page_sz: usize, start_offset: usize) -> Self {
+        let len = data.len().saturating_sub(start_offset);
+
+        // Check dat uglycast
+        let pg_count = len / page_sz + (if len % page_sz > 0 { 1 } else { 0 });
+
+        // Initialize Big Ole Page Map
+        let pages = (0..pg_count)
+            .map(|pg| {
+                let start = start_offset + pg * page_sz;
+                let stop = start_offset + page_sz * (pg + 1);
+                Page::Assigned(start..stop)
+            })
+            .collect();
+
+        Self {
+            data: data,
+            page_sz: page_sz,
+            start_offset: start_offset,
+            pages: pages,
+            magics: Magics::default(),
+            lf_index: None,
+            lf_names: None,
+            fuzzy_eocd: None,
+            fuzzy_lf: false,
+        }
+    }
+
+    /// The same dump cut into pages of `page_sz` instead, e.g. once we've worked out what the page
+    /// size really is. The page pool starts afresh, so this is for before reconstruction, not
+    /// partway through it.
+    pub fn with_page_sz(self, page_sz: usize) -> Self {
+        let mut fs = Self::from_data_aligned(self.data, page_sz, self.start_offset);
+        fs.magics = self.magics;
+        fs.fuzzy_eocd = self.fuzzy_eocd;
+        fs.fuzzy_lf = self.fuzzy_lf;
+        fs
+    }
+
+    /// Offset into the dump at which the page grid starts
+    pub fn start_offset(&self) -> usize {
+        self.start_offset
+    }
+
+    /// Search FragSys for a given page, and if found, pull the page from the FS.
+    ///
+    /// If more than one page in the pool holds the address, none of them is pulled and the error
+    /// says which they were, so the caller can decide between them and `take_page` that.
+    pub fn get_pg_for_addr(&mut self, address: usize) -> Result<Page, PageLookupError> {
+        let matches: Vec<usize> = self.pages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, page)|
+                        if page.contains(address) {
+                            Some(i)
+                        } else {
+                            None
+                        })
+            .collect();
+        match matches.len() {
+            0 => Err(PageLookupError::NotFound),
+            1 => Ok(self.pages.swap_remove(matches[0])),
+            _ => Err(PageLookupError::Ambiguous(matches)),
+        }
+    }
+
+    /// Pull the page at index `idx` of the pool, e.g. one picked out of an `Ambiguous` lookup.
+    pub fn take_page(&mut self, idx: usize) -> Option<Page> {
+        if idx < self.pages.len() {
+            Some(self.pages.swap_remove(idx))
+        } else {
+            None
+        }
+    }
+
+    /// The pages left in the pool, i.e. those nothing has claimed yet, for anyone wanting to try
+    /// their own gap filling on them. Indexes into this are what `take_page` wants.
+    pub fn remaining_pages(&self) -> &[Page] {
+        &self.pages
+    }
+
+    /// Number of pages left in the pool
+    pub fn remaining_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Ranges of the dump covered by the pages left in the pool
+    pub fn remaining_ranges(&self) -> impl Iterator<Item = &Range<usize>> {
+        self.pages.iter().filter_map(|page| match *page {
+            Page::Assigned(ref bytes) => Some(bytes),
+            Page::Unassigned | Page::Synthesized(_) => None,
+        })
+    }
+
+    /// Indexes of the pages left in the pool whose entropy exceeds `threshold` bits per byte,
+    /// i.e. the likeliest candidates for filling gaps in compressed data.
+    pub fn high_entropy_pages(&self, threshold: f64) -> Vec<usize> {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter(|&(_, page)| page.entropy(&self.data) > threshold)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Put a page back in the pool, e.g. one taken out for a reconstruction hypothesis which
+    /// didn't pan out. Only pages from the dump belong in the pool, so anything else is dropped.
+    pub fn return_page(&mut self, page: Page) {
+        match page {
+            Page::Assigned(_) => self.pages.push(page),
+            Page::Unassigned | Page::Synthesized(_) => {}
+        }
+    }
+
+    /// Return Page Size for FS
+    pub fn page_sz(&self) -> usize {
+        self.page_sz
+    }
+
+    /// The header signatures currently being searched for
+    pub fn magics(&self) -> &Magics {
+        &self.magics
+    }
+
+    /// Accept EOCDs with a few bits of their magic flipped, subject to `fuzzy`'s checks, or
+    /// don't with `None` (the default).
+    pub fn set_fuzzy_eocd(&mut self, fuzzy: Option<FuzzyEocd>) {
+        self.fuzzy_eocd = fuzzy;
+    }
+
+    /// Let `match_lf` fall back on `find_lf_fuzzy` when nothing matches exactly. Off by default,
+    /// since a header that's merely close is a weaker guess than most of what goes into placing
+    /// pages.
+    pub fn set_fuzzy_lf(&mut self, fuzzy: bool) {
+        self.fuzzy_lf = fuzzy;
+    }
+
+    /// Search for (and parse with) a different set of header signatures from here on
+    pub fn set_magics(&mut self, magics: Magics) {
+        self.magics = magics;
+        self.lf_index = None;
+        self.lf_names = None;
+    }
+
+    //    /// Update `FragSys` with fresh page size
+    //    pub fn with_page_sz(&mut self, page_sz: usize) {
+    //        self.page_sz = page_sz;
+    //    }
+
+    /// Find all occurrences of `pattern` in the dump, overlapping or not, with a single memchr
+    /// driven `scan_bytes` pass.
+    #[cfg(not(feature = "parallel"))]
+    fn find_bytes(&self, pattern: &[u8]) -> Vec<usize> {
+        scan_bytes(&self.data, pattern, 1)
+    }
+
+    /// Find all occurrences of `pattern` in the dump, overlapping or not, spread across all the
+    /// cores we've got.
+    #[cfg(feature = "parallel")]
+    fn find_bytes(&self, pattern: &[u8]) -> Vec<usize> {
+        scan_bytes_parallel(&self.data, pattern, PARALLEL_CHUNK_SZ)
+    }
+
+    /// Find all identifiable EOCD magics, returning a collection of pointers.
+    ///
+    /// With fuzzy matching turned on, this includes near-miss magics whose EOCDs pass the checks.
+    fn find_eocds(&self) -> Vec<usize> {
+        let mut ptrs = self.find_bytes(&self.magics.eocd);
+        if let Some(fuzzy) = self.fuzzy_eocd {
+            ptrs.extend(self.find_fuzzy_eocds(&fuzzy));
+            ptrs.sort();
+        }
+        ptrs
+    }
+
+    /// Find EOCDs whose magic is within `fuzzy.max_bit_errors` of the configured one (but not
+    /// an exact match) and whose fields pass both `check_eocd` and `fuzzy`'s checks.
+    fn find_fuzzy_eocds(&self, fuzzy: &FuzzyEocd) -> Vec<usize> {
+        let magic = &self.magics.eocd;
+        let mut findings = vec![];
+        if magic.is_empty() || self.data.len() < magic.len() {
+            return findings;
+        }
+        for ptr in 0..self.data.len() - magic.len() + 1 {
+            let found = &self.data[ptr..ptr + magic.len()];
+            let errors = bit_errors(found, magic);
+            if errors == 0 || errors > fuzzy.max_bit_errors {
+                continue;
+            }
+            match parse_eocd_with(&self.data[ptr..], found) {
+                Done(_, eocd) => match self.check_eocd(ptr, &eocd)
+                    .and_then(|()| fuzzy.check(&eocd))
+                {
+                    Ok(()) => {
+                        info!("Accepting EOCD at {:#x} with {} bit errors in its magic", ptr,
+                              errors);
+                        findings.push(ptr);
+                    }
+                    Err(reason) => {
+                        debug!("Rejecting fuzzy EOCD match at {:#x}: {}", ptr, reason);
+                    }
+                },
+                _ => debug!("Rejecting fuzzy EOCD match at {:#x}: doesn't parse", ptr),
+            }
+        }
+        findings
+    }
+
+    /// The magic to parse the EOCD at `ptr` with: whatever's actually there if it's a near miss
+    /// we're accepting, or the configured one otherwise.
+    fn eocd_magic_at(&self, ptr: usize) -> &[u8] {
+        let magic = &self.magics.eocd[..];
+        if let Some(fuzzy) = self.fuzzy_eocd {
+            if let Some(found) = self.data.get(ptr..ptr + magic.len()) {
+                if bit_errors(found, magic) <= fuzzy.max_bit_errors {
+                    return found;
+                }
+            }
+        }
+        magic
+    }
+
+    /// The (`cd_offset`, `cd_sz`) the EOCD at `ptr` gives, from the Zip64 record ahead of it if
+    /// it has one, along with where in the dump the central directory would end if it ran
+    /// straight on into the Zip64 records, signature and EOCD after it, laid out as
+    /// `ZipFile::new_with_prefix` has them. `None` for Zip64 sentinels with no record to resolve
+    /// them.
+    fn cd_extent(&self, ptr: usize, eocd: &EOCD) -> Option<(u64, u64, usize)> {
+        let plain = (u64::from(eocd.cd_offset), u64::from(eocd.cd_sz));
+        let has_locator = ptr.checked_sub(EOCD64_LOCATOR_SZ).map_or(false, |start| {
+            match parse_eocd64_locator(&self.data[start..ptr]) {
+                Done(_, _) => true,
+                _ => false,
+            }
+        });
+        let ((cd_offset, cd_sz), eocd64_len) = if eocd.has_zip64_sentinels() || has_locator {
+            match find_eocd64(&self.data, ptr) {
+                Some((len, eocd64)) => ((eocd64.cd_offset, eocd64.cd_sz), len + EOCD64_LOCATOR_SZ),
+                None if eocd.has_zip64_sentinels() => return None,
+                None => {
+                    let len = EOCD64_FIXED_SZ + EOCD64_LOCATOR_SZ;
+                    (plain, if ptr >= len { len } else { 0 })
+                }
+            }
+        } else {
+            (plain, 0)
+        };
+        let cd_sig_len = ptr.checked_sub(eocd64_len)
+            .map_or(0, |end| cd_signature_len(&self.data, end));
+        Some((cd_offset, cd_sz, ptr.saturating_sub(eocd64_len + cd_sig_len)))
+    }
+
+    /// Whether the central directory described by `cd_extent` is there in the dump right ahead
+    /// of its EOCD, going by whether it starts with a CD header.
+    fn has_adjacent_cd(&self, extent: (u64, u64, usize)) -> bool {
+        let (_, cd_sz, cd_end) = extent;
+        cd_sz > 0 && cd_sz <= cd_end as u64 &&
+            self.data[cd_end - cd_sz as usize..].starts_with(&self.magics.cd)
+    }
+
+    /// Find all parseable EOCDs, grouping together the pointers of those which describe the same
+    /// central directory (same `cd_offset` and `cd_sz`, from the Zip64 record where there is
+    /// one).
+    ///
+    /// This happens when an old and a new generation of an archive both survive in a dump but
+    /// only the payload was rewritten: the two EOCDs point at the one intact central directory,
+    /// which we only want to count (and cluster) as one archive. Two EOCDs which each have a
+    /// central directory of their own right ahead of them are two archives which just happen to
+    /// be laid out alike, so they're never grouped, and nor is an EOCD whose Zip64 sentinels
+    /// can't be resolved.
+    pub fn find_eocd_groups(&self) -> Vec<Vec<usize>> {
+        // (cd_offset, cd_sz), whether any EOCD in the group has its own CD, and the EOCDs
+        let mut groups: Vec<(Option<(u64, u64)>, bool, Vec<usize>)> = vec![];
+        for ptr in self.find_eocds() {
+            if let Done(_, eocd) = parse_eocd_with(&self.data[ptr..], self.eocd_magic_at(ptr)) {
+                let extent = match self.cd_extent(ptr, &eocd) {
+                    Some(extent) => extent,
+                    None => {
+                        groups.push((None, false, vec![ptr]));
+                        continue;
+                    }
+                };
+                let key = Some((extent.0, extent.1));
+                let own_cd = self.has_adjacent_cd(extent);
+                if let Some(group) = groups
+                    .iter_mut()
+                    .find(|group| group.0 == key && !(own_cd && group.1))
+                {
+                    group.1 |= own_cd;
+                    group.2.push(ptr);
+                    continue;
+                }
+                groups.push((key, own_cd, vec![ptr]));
+            }
+        }
+        groups.into_iter().map(|(_, _, ptrs)| ptrs).collect()
+    }
+
+    /// Find and return a collection of ZipFile instances
+    ///
+    /// This is performed by searching for EOCD magic values and then parsing them with nom.
+    pub fn find_zips(&mut self) -> Vec<ZipFile> {
+        self.find_zips_with_prefix(0)
+    }
+
+    /// Check the EOCD at `ptr` describes an archive which could actually be in the dump, giving
+    /// the reason for rejecting it if not.
+    ///
+    /// Firmware padding turns up EOCD magics by the dozen, and each one taken at its word makes
+    /// for a `ZipFile` with a nonsense page count to throw off matching CDs up with archives. Any
+    /// archive in the dump has to fit in it, and so does any EOCD's comment. Zip64 sentinels are
+    /// let through, as the real offsets are elsewhere.
+    fn check_eocd(&self, ptr: usize, eocd: &EOCD) -> Result<(), &'static str> {
+        if !eocd.has_zip64_sentinels() &&
+            u64::from(eocd.cd_offset) + u64::from(eocd.cd_sz) > self.data.len() as u64
+        {
+            return Err("central directory runs past the end of the dump");
+        }
+        if ptr + EOCD_FIXED_SZ + eocd.cmt_len as usize > self.data.len() {
+            return Err("comment runs past the end of the dump");
+        }
+        Ok(())
+    }
+
+    /// Find and return a collection of ZipFile instances, each wrapped in a container header
+    /// `prefix_len` bytes long.
+    ///
+    /// Where several EOCDs share a central directory only the first becomes a `ZipFile`, and
+    /// EOCDs which fail `check_eocd` or put their central directory in the same place in the
+    /// dump as one already taken don't become one at all.
+    ///
+    /// That place is where the central directory would end up running straight into the EOCD:
+    /// `cd_offset` is relative to the start of its own archive, so any two archives laid out
+    /// alike ahead of their CDs have the same one, and every Zip64 EOCD has the same sentinel.
+    pub fn find_zips_with_prefix(&mut self, prefix_len: usize) -> Vec<ZipFile> {
+        let eocd_groups = self.find_eocd_groups();
+        let mut zips: Vec<ZipFile> = Vec::with_capacity(eocd_groups.len());
+        // Where in the dump each accepted EOCD's central directory starts, and the EOCD
+        let mut cd_starts: Vec<(u64, usize)> = Vec::with_capacity(eocd_groups.len());
+        for group in eocd_groups {
+            let ptr = group[0];
+            if group.len() > 1 {
+                info!("EOCDs at {:?} share a central directory, using {}", group, ptr);
+            }
+            let mut cd_start = None;
+            if let Done(_, eocd) = parse_eocd_with(&self.data[ptr..], self.eocd_magic_at(ptr)) {
+                if let Err(reason) = self.check_eocd(ptr, &eocd) {
+                    info!("Rejecting EOCD at {}: {}", ptr, reason);
+                    continue;
+                }
+                cd_start = self.cd_extent(ptr, &eocd)
+                    .and_then(|(_, cd_sz, cd_end)| (cd_end as u64).checked_sub(cd_sz));
+                let taken = cd_starts.iter().find(|&&(start, _)| Some(start) == cd_start);
+                if let Some(&(_, other)) = taken {
+                    info!("Rejecting EOCD at {}: same central directory as the EOCD at {}", ptr,
+                          other);
+                    continue;
+                }
+            }
+            match ZipFile::new_with_prefix(self, ptr, prefix_len) {
+                Ok(zf) => {
+                    if let Some(start) = cd_start {
+                        cd_starts.push((start, ptr));
+                    }
+                    if zf.is_spanned() {
+                        warn!("EOCD at {} is on disk {} of a spanned set with the CD on disk {}, \
+                               expecting {} of its {} entries", ptr, zf.eocd.dsk_no,
+                              zf.eocd.dsk_w_cd, zf.expected_cd_count(), zf.tot_entries());
+                    }
+                    zips.push(zf)
+                }
+                Err(e) => {
+                    error!("Error: {}", e);
+                }
+            };
+        }
+        zips
+    }
+
+    /// Return a collection of instances of CD Headers recognised and parsed with nom.
+    pub fn find_cds(&mut self) -> Vec<CDInstance> {
+        let cd_ptrs = find_bytes(&self.data, &self.magics.cd);
+        let mut results = Vec::with_capacity(cd_ptrs.len());
+        for ptr in cd_ptrs {
+            match CD::new(self, ptr) {
+                Ok(cd) => results.push(CDInstance(ptr, cd)),
+                Err(e) => {
+                    error!("Error: {}", e);
+                }
+            }
+        }
+        results
+    }
+
+    /// Count the central directory headers in the dump by compression method.
+    ///
+    /// A quick survey of what we're up against before bothering with reconstruction: all deflate
+    /// is the easy case, anything else is going to need more than we can do for it.
+    pub fn method_histogram(&mut self) -> HashMap<u16, usize> {
+        let mut histogram = HashMap::new();
+        for instance in self.find_cds() {
+            *histogram.entry(instance.header().method).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Count the central directory headers in the dump by version needed to extract (e.g. 45
+    /// for Zip64, 51 for AES).
+    pub fn version_histogram(&mut self) -> HashMap<u16, usize> {
+        let mut histogram = HashMap::new();
+        for instance in self.find_cds() {
+            *histogram.entry(instance.header().v_needed).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Return a collection of pointers to instances of Central Directory magics, parseable or not.
+    pub fn find_cd_ptrs(&self) -> Vec<usize> {
+        self.find_bytes(&self.magics.cd)
+    }
+
+    /// Return a collection of pointers to instances of Local File Header magics.
+    pub fn find_lfs(&self) -> Vec<usize> {
+        self.find_bytes(&self.magics.lf)
+    }
+
+    /// Return a collection of instances of LF Headers recognised and parsed with nom.
+    pub fn find_lf_instances(&self) -> Vec<LFInstance> {
+        let lfs = self.find_lfs();
+        let mut headers = lfs.clone();
+        headers.extend(self.find_cd_ptrs());
+        headers.sort();
+        lfs.into_iter()
+            .filter_map(|ptr| self.lf_at(ptr).map(|lf| LFInstance(ptr, lf)))
+            .map(|LFInstance(ptr, mut lf)| {
+                let next = match headers.binary_search(&(ptr + 1)) {
+                    Ok(i) | Err(i) => headers.get(i).cloned().unwrap_or_else(|| self.data.len()),
+                };
+                if let Some((dd_ptr, dd)) = self.match_data_descriptor(ptr, &lf, next) {
+                    debug!("Folding descriptor at {:#x} into {} at {:#x}", dd_ptr, lf.filename,
+                           ptr);
+                    // Zip64 sizes which don't fit get the usual sentinel
+                    let narrow = |sz: u64| if sz > u64::from(u32::max_value()) {
+                        u32::max_value()
+                    } else {
+                        sz as u32
+                    };
+                    lf.dd = DD {
+                        crc32: dd.crc32,
+                        z_sz: narrow(dd.z_sz),
+                        u_sz: narrow(dd.u_sz),
+                    };
+                }
+                LFInstance(ptr, lf)
+            })
+            .collect()
+    }
+
+    /// Return a collection of pointers to instances of Data Descriptor magics.
+    ///
+    /// The data descriptor signature is optional, so this only finds those written with one.
+    pub fn find_data_descriptors(&self) -> Vec<usize> {
+        self.find_bytes(&self.magics.dd)
+    }
+
+    /// Find the data descriptor carrying the real CRC32 and sizes for `lf`, the local header at
+    /// `ptr`, returning where it is along with its contents. Only headers flagged as having a
+    /// descriptor and with their inline sizes zeroed out (or Zip64 sentinels) are worth asking
+    /// about. Zip64 entries get a descriptor with 8 byte sizes.
+    ///
+    /// A descriptor is only taken if it sits right where its own compressed size says the data
+    /// should end, i.e. the data is contiguous in the dump, and its sizes are plausible for the
+    /// method: a stored entry has to have matching sizes and a CRC32 matching the bytes in
+    /// between. Anything else could just as easily be the descriptor of a later entry.
+    ///
+    /// The search stops at `limit`, which wants to be the next LF or CD magic after `ptr`: the
+    /// descriptor has to come before whatever header follows the entry, and without a bound we'd
+    /// be trawling the rest of the dump for every streamed entry in it.
+    pub fn match_data_descriptor(&self, ptr: usize, lf: &LF, limit: usize)
+        -> Option<(usize, DD64)>
+    {
+        let zip64 = lf.is_zip64();
+        let unknown = |sz: u32| sz == 0 || zip64 && sz == u32::max_value();
+        if !lf.gp_flags.contains(DATA_DESCRIPTOR) || !unknown(lf.dd.z_sz) || !unknown(lf.dd.u_sz) {
+            return None;
+        }
+        let start = ptr + LF_FIXED_SZ + lf.fn_len as usize + lf.ef_len as usize;
+        let end = cmp::min(limit, self.data.len());
+        if start > end {
+            return None;
+        }
+        let magic = &self.magics.dd;
+        scan_bytes(&self.data[start..end], magic, 1)
+            .into_iter()
+            .filter_map(|len| {
+                match parse_trailing_dd_with(&self.data[start + len..], magic, zip64) {
+                    Done(_, dd) if dd.z_sz == len as u64 => Some((start + len, dd)),
+                    _ => None,
+                }
+            })
+            .find(|&(_, ref dd)| {
+                lf.method != 0 ||
+                    dd.u_sz == dd.z_sz && {
+                        let mut crc = Crc::new();
+                        crc.update(&self.data[start..start + dd.z_sz as usize]);
+                        crc.sum() == dd.crc32
+                    }
+            })
+    }
+
+    /// Guess the length of a container header starting with `container_magic` by measuring from
+    /// the first instance of the magic to the first local file header following it on the same
+    /// page.
+    pub fn detect_archive_prefix(&self, container_magic: &[u8]) -> Option<usize> {
+        let lfs = find_bytes(&self.data, &self.magics.lf);
+        for ptr in find_bytes(&self.data, container_magic) {
+            if let Some(&lf) = lfs.iter().find(|&&lf| lf >= ptr + container_magic.len()) {
+                if lf - ptr < self.page_sz {
+                    debug!("Container at {:#x} has a {} byte prefix", ptr, lf - ptr);
+                    return Some(lf - ptr);
+                }
+            }
+        }
+        None
+    }
+
+    /// Index every parseable local file header in the dump by `LfKey` and by filename, unless
+    /// that's been done already. Each header only gets parsed the once.
+    fn build_lf_index(&mut self) {
+        if self.lf_index.is_some() && self.lf_names.is_some() {
+            return;
+        }
+        let mut index: HashMap<LfKey, Vec<usize>> = HashMap::new();
+        let mut names: HashMap<String, Vec<(usize, LF)>> = HashMap::new();
+        for ptr in self.find_lfs() {
+            if let Done(_, lf) = parse_lf_with(&self.data[ptr..], &self.magics.lf) {
+                index.entry(lf_key(&lf)).or_insert_with(Vec::new).push(ptr);
+                names.entry(lf.filename.clone()).or_insert_with(Vec::new).push((ptr, lf));
+            }
+        }
+        debug!("Indexed local file headers under {} keys", index.len());
+        self.lf_index = Some(index);
+        self.lf_names = Some(names);
+    }
+
+    /// Parse the local file header at `ptr` in the dump
+    pub fn lf_at(&self, ptr: usize) -> Option<LF> {
+        match self.data.get(ptr..).map(|data| parse_lf_with(data, &self.magics.lf)) {
+            Some(Done(_, lf)) => Some(lf),
+            _ => None,
+        }
+    }
+
+    /// Look up the local file headers sharing `lf`'s timestamp and CRC32, returning how many
+    /// there were and the pointer of the first to match `lf`, along with whether it matched byte
+    /// for byte.
+    ///
+    /// Failing an exact match, we'll take one agreeing on everything but the filename: local and
+    /// central filenames disagreeing is odd (and worth knowing about), but doesn't make it any
+    /// less the right header. Everything includes the CRC32 and sizes, which for a streamed entry
+    /// means its data descriptor has to turn up and agree (see `same_contents`).
+    fn lf_candidates(&mut self, lf: &LF) -> (usize, Option<(usize, bool)>) {
+        self.build_lf_index();
+        let mut bytes = self.magics.lf.clone();
+        bytes.extend_from_slice(&lf.unparse()[MAGIC_LEN..]);
+
+        let candidates = match self.lf_index.as_ref().and_then(|index| index.get(&lf_key(lf))) {
+            Some(candidates) => candidates,
+            None => return (0, None),
+        };
+        let exact = candidates
+            .iter()
+            .cloned()
+            .find(|&i| self.data.get(i..i + bytes.len()) == Some(bytes.as_slice()))
+            .map(|i| (i, true));
+        let matched = exact.or_else(|| {
+            candidates
+                .iter()
+                .cloned()
+                .find(|&i| match self.lf_at(i) {
+                    Some(found) => {
+                        found.method == lf.method && found.gp_flags == lf.gp_flags &&
+                            found.v_needed == lf.v_needed && self.same_contents(i, &found, lf)
+                    }
+                    None => false,
+                })
+                .map(|i| (i, false))
+        });
+        (candidates.len(), matched)
+    }
+
+    /// Whether the local header `found` at `ptr` has the same CRC32 and sizes as `lf`, one
+    /// rebuilt from a CD entry.
+    ///
+    /// A streamed header leaves those to its data descriptor, so that's looked for up to the next
+    /// header and compared instead. No descriptor, no match: every entry a JAR tool streams out
+    /// in the same second shares a timestamp, method and flags, so without its CRC32 there'd be
+    /// no telling a header from its neighbours'.
+    fn same_contents(&self, ptr: usize, found: &LF, lf: &LF) -> bool {
+        if !found.gp_flags.contains(DATA_DESCRIPTOR) {
+            return found.dd == lf.dd;
+        }
+        let (lf_magic, cd_magic) = (&self.magics.lf, &self.magics.cd);
+        let limit = (ptr + 1..self.data.len())
+            .find(|&i| self.data[i..].starts_with(lf_magic) || self.data[i..].starts_with(cd_magic))
+            .unwrap_or_else(|| self.data.len());
+        match self.match_data_descriptor(ptr, found, limit) {
+            Some((_, dd)) => dd == DD64::from(lf.dd),
+            None => false,
+        }
+    }
+
+    /// Find the local file header in the dump best agreeing with `lf` by `LF::fuzzy_score`,
+    /// returning its pointer and score if it scores at least `LF_FUZZY_THRESHOLD` and has the same
+    /// compression method. Headers whose page is still in the pool win over those whose isn't,
+    /// then the higher score, then the first in the dump.
+    ///
+    /// Only a header with the same filename can reach the threshold, so candidates come from the
+    /// filename index built alongside `find_lf`'s rather than parsing the dump all over again.
+    /// Strictly for when `find_lf` has come up empty, e.g. the timestamp was rounded differently
+    /// to how we'd round it.
+    pub fn find_lf_fuzzy(&mut self, lf: &LF) -> Option<(usize, u32)> {
+        self.build_lf_index();
+        let candidates = match self.lf_names.as_ref().and_then(|names| names.get(&lf.filename)) {
+            Some(candidates) => candidates,
+            None => return None,
+        };
+        let pages = &self.pages;
+        candidates
+            .iter()
+            .filter(|&&(_, ref found)| found.method == lf.method)
+            .map(|&(ptr, ref found)| (ptr, lf.fuzzy_score(found)))
+            .filter(|&(_, score)| score >= LF_FUZZY_THRESHOLD)
+            .max_by_key(|&(ptr, score)| {
+                (pages.iter().any(|page| page.contains(ptr)), score, cmp::Reverse(ptr))
+            })
+    }
+
+    /// Find the local file header in the dump matching `lf` byte for byte, returning its pointer.
+    ///
+    /// Candidates are looked up by timestamp and CRC32 in an index built over the whole dump on
+    /// the first call, so only the handful sharing those get compared in full.
+    pub fn find_lf(&mut self, lf: &LF) -> Option<usize> {
+        self.lf_candidates(lf).1.map(|(ptr, _)| ptr)
+    }
+
+    /// As `find_lf`, but saying why when there's no match to be had, and checking the matching
+    /// header's page is still in the pool. With `set_fuzzy_lf` on, falls back on
+    /// `find_lf_fuzzy` when there's no exact match.
+    pub fn match_lf(&mut self, lf: &LF) -> LfMatchResult {
+        let (candidates, matched) = self.lf_candidates(lf);
+        let fuzzy = match matched {
+            None if self.fuzzy_lf => self.find_lf_fuzzy(lf),
+            _ => None,
+        };
+        let (ptr, result) = match (matched, fuzzy) {
+            (Some((ptr, true)), _) => (ptr, LfMatchResult::Matched(ptr)),
+            (Some((ptr, false)), _) => (ptr, LfMatchResult::FilenameMismatch(ptr)),
+            (None, Some((ptr, score))) => (ptr, LfMatchResult::Fuzzy { ptr: ptr, score: score }),
+            (None, None) if candidates == 0 => return LfMatchResult::MagicNotFound,
+            (None, None) => {
+                return LfMatchResult::MagicFoundNoByteMatch { candidates: candidates };
+            }
+        };
+        if self.pages.iter().any(|page| page.contains(ptr)) {
+            result
+        } else {
+            LfMatchResult::PageUnavailable
+        }
+    }
+}
+
+/// Find the non-overlapping occurrences of `pattern` in `data` (see `scan_bytes`).
+fn find_bytes(data: &[u8], pattern: &[u8]) -> Vec<usize> {
+    scan_bytes(data, pattern, pattern.len())
+}
+
+/// Size of the chunks a dump is split into for scanning in parallel. Big enough that the overhead
+/// of farming them out is lost in the noise, small enough that there's plenty to go round.
+#[cfg(feature = "parallel")]
+const PARALLEL_CHUNK_SZ: usize = 0x10_0000;
+
+/// As `scan_bytes` with a `step` of 1, scanning `chunk_sz` byte chunks of `data` in parallel.
+///
+/// Each chunk is searched along with the first `pattern.len() - 1` bytes of the next so that
+/// matches straddling the boundary are caught, but only matches starting within the chunk proper
+/// are kept, so the overlap doesn't turn up anything twice. Chunks come back in order, so the
+/// offsets do too.
+#[cfg(feature = "parallel")]
+fn scan_bytes_parallel(data: &[u8], pattern: &[u8], chunk_sz: usize) -> Vec<usize> {
+    if pattern.is_empty() || chunk_sz == 0 {
+        return Vec::new();
+    }
+    let chunks = (data.len() + chunk_sz - 1) / chunk_sz;
+    (0..chunks)
+        .into_par_iter()
+        .map(|i| {
+            let start = i * chunk_sz;
+            let end = cmp::min(start + chunk_sz + pattern.len() - 1, data.len());
+            scan_bytes(&data[start..end], pattern, 1)
+                .into_iter()
+                .take_while(|&ptr| ptr < chunk_sz)
+                .map(|ptr| start + ptr)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+/// Offsets of `pattern` in `data`, carrying on `step` bytes after each match (so 1 finds
+/// overlapping matches and `pattern.len()` doesn't).
+///
+/// Dumps run to hundreds of megabytes, so rather than comparing a window at every offset we let
+/// memchr skip to each occurrence of the pattern's first byte and only compare there.
+pub(crate) fn scan_bytes(data: &[u8], pattern: &[u8], step: usize) -> Vec<usize> {
+    let mut findings = Vec::new();
+    let first = match pattern.first() {
+        Some(&first) => first,
+        None => return findings,
+    };
+    let mut cursor = 0;
+    // Inclusive of a match ending on the very last byte of the data
+    while cursor + pattern.len() <= data.len() {
+        let ptr = match memchr(first, &data[cursor..data.len() - pattern.len() + 1]) {
+            Some(ptr) => cursor + ptr,
+            None => break,
+        };
+        if &data[ptr..ptr + pattern.len()] == pattern {
+            findings.push(ptr);
+            cursor = ptr + step;
+        } else {
+            cursor = ptr + 1;
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use chunks::*;
+    use analysis::Instance;
+
+    #[test]
+    fn eocd_at_end_of_dump() {
+        // An EOCD ending on the last byte of the dump, with its comment cut off
+        let mut dump = vec![0u8; 0x3ea];
+        dump.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00\x02\x00\x02\x00\x5c\x00\
+                                 \x00\x00\x00\x03\x00\x00\x10\x00");
+        let fs = FragSys::from_bytes(dump, 0x400);
+
+        assert_eq!(fs.find_eocds(), vec![0x3ea]);
+        assert_eq!(fs.find_eocd_groups(), vec![vec![0x3ea]]);
+
+        // And just the magic, ending on the last byte
+        let mut dump = vec![0u8; 0x3fc];
+        dump.extend_from_slice(b"PK\x05\x06");
+        let fs = FragSys::from_bytes(dump, 0x400);
+        assert_eq!(fs.find_eocds(), vec![0x3fc]);
+    }
+
+    #[test]
+    fn lf_with_unexpected_extra_field() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let cd = cd_for(&FragSys::from_bytes(archive, 0x400), 0);
+        let expected = LF::from(&cd);
+
+        // A decoy for a different file, then the real header with an extended timestamp field
+        // we know nothing about and its time two seconds on
+        let mut decoy = expected.clone();
+        decoy.filename = "b.txt".to_owned();
+        decoy.raw_filename = None;
+        decoy.dd.crc32 ^= 1;
+        let mut dump = decoy.unparse();
+        dump.extend_from_slice(b"hello");
+        let real = dump.len();
+        let mut on_disk = expected.clone();
+        on_disk.timestamp += 2;
+        on_disk.raw_datetime = None;
+        on_disk.ef_len = 9;
+        dump.extend(on_disk.unparse());
+        dump.extend_from_slice(b"UT\x05\x00\x01\x00\x2f\x68\x59");
+        dump.extend_from_slice(b"hello");
+        dump.resize(0x400, 0);
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+
+        assert_eq!(fs.lf_at(real).unwrap().extra.len(), 1);
+        assert_eq!(fs.find_lf_fuzzy(&expected), Some((real, 6)));
+        assert_eq!(fs.find_lf(&expected), None);
+        assert_eq!(fs.match_lf(&expected), LfMatchResult::MagicNotFound);
+        fs.set_fuzzy_lf(true);
+        assert_eq!(fs.match_lf(&expected), LfMatchResult::Fuzzy { ptr: real, score: 6 });
+
+        // Right name, wrong data
+        let mut other = expected.clone();
+        other.dd.crc32 ^= 2;
+        other.timestamp += 4;
+        assert_eq!(fs.find_lf_fuzzy(&other), None);
+
+        // Right name and CRC32, wrong method
+        let mut other = expected.clone();
+        other.method = 8;
+        assert_eq!(fs.find_lf_fuzzy(&other), None);
+    }
+
+    #[test]
+    fn lf_fuzzy_needs_known_crcs() {
+        // The same name in two archives, both with their CRC32s in data descriptors
+        let archive = ::testgen::stored_archive(&[("META-INF/MANIFEST.MF", &b"x"[..])],
+                                                1_500_000_000);
+        let mut lf = LF::from(&cd_for(&FragSys::from_bytes(archive, 0x400), 0));
+        lf.gp_flags.insert(DATA_DESCRIPTOR);
+        lf.dd = DD { crc32: 0, z_sz: 0, u_sz: 0 };
+        let mut dump = lf.unparse();
+        dump.resize(0x40, 0);
+        let second = dump.len();
+        dump.extend(lf.unparse());
+        dump.resize(0x80, 0);
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+
+        let mut other = lf.clone();
+        other.timestamp += 2;
+        other.raw_datetime = None;
+        assert_eq!(fs.find_lf_fuzzy(&other), None);
+
+        // With CRC32s to go on it'll match, preferring a header whose page is still in the pool
+        let mut known = lf.clone();
+        known.gp_flags.remove(DATA_DESCRIPTOR);
+        known.dd.crc32 = 0xdead_beef;
+        let mut dump = known.unparse();
+        dump.resize(0x40, 0);
+        dump.extend(known.unparse());
+        dump.resize(0x80, 0);
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let mut other = known.clone();
+        other.timestamp += 2;
+        other.raw_datetime = None;
+        assert_eq!(fs.find_lf_fuzzy(&other), Some((0, 6)));
+        fs.get_pg_for_addr(0).unwrap();
+        assert_eq!(fs.find_lf_fuzzy(&other), Some((second, 6)));
+    }
+
+    #[test]
+    fn init_offset_from_eocd() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        let eocd_ptr = dump.len() - EOCD_FIXED_SZ;
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+
+        assert_eq!(zf.init_offset(), 0x10);
+        assert_eq!(zf.page_count(), zf.pages.len());
+        let layout = zf.layout();
+        assert_eq!(layout.len(), zf.page_count());
+        let eocd_pg = eocd_ptr / 0x40;
+        assert!(layout.iter().any(|&(_, ref range)| {
+            range.as_ref() == Some(&(eocd_pg * 0x40..(eocd_pg + 1) * 0x40))
+        }));
+        assert!(layout.iter().enumerate().all(|(i, &(idx, _))| i == idx));
+    }
+
+    #[test]
+    fn corrupt_eocd_offsets_rejected() {
+        let eocd = |cd_offset: u32, cd_sz: u32| {
+            EOCD {
+                dsk_no: 0,
+                dsk_w_cd: 0,
+                dsk_entries: 1,
+                tot_entries: 1,
+                cd_sz: cd_sz,
+                cd_offset: cd_offset,
+                cmt_len: 0,
+                zip_cmt: String::new(),
+            }.unparse()
+        };
+
+        // A sentinel cd_offset with no Zip64 record to go with it
+        let mut dump = vec![0u8; 0x30];
+        dump.extend(eocd(0xffff_ffff, 0x40));
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        assert!(ZipFile::new(&mut fs, 0x30).is_err());
+
+        // An archive starting in the same page as its own EOCD is fine, if small
+        let mut dump = vec![0u8; 0x30];
+        dump.extend(eocd(0, 0x10));
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zf = ZipFile::new(&mut fs, 0x30).unwrap();
+        assert_eq!(zf.init_offset(), 0x20);
+        assert_eq!(zf.get_pg_idx_for_offs(0x10, 0x40), 0);
+
+        // Gigabytes of archive in a dump of a few bytes
+        let mut dump = vec![0u8; 0x30];
+        dump.extend(eocd(0xffff_ff00, 0x40));
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        match ZipFile::new(&mut fs, 0x30) {
+            Err(ZipDefragError::EocdParse) => {}
+            _ => panic!("expected an EocdParse error"),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_reader_pages() {
+        use std::io::Cursor;
+
+        let fs = FragSys::from_reader(Cursor::new(vec![0u8; 0x1010]), 0x400).unwrap();
+        assert_eq!(fs.data.len(), 0x1010);
+        assert_eq!(fs.pages.len(), 5);
+        assert_eq!(fs.remaining_count(), 5);
+    }
+
+    #[test]
+    fn write_to_sink() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        let eocd_ptr = dump.len() - EOCD_FIXED_SZ;
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+
+        let mut sink = vec![];
+        let written = zf.write_to(&fs.data, 0x40, &mut sink).unwrap();
+        assert_eq!(written, sink.len());
+        assert_eq!(sink.len(), zf.page_count() * 0x40);
+        assert_eq!(sink, zf.render_pages(&fs.data, 0x40));
+    }
+
+    #[test]
+    fn encrypted_cd() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut cd = cd_for(&FragSys::from_bytes(archive, 0x400), 0);
+        assert!(!cd.is_encrypted());
+
+        cd.gp_flags.insert(ENCRYPTED);
+        assert!(cd.is_encrypted());
+        cd.gp_flags = STRONG_ENCRYPTION | MASKED_CD_RECORDS;
+        assert!(cd.is_encrypted());
+        assert!(cd.has_masked_records());
+    }
+
+    #[test]
+    fn display_in_hex() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        let eocd_ptr = dump.len() - EOCD_FIXED_SZ;
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let cd = CDInstance(0x1234, cd_for(&fs, 0));
+        assert_eq!((cd.offset(), &cd.cd().filename[..]), (0x1234, "a.txt"));
+        assert_eq!(cd.to_string(), "CD @ 0x00001234: a.txt (method 0, lf@0x0)");
+
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+        assert_eq!(zf.to_string(),
+                   format!("ZIP @ 0x{:08x}: 0/1 entries, 1/{} pages, starting 0x10 into its first \
+                            page", eocd_ptr, zf.page_count()));
+    }
+
+    #[test]
+    fn spanned_eocd_expects_this_disk() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        let mut fs = FragSys::from_bytes(archive.clone(), 0x400);
+        let eocd_ptr = archive.len() - EOCD_FIXED_SZ;
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+        assert!(!zf.is_spanned());
+        assert_eq!(zf.expected_cd_count(), 2);
+
+        // Only one of the two entries on this disk
+        let mut dump = archive;
+        dump[eocd_ptr + 8] = 1;
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+        assert!(zf.is_spanned());
+        assert_eq!((zf.dsk_entries(), zf.tot_entries()), (1, 2));
+        assert_eq!(zf.expected_cd_count(), 1);
+    }
+
+    #[test]
+    fn cd_magic_in_signature_skipped() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        let mut eocd = ::parser::parse_eocd(&archive[archive.len() - EOCD_FIXED_SZ..]).unwrap().1;
+        let cd_off = eocd.cd_offset as usize;
+        let cd_len = CD_FIXED_SZ + 5;
+
+        // A signature record whose data happens to be a perfectly good CD for some other file,
+        // sat between the two real ones
+        let mut fake = ::parser::parse_cd(&archive[cd_off..]).unwrap().1;
+        fake.filename = "c.txt".to_owned();
+        fake.raw_filename = None;
+        let fake = fake.unparse();
+        let mut sig = CD_SIG_MAGIC.to_vec();
+        sig.extend_from_slice(&u16_to_le(fake.len() as u16));
+        sig.extend(fake);
+
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive[..cd_off + cd_len]);
+        dump.extend_from_slice(&sig);
+        dump.extend_from_slice(&archive[cd_off + cd_len..cd_off + 2 * cd_len]);
+        eocd.cd_sz += sig.len() as u32;
+        let eocd_ptr = dump.len();
+        dump.extend(eocd.unparse());
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+
+        let names = zf.find_cds(&fs.data, 0x400)
+            .into_iter()
+            .map(|instance| instance.header().filename.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn confidence_of_whole_zip() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        let eocd_ptr = dump.len() - EOCD_FIXED_SZ;
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+        let mut zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+        assert!(zf.confidence(&fs.data, 0x400) > 0.99);
+
+        // Without its one page there's nothing to back it up
+        zf.pages[0] = Page::Unassigned;
+        assert!(zf.confidence(&fs.data, 0x400) < 0.01);
+    }
+
+    #[test]
+    fn nested_archive_overlaps() {
+        // A zip stored inside another, then an unrelated one starting on a fresh page (with a
+        // longer name than the inner one's, so they don't share a CD offset)
+        let inner = ::testgen::stored_archive(&[("i.txt", &b"inner"[..])], 1_500_000_000);
+        let outer = ::testgen::stored_archive(&[("inner.zip", &inner[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&outer);
+        let pad = 0x40 - dump.len() % 0x40;
+        dump.extend(repeat(0).take(pad));
+        dump.extend(::testgen::stored_archive(&[("other.txt", &b"other"[..])], 1_500_000_000));
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zips = fs.find_zips();
+        assert_eq!(zips.len(), 3);
+
+        assert!(zips[1].dump_span().start <= zips[0].dump_span().start);
+        assert!(zips[0].dump_span().end <= zips[1].dump_span().end);
+        assert_eq!(detect_overlaps(&zips, 0x40), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn walk_past_magic_in_filename() {
+        let files = [("PK\u{1}\u{2}.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        let eocd_ptr = dump.len() - EOCD_FIXED_SZ;
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+
+        let walked = zf.walk_central_directory(&fs.data, 0x400);
+        let names = walked
+            .iter()
+            .map(|instance| &instance.header().filename[..])
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["PK\u{1}\u{2}.txt", "b.txt"]);
+        assert_eq!(walked[0].ptr(), zf.adj_offs(zf.cd_offset() as usize));
+    }
+
+    #[test]
+    fn remaining_pages_shrink() {
+        let mut fs = FragSys::from_bytes(vec![0u8; 0x1000], 0x400);
+        assert_eq!(fs.remaining_count(), 4);
+        assert_eq!(fs.remaining_pages().len(), 4);
+
+        assert!(fs.get_pg_for_addr(0x410).is_ok());
+        assert_eq!(fs.remaining_count(), 3);
+        let mut ranges = fs.remaining_ranges().cloned().collect::<Vec<_>>();
+        ranges.sort_by_key(|range| range.start);
+        assert_eq!(ranges, vec![0..0x400, 0x800..0xc00, 0xc00..0x1000]);
+
+        assert!(fs.get_pg_for_addr(0x410).is_err());
+        assert_eq!(fs.remaining_count(), 3);
+    }
+
+    #[test]
+    fn junk_eocds_rejected() {
+        let mut dump = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let real = find_bytes(&dump, b"PK\x05\x06")[0];
+        let (_, eocd) = ::parser::parse_eocd(&dump[real..]).unwrap();
+        // Right behind the real one, reckoning the central directory runs on through it
+        let cd_sz = eocd.cd_sz + EOCD_FIXED_SZ as u32;
+        dump.extend(EOCD { cd_sz: cd_sz, ..eocd.clone() }.unparse());
+        dump.resize(0x400, 0);
+
+        // Way past the end of the dump
+        dump.extend(EOCD { cd_offset: 0x7000_0000, ..eocd.clone() }.unparse());
+        dump.resize(0x800 - EOCD_FIXED_SZ, 0);
+        // With a comment running off the end of the dump
+        let mut cut_off = EOCD { cd_offset: 0, cd_sz: 0, ..eocd.clone() }.unparse();
+        let len = cut_off.len();
+        cut_off[len - 2..].copy_from_slice(b"\xff\xff");
+        dump.extend(cut_off);
+
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+        assert_eq!(fs.find_eocds().len(), 4);
+        let zips = fs.find_zips();
+        assert_eq!(zips.len(), 1);
+        assert_eq!(zips[0].eocd_ptr(), real);
+    }
+
+    #[test]
+    fn shared_cd_eocds_grouped() {
+        // An old generation's EOCD left behind on its own, pointing at the CD the new one has
+        let mut dump = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let real = dump.len() - EOCD_FIXED_SZ;
+        let eocd = dump[real..].to_vec();
+        dump.resize(0x400, 0);
+        dump.extend(eocd);
+        let fs = FragSys::from_bytes(dump, 0x400);
+        assert_eq!(fs.find_eocd_groups(), vec![vec![real, 0x400]]);
+
+        // Zip64 archives laid out alike have identical sentinels, but each has its own CD
+        let mut dump = ::testgen::zip64_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let first = dump.len() - EOCD_FIXED_SZ;
+        dump.resize(0x400, 0);
+        dump.extend(::testgen::zip64_archive(&[("a.txt", &b"world"[..])], 1_500_000_000));
+        let second = dump.len() - EOCD_FIXED_SZ;
+        let fs = FragSys::from_bytes(dump, 0x400);
+        assert_eq!(fs.find_eocd_groups(), vec![vec![first], vec![second]]);
+    }
+
+    #[test]
+    fn same_layout_archives_kept() {
+        // Same names and sizes, so the same cd_offset and cd_sz, but two archives all the same
+        let builds: [fn(&[(&str, &[u8])], u32) -> Vec<u8>; 2] =
+            [::testgen::stored_archive, ::testgen::zip64_archive];
+        for build in &builds {
+            let mut dump = build(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+            dump.resize(0x400, 0);
+            dump.extend(build(&[("a.txt", &b"world"[..])], 1_500_000_000));
+            let mut fs = FragSys::from_bytes(dump, 0x400);
+            let zips = fs.find_zips();
+            assert_eq!(zips.len(), 2);
+            assert!(zips[0].eocd_ptr() < 0x400 && zips[1].eocd_ptr() > 0x400);
+            assert_eq!(zips[0].cd_offset(), zips[1].cd_offset());
+            assert_eq!(zips[0].cd_sz(), zips[1].cd_sz());
+        }
+    }
+
+    #[test]
+    fn fuzzy_eocd_acceptance() {
+        // A two entry archive's EOCD with a flipped bit in its magic
+        let mut dump = vec![0u8; 0x3ea];
+        dump.extend_from_slice(b"PK\x05\x07\x00\x00\x00\x00\x02\x00\x02\x00\x5c\x00\
+                                 \x00\x00\x00\x03\x00\x00\x00\x00");
+        // And a near miss in the padding which doesn't look like an EOCD at all
+        dump[0x100..0x104].copy_from_slice(b"PK\x04\x06");
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+        assert!(fs.find_eocds().is_empty());
+
+        fs.set_fuzzy_eocd(Some(FuzzyEocd::default()));
+        assert_eq!(fs.find_eocds(), vec![0x3ea]);
+        assert_eq!(fs.find_eocd_groups(), vec![vec![0x3ea]]);
+
+        // Too few bits of slack
+        fs.set_fuzzy_eocd(Some(FuzzyEocd { max_bit_errors: 0, ..FuzzyEocd::default() }));
+        assert!(fs.find_eocds().is_empty());
+
+        // Or too strict about the entry count
+        fs.set_fuzzy_eocd(Some(FuzzyEocd { max_entries: 1, ..FuzzyEocd::default() }));
+        assert!(fs.find_eocds().is_empty());
+
+        // Plausible fields don't excuse a central directory past the end of the dump, which is
+        // down to the checks every EOCD gets
+        let mut dump = fs.data.to_vec();
+        dump[0x3fa..0x3fe].copy_from_slice(&[0x00, 0x00, 0x01, 0x00]);
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+        fs.set_fuzzy_eocd(Some(FuzzyEocd::default()));
+        assert!(fs.find_eocds().is_empty());
+    }
+
+    #[test]
+    fn lf_instances_and_affinity() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        let fs = FragSys::from_bytes(archive, 0x400);
+        let lfs = fs.find_lf_instances();
+        assert_eq!(lfs.iter().map(|lf| lf.ptr()).collect::<Vec<_>>(), vec![0, 35]);
+        assert_eq!(lfs[1].header().filename, "b.txt");
+        assert!(cd_for(&fs, 0).lf_mismatches(lfs[0].header()).is_empty());
+        assert!(!cd_for(&fs, 1).lf_mismatches(lfs[0].header()).is_empty());
+    }
+
+    #[test]
+    fn cd_straddling_missing_page() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let cd = cd_for(&FragSys::from_bytes(archive, 0x400), 0);
+        let magic = Magics::default().cd;
+
+        // A CD starting near the end of the first page, running on into the second
+        let mut rendered = vec![0u8; 0x30];
+        rendered.extend(cd.unparse());
+        rendered.resize(0x80, 0);
+        assert_eq!(CD::from_data(&rendered, 0x30, &magic, &[]).unwrap(), cd);
+        match CD::from_data(&rendered, 0x30, &magic, &[0x40..0x80]) {
+            Err(ZipDefragError::Incomplete) => {}
+            other => panic!("expected Incomplete, got {:?}", other),
+        }
+        match CD::from_data(&rendered[..0x38], 0x30, &magic, &[]) {
+            Err(ZipDefragError::Incomplete) => {}
+            other => panic!("expected Incomplete, got {:?}", other),
+        }
+        // Not a CD at all, as opposed to not all there
+        match CD::from_data(&rendered, 0x10, &magic, &[]) {
+            Err(ZipDefragError::CdParse { offset }) => assert_eq!(offset, 0x10),
+            other => panic!("expected CdParse, got {:?}", other),
+        }
+
+        // And the same from a ZipFile which only has the first page
+        let eocd = EOCD {
+            dsk_no: 0,
+            dsk_w_cd: 0,
+            dsk_entries: 1,
+            tot_entries: 1,
+            cd_sz: cd.unparse().len() as u32,
+            cd_offset: 0x30,
+            cmt_len: 0,
+            zip_cmt: String::new(),
+        };
+        let zip = bare_zip(eocd, vec![Page::Assigned(0..0x40), Page::Unassigned]);
+        assert!(zip.find_cds(&rendered, 0x40).is_empty());
+        let pages = vec![Page::Assigned(0..0x40), Page::Assigned(0x40..0x80)];
+        let zip = bare_zip(zip.eocd.clone(), pages);
+        assert_eq!(zip.find_cds(&rendered, 0x40).len(), 1);
+    }
+
+    #[test]
+    fn page_span_estimate_with_straddling_eocd() {
+        // The EOCD starts 0x2b into the second page and runs into the third
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let eocd_ptr = fs.find_eocds()[0];
+        assert_eq!(eocd_ptr, 0x6b);
+        let cds = fs.find_cds().iter().map(|cd| cd.header().clone()).collect::<Vec<_>>();
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+
+        // Counted the same way as the EOCD's own layout, so a complete archive agrees with it
+        assert_eq!(zf.estimate_page_span(&cds, 0x40), 2);
+        assert_eq!(zf.page_count(), 3);
+        let zips = ::reconstruct(&mut FragSys::from_bytes(fs.data.to_vec(), 0x40),
+                                 &::options::DefragOptions::default());
+        assert_eq!(zips[0].page_count(), 3);
+    }
+
+    #[test]
+    fn page_span_growth_keeps_eocd_last() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        dump.extend(vec![0u8; 0x200]);
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let eocd_ptr = fs.find_eocds()[0];
+        let mut zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+        assert_eq!(zf.page_count(), 3);
+
+        // Two pages' more data than the EOCD leaves room for
+        let mut cd = cd_for(&fs, 0);
+        cd.dd.z_sz += 0x80;
+        zf.entries = vec![cd];
+        zf.recompute_layout(0x40);
+        assert_eq!(zf.page_count(), 5);
+        assert!(zf.pages[3].contains(eocd_ptr));
+        assert!(zf.pages[1..3].iter().all(|page| match *page {
+            Page::Unassigned => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn page_span_growth_bounded_by_dump() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let eocd_ptr = fs.find_eocds()[0];
+        let mut zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+
+        // A bogus Zip64 extra field claiming the largest sizes there are
+        let mut cd = cd_for(&fs, 0);
+        cd.dd.z_sz = u32::max_value();
+        cd.dd.u_sz = u32::max_value();
+        cd.extra = vec![ExtraField { id: EF_ZIP64, len: 16, data: vec![0xff; 16] }];
+        zf.entries = vec![cd];
+        assert_eq!(zf.estimate_page_span(&zf.entries, 0x40), usize::max_value());
+        assert!(!zf.size_budget_consistent(0x40));
+        zf.recompute_layout(0x40);
+        assert_eq!(zf.page_count(), 3);
+    }
+
+    #[test]
+    fn corrupt_size_not_stretched_to_fit() {
+        let mut archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let cd = find_bytes(&archive, b"PK\x01\x02")[0];
+        archive[cd + 20..cd + 24].copy_from_slice(&le(0x400, 4));
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        dump.extend(vec![0u8; 0x800]);
+
+        // Plenty of dump left for the CD's idea of the span, but the EOCD's is what gets checked
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zips = ::reconstruct(&mut fs, &::options::DefragOptions::default());
+        assert!(zips.is_empty());
+    }
+
+    #[test]
+    fn render_padded_exact_length() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut dump = vec![0xaau8; 0x10];
+        dump.extend_from_slice(&archive);
+        dump.extend(vec![0xaau8; 0x40]);
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zips = ::reconstruct(&mut fs, &::options::DefragOptions::default());
+        assert_eq!(zips.len(), 1);
+        assert_eq!(zips[0].init_offset(), 0x10);
+
+        assert_eq!(zips[0].implied_len(), archive.len());
+        assert_eq!(zips[0].render_padded(&fs.data, 0x40, zips[0].implied_len()), archive);
+    }
+
+    #[test]
+    fn entries_in_lf_order() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let mut archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        // Swap the two (equally long) CD records, so the directory lists b.txt first
+        let first = find_bytes(&archive, b"PK\x01\x02")[0];
+        let len = 46 + 5;
+        let records = archive[first..first + 2 * len].to_vec();
+        archive[first..first + len].copy_from_slice(&records[len..]);
+        archive[first + len..first + 2 * len].copy_from_slice(&records[..len]);
+
+        let (fs, zips) = ::testgen::reconstructed(&archive);
+        assert_eq!(zips.len(), 1);
+
+        let entries = zips[0].entries(&fs.data, 0x40);
+        assert_eq!(entries.iter().map(|cd| cd.filename.as_str()).collect::<Vec<_>>(),
+                   vec!["a.txt", "b.txt"]);
+        assert!(entries[0].lf_offset < entries[1].lf_offset);
+        assert_eq!(zips[0].entry_count(&fs.data, 0x40), 2);
+    }
+
+    #[test]
+    fn renamed_lf_matched_by_contents() {
+        // A local header renamed behind the CD's back
+        let mut archive = ::testgen::stored_archive(&[("a.txt", &b"hello world"[..])],
+                                                    1_500_000_000);
+        archive[LF_FIXED_SZ] = b'x';
+        let mut fs = FragSys::from_bytes(archive, 0x400);
+        let cd = cd_for(&fs, 0);
+        assert_eq!(fs.match_lf(&LF::from(&cd)), LfMatchResult::FilenameMismatch(0));
+
+        // Streamed entries all have the same LfKey, so the descriptor has to tell them apart
+        let streamed = |name: &str, contents: &[u8]| {
+            let archive = ::testgen::stored_archive(&[(name, contents)], 1_500_000_000);
+            let mut cd = cd_for(&FragSys::from_bytes(archive, 0x400), 0);
+            cd.gp_flags = DATA_DESCRIPTOR;
+            let mut dump = LF::from(&cd).unparse();
+            dump.extend_from_slice(contents);
+            dump.extend_from_slice(b"PK\x07\x08");
+            dump.extend_from_slice(&cd.dd.unparse());
+            dump.resize(0x400, 0);
+            (cd, dump)
+        };
+        let (cd, _) = streamed("a.txt", b"hello world");
+        let (_, renamed) = streamed("x.txt", b"hello world");
+        let mut fs = FragSys::from_bytes(renamed, 0x400);
+        assert_eq!(fs.match_lf(&LF::from(&cd)), LfMatchResult::FilenameMismatch(0));
+
+        // Its neighbour, stamped the same second, isn't it just for having lost its name
+        let (_, neighbour) = streamed("b.txt", b"other words");
+        let mut fs = FragSys::from_bytes(neighbour, 0x400);
+        assert_eq!(fs.match_lf(&LF::from(&cd)),
+                   LfMatchResult::MagicFoundNoByteMatch { candidates: 1 });
+    }
+
+    #[test]
+    fn data_descriptor_folded_into_lf() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello world"[..])], 1_500_000_000);
+        let mut cd = cd_for(&FragSys::from_bytes(archive, 0x400), 0);
+        cd.gp_flags = DATA_DESCRIPTOR;
+
+        // A streamed entry: zeroed inline sizes, then the data, then the descriptor
+        let mut dump = LF::from(&cd).unparse();
+        dump.extend_from_slice(b"hello world");
+        dump.extend_from_slice(b"PK\x07\x08");
+        dump.extend_from_slice(&cd.dd.unparse());
+        dump.resize(0x400, 0);
+        let fs = FragSys::from_bytes(dump, 0x400);
+
+        assert_eq!(fs.find_data_descriptors(), vec![46]);
+        let lf = fs.lf_at(0).unwrap();
+        assert_eq!(lf.dd.z_sz, 0);
+        assert_eq!(fs.match_data_descriptor(0, &lf, 0x400), Some((46, DD64::from(cd.dd))));
+
+        let lfs = fs.find_lf_instances();
+        assert_eq!(lfs[0].header().dd, cd.dd);
+        assert!(cd.lf_mismatches(lfs[0].header()).is_empty());
+
+        // Nothing to match for an unflagged header, or beyond the limit
+        let mut unflagged = lf.clone();
+        unflagged.gp_flags = ZipFlags::empty();
+        assert_eq!(fs.match_data_descriptor(0, &unflagged, 0x400), None);
+        assert_eq!(fs.match_data_descriptor(0, &lf, 46), None);
+
+        // A Zip64 entry's descriptor has 8 byte sizes
+        let mut cd64 = cd.clone();
+        cd64.v_needed = 45;
+        let mut dump = LF::from(&cd64).unparse();
+        dump.extend_from_slice(b"hello world");
+        dump.extend_from_slice(b"PK\x07\x08");
+        dump.extend(le(u64::from(cd.dd.crc32), 4));
+        dump.extend(le(11, 8));
+        dump.extend(le(11, 8));
+        dump.resize(0x400, 0);
+        let fs = FragSys::from_bytes(dump, 0x400);
+        let lf = fs.lf_at(0).unwrap();
+        assert_eq!(fs.match_data_descriptor(0, &lf, 0x400),
+                   Some((46, DD64 { crc32: cd.dd.crc32, z_sz: 11, u_sz: 11 })));
+        assert_eq!(fs.find_lf_instances()[0].header().dd, cd.dd);
+    }
+
+    #[test]
+    fn seeded_kmeans_repeatable() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..]), ("c.txt", &b"!"[..])];
+        let mut dump = vec![];
+        for &timestamp in &[1_000_000_000, 1_000_100_000, 1_000_200_000, 1_000_300_000] {
+            dump.extend(::testgen::stored_archive(&files, timestamp));
+        }
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+        let cds = fs.find_cds();
+        let partition = |params: &ClusterParams| {
+            CDInstance::cluster_with(&cds, 3, params)
+                .unwrap()
+                .0
+                .iter()
+                .map(|cluster| cluster.iter().map(|cd| cd.ptr()).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        let seeded = ClusterParams { seed: Some(42), ..ClusterParams::default() };
+        assert_eq!(partition(&seeded), partition(&seeded));
+        assert_eq!(partition(&ClusterParams::default()), partition(&ClusterParams::default()));
+        assert_eq!(partition(&seeded).iter().map(|c| c.len()).sum::<usize>(), 12);
+    }
+
+    #[test]
+    fn normalised_clustering_sees_method() {
+        // Two entries differing only in method (and the version needed to go with it), alongside
+        // more stored ones at the same time and deflated ones trickling out over the next minute
+        // and a half, which is all an unnormalised kmeans can see
+        let files = (0..7).map(|i| (format!("{}.txt", i), vec![b'x'; i + 1])).collect::<Vec<_>>();
+        let files = files.iter().map(|&(ref name, ref data)| (name.as_str(), &data[..]))
+            .collect::<Vec<_>>();
+        let archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        let fs = FragSys::from_bytes(archive, 0x400);
+        let mut lfs = fs.find_lf_instances();
+        assert_eq!(lfs.len(), 7);
+        for (i, lf) in lfs.iter_mut().enumerate() {
+            let deflated = i % 2 == 1;
+            lf.1.method = if deflated { 8 } else { 0 };
+            lf.1.v_needed = if deflated { 20 } else { 10 };
+        }
+        lfs[3].1.timestamp += 60;
+        lfs[5].1.timestamp += 90;
+        let (x, y) = (lfs[0].ptr(), lfs[1].ptr());
+
+        let together = |params: &ClusterParams| {
+            LFInstance::cluster_with(&lfs, 2, params)
+                .unwrap()
+                .0
+                .iter()
+                .any(|cluster| {
+                    cluster.iter().any(|lf| lf.ptr() == x) && cluster.iter().any(|lf| lf.ptr() == y)
+                })
+        };
+        assert!(!together(&ClusterParams::default()));
+        assert!(together(&ClusterParams { normalise: false, ..ClusterParams::default() }));
+    }
+
+    #[test]
+    fn dbscan_finds_timestamp_groups() {
+        // Three archives of three entries years apart, plus a loner
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..]), ("c.txt", &b"!"[..])];
+        let mut dump = vec![];
+        for &timestamp in &[1_000_000_000, 1_200_000_000, 1_400_000_000] {
+            dump.extend(::testgen::stored_archive(&files, timestamp));
+        }
+        dump.extend(::testgen::stored_archive(&files[..1], 1_600_000_000));
+        let fs = FragSys::from_bytes(dump, 0x400);
+        let lfs = fs.find_lf_instances();
+        assert_eq!(lfs.len(), 10);
+
+        let (clusters, noise) = ::analysis::cluster_dbscan(&lfs, 3600.0, 2).unwrap();
+        assert_eq!(clusters.len(), 3);
+        for (cluster, &timestamp) in clusters.iter()
+            .zip(&[1_000_000_000, 1_200_000_000, 1_400_000_000])
+        {
+            assert_eq!(cluster.len(), 3);
+            assert!(cluster.iter().all(|lf| lf.header().timestamp == timestamp));
+        }
+        assert_eq!(noise.len(), 1);
+        assert_eq!(noise[0].header().timestamp, 1_600_000_000);
+    }
+
+    /// A `ZipFile` over `pages` with nothing else worked out yet
+    fn bare_zip(eocd: EOCD, pages: Vec<Page>) -> ZipFile {
+        ZipFile {
+            init_offs: 0,
+            prefix_len: 0,
+            eocd: eocd,
+            pages: pages,
+            synthesized: vec![],
+            entries: vec![],
+            eocd_ptr: 0,
+            cd_start_pg: 0,
+            entry_pages: vec![],
+            magics: Magics::default(),
+            cd_sig_len: 0,
+            eocd64: None,
+            eocd64_len: 0,
+            locator: None,
+            page_origin: 0,
+            dump_pages: usize::max_value(),
+        }
+    }
+
+    /// `value` as `n` little endian bytes
+    fn le(value: u64, n: usize) -> Vec<u8> {
+        (0..n).map(|i| (value >> (8 * i)) as u8).collect()
+    }
+
+    /// The straightforward scan `scan_bytes` replaced, to check it against
+    fn naive_scan(data: &[u8], pattern: &[u8], step: usize) -> Vec<usize> {
+        let mut findings = vec![];
+        let mut i = 0;
+        while !pattern.is_empty() && i + pattern.len() <= data.len() {
+            if &data[i..i + pattern.len()] == pattern {
+                findings.push(i);
+                i += step;
+            } else {
+                i += 1;
+            }
+        }
+        findings
+    }
+
+    #[test]
+    fn scan_matches_naive() {
+        // Lots of 'P's which aren't magics, some which nearly are, and overlapping patterns
+        let mut data = vec![];
+        for i in 0..0x4000u32 {
+            match i % 97 {
+                0 => data.extend_from_slice(b"PK\x05\x06"),
+                1 | 2 | 3 => data.extend_from_slice(b"PK"),
+                _ => data.push(if i % 3 == 0 { b'P' } else { (i * 31) as u8 }),
+            }
+        }
+        data.extend_from_slice(b"PPPPK\x05\x06");
+        for pattern in &[&b"PK\x05\x06"[..], &b"PP"[..], &b"P"[..], &b"PK\x01\x02"[..]] {
+            assert_eq!(scan_bytes(&data, pattern, 1), naive_scan(&data, pattern, 1));
+            assert_eq!(scan_bytes(&data, pattern, pattern.len()),
+                       naive_scan(&data, pattern, pattern.len()));
+        }
+        assert_eq!(scan_bytes(&b"PPP"[..], b"PP", 1), vec![0, 1]);
+        assert_eq!(find_bytes(&b"PPP"[..], b"PP"), vec![0]);
+        assert!(scan_bytes(&b"PK"[..], b"PK\x05\x06", 1).is_empty());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_scan_matches_sequential() {
+        let magic = b"PK\x05\x06";
+        let mut data = vec![0u8; 0x400];
+        // At the very start, ending on and starting on a boundary, straddling boundaries at every
+        // offset, and running right up to the end
+        for &ptr in &[0, 0xfc, 0x100, 0x1fd, 0x2fe, 0x3fc] {
+            data[ptr..ptr + 4].copy_from_slice(magic);
+        }
+        for &chunk_sz in &[1, 3, 4, 0x100, 0x3ff, 0x400, 0x1000] {
+            assert_eq!(scan_bytes_parallel(&data, magic, chunk_sz), scan_bytes(&data, magic, 1),
+                       "chunk size {}", chunk_sz);
+        }
+        let ps = vec![b'P'; 0x101];
+        assert_eq!(scan_bytes_parallel(&ps, b"PP", 0x10), scan_bytes(&ps, b"PP", 1));
+    }
+
+    #[test]
+    fn render_unassigned_at_page_size() {
+        let data = vec![0xaa; 0x1000];
+        let empty = ::testgen::stored_archive(&[], 0);
+        let pages = vec![Page::Assigned(0..0x800), Page::Unassigned, Page::Assigned(0x800..0x1000)];
+        let zf = bare_zip(::parser::parse_eocd(&empty).unwrap().1, pages);
+
+        let rendered = zf.render_pages(&data, 0x800);
+        assert_eq!(rendered.len(), 0x1800);
+        assert!(rendered[0x800..0x1000].iter().all(|&b| b == 0));
+        assert_eq!(rendered[0x1000], 0xaa);
+    }
+
+    #[test]
+    fn zip64_eocd_offsets() {
+        // 16 bytes of something else, then 0x30 bytes of entries and a 0x40 byte CD, followed by
+        // the Zip64 record and locator and an EOCD of nothing but sentinels
+        let mut dump = vec![0u8; 0x10 + 0x70];
+        dump.extend_from_slice(b"PK\x06\x06");
+        for &(value, n) in &[(44, 8), (45, 2), (45, 2), (0, 4), (0, 4), (3, 8), (3, 8), (0x40, 8),
+                             (0x30, 8)] {
+            dump.extend(le(value, n));
+        }
+        dump.extend_from_slice(b"PK\x06\x07");
+        dump.extend(le(0, 4));
+        dump.extend(le(0x70, 8));
+        dump.extend(le(1, 4));
+        let eocd_ptr = dump.len();
+        dump.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00\xff\xff\xff\xff\xff\xff\xff\xff\
+                                 \xff\xff\xff\xff\x00\x00");
+        assert_eq!(eocd_ptr, 0xcc);
+
+        let mut fs = FragSys::from_bytes(dump.clone(), 0x40);
+        let zf = ZipFile::new(&mut fs, eocd_ptr).unwrap();
+        assert_eq!(zf.eocd64().map(|eocd64| eocd64.tot_entries), Some(3));
+        assert_eq!((zf.cd_offset(), zf.cd_sz(), zf.tot_entries()), (0x30, 0x40, 3));
+        assert_eq!(zf.locator().map(|locator| locator.eocd64_offset), Some(0x70));
+        assert_eq!(zf.init_offs, 0x10);
+        assert_eq!(zf.pages.len(), 5);
+
+        // A locator with no room ahead of it for a record
+        let mut early = vec![0u8; 0xc];
+        early.extend_from_slice(b"PK\x06\x07");
+        early.extend(le(0, 4));
+        early.extend(le(0, 8));
+        early.extend(le(1, 4));
+        early.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                                  \x00\x00\x00\x00\x00\x00");
+        early.resize(0x40, 0);
+        let mut early_fs = FragSys::from_bytes(early, 0x40);
+        assert!(ZipFile::new(&mut early_fs, 0x20).is_ok());
+
+        // Without the Zip64 record there's nothing to go on
+        dump[0x80] = 0;
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        assert!(ZipFile::new(&mut fs, eocd_ptr).is_err());
+    }
+
+    #[test]
+    fn page_entropy() {
+        // A page of zeroes, then one of xorshift noise
+        let mut dump = vec![0u8; 0x400];
+        let mut rng = ::analysis::XorShift::new(0x2545_f491_4f6c_dd1d);
+        for _ in 0..0x400 {
+            dump.push(rng.next() as u8);
+        }
+        let fs = FragSys::from_bytes(dump, 0x400);
+
+        assert!(fs.pages[0].entropy(&fs.data) < 0.01);
+        assert!(fs.pages[1].entropy(&fs.data) > 7.5);
+        assert_eq!(Page::Unassigned.entropy(&fs.data), 0.0);
+        assert_eq!(fs.high_entropy_pages(7.0), vec![1]);
+    }
+
+    #[test]
+    fn export_remap_rows() {
+        let empty = ::testgen::stored_archive(&[], 0);
+        let mut zf = bare_zip(::parser::parse_eocd(&empty).unwrap().1,
+                              vec![Page::Assigned(0x800..0xc00), Page::Unassigned,
+                                   Page::Assigned(0x400..0x800)]);
+        let mut out = vec![];
+        zf.export_remap(0x400, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "logical_page,source_offset\n0,0x800\n1,HOLE\n2,0x400\n");
+
+        // Coalescing mustn't change the answer
+        zf.pages = vec![Page::Assigned(0x0..0x800), Page::Synthesized(vec![0; 0x400])];
+        let mut out = vec![];
+        zf.export_remap(0x400, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "logical_page,source_offset\n0,0x0\n1,0x400\n2,HOLE\n");
+    }
+
+    #[test]
+    fn ambiguous_page_lookup() {
+        let mut fs = FragSys::from_bytes(vec![0; 0x800], 0x400);
+        fs.pages.push(Page::Assigned(0x200..0x600));
+
+        assert_eq!(fs.get_pg_for_addr(0x300).unwrap_err(), PageLookupError::Ambiguous(vec![0, 2]));
+        assert_eq!(fs.get_pg_for_addr(0x1000).unwrap_err(), PageLookupError::NotFound);
+        assert_eq!(fs.pages.len(), 3);
+
+        match fs.get_pg_for_addr(0x700) {
+            Ok(Page::Assigned(range)) => assert_eq!(range, 0x400..0x800),
+            other => panic!("got {:?}", other),
+        }
+        // With that gone, 0x500 is only in the overlapping page
+        match fs.get_pg_for_addr(0x500) {
+            Ok(Page::Assigned(range)) => assert_eq!(range, 0x200..0x600),
+            other => panic!("got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn paged_trait_object() {
+        let empty = ::testgen::stored_archive(&[], 0);
+        let eocd = ::parser::parse_eocd(&empty).unwrap().1;
+        let mut zf = bare_zip(eocd, vec![Page::Unassigned, Page::Assigned(0..0x400)]);
+
+        {
+            let paged: &mut Paged = &mut zf;
+            assert!(paged.assign_page(0, Page::Assigned(0x400..0x800)).is_none());
+            match paged.assign_page(1, Page::Unassigned) {
+                Some(Page::Assigned(range)) => assert_eq!(range, 0..0x400),
+                other => panic!("displaced {:?}", other),
+            }
+            assert!(paged.assign_pages(1, vec![Page::Assigned(0..0x400)]).is_empty());
+            assert_eq!(paged.pages().len(), 2);
+        }
+        assert_eq!(zf.coverage().missing, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn coverage_lists_gaps() {
+        let empty = ::testgen::stored_archive(&[], 0);
+        let eocd = ::parser::parse_eocd(&empty).unwrap().1;
+        let mut pages = (0..12).map(|pg| Page::Assigned(pg * 0x400..(pg + 1) * 0x400))
+            .collect::<Vec<_>>();
+        for &idx in &[1, 2, 5, 6, 7, 8, 9, 10, 11] {
+            pages[idx] = Page::Unassigned;
+        }
+        pages[4] = Page::Synthesized(vec![0; 0x400]);
+        let coverage = bare_zip(eocd, pages).coverage();
+
+        assert_eq!(coverage.total_pages, 12);
+        assert_eq!(coverage.assigned_pages, 2);
+        assert_eq!(coverage.missing, vec![1, 2, 5, 6, 7, 8, 9, 10, 11]);
+        assert_eq!(coverage.to_string(), "2/12 pages (9 missing: [1, 2, 5, 6, 7, 8, 9, 10, ...])");
+        let eocd = ::parser::parse_eocd(&empty).unwrap().1;
+        assert_eq!(bare_zip(eocd, vec![Page::Assigned(0..0x400)]).coverage().to_string(),
+                   "1/1 pages");
+    }
+
+    #[test]
+    fn infer_z_sz_from_next_lf() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        let eocd = ::parser::parse_eocd(&archive[archive.len() - EOCD_FIXED_SZ..]).unwrap().1;
+        let fs = FragSys::from_bytes(archive, 0x400);
+        let mut cds = vec![cd_for(&fs, 0), cd_for(&fs, 1)];
+        let zf = bare_zip(eocd, vec![]);
+
+        // Declared sizes are taken as read
+        assert_eq!(zf.infer_z_sz(&cds[0], &cds), Some(5));
+
+        // Sentinels are measured up to the next local header, or the CD for the last entry
+        cds[0].dd.z_sz = u32::max_value();
+        cds[1].dd.z_sz = u32::max_value();
+        assert_eq!(zf.infer_z_sz(&cds[0], &cds), Some(5));
+        assert_eq!(zf.infer_z_sz(&cds[1], &cds), Some(5));
+
+        // There's no room for a data descriptor in there as well
+        cds[0].dd.z_sz = 0;
+        cds[0].gp_flags = DATA_DESCRIPTOR;
+        assert_eq!(zf.infer_z_sz(&cds[0], &cds), None);
+    }
+
+    #[test]
+    fn zip64_effective_sizes() {
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let fs = FragSys::from_bytes(archive, 0x400);
+        let mut cd = cd_for(&fs, 0);
+        assert_eq!(cd.effective_sizes(), (5, 5, Some(0)));
+
+        // Both sizes in the extra field, uncompressed first, and the offset left alone
+        cd.dd.z_sz = u32::max_value();
+        cd.dd.u_sz = u32::max_value();
+        let mut data = le(0x1_2345_6789, 8);
+        data.extend(le(0x1_0000_0005, 8));
+        cd.extra = vec![ExtraField { id: EF_ZIP64, len: 16, data: data }];
+        assert_eq!(cd.effective_sizes(), (0x1_0000_0005, 0x1_2345_6789, Some(0)));
+        assert!(cd.is_zip64());
+
+        // Nothing left over for a sentinel offset
+        cd.lf_offset = u32::max_value();
+        assert_eq!(cd.effective_sizes().2, None);
+
+        // The page-gap arithmetic goes by the real size
+        let eocd = ::parser::parse_eocd(&fs.data[fs.data.len() - EOCD_FIXED_SZ..]).unwrap().1;
+        cd.lf_offset = 0;
+        assert_eq!(bare_zip(eocd, vec![]).infer_z_sz(&cd, &[]), Some(0x1_0000_0005));
+    }
+
+    /// Parse the `n`th central directory entry of the dump
+    fn cd_for(fs: &FragSys, n: usize) -> CD {
+        let ptr = find_bytes(&fs.data, &fs.magics.cd)[n];
+        CD::from_data(&fs.data, ptr, &fs.magics.cd, &[]).unwrap()
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn mapped_matches_owned() {
+        use std::io::Write;
+
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+        let path = ::std::env::temp_dir().join("zipdefrag-mapped-matches-owned.bin");
+        File::create(&path).unwrap().write_all(&dump).unwrap();
+
+        let mapped = FragSys::from_mmap(&path, 0x40).unwrap();
+        let owned = FragSys::from_bytes(dump.clone(), 0x40);
+        assert_eq!(&mapped.data[..], &dump[..]);
+        assert_eq!(mapped.pages.len(), owned.pages.len());
+        assert_eq!(mapped.find_eocd_groups(), owned.find_eocd_groups());
+        assert_eq!(mapped.find_lfs(), owned.find_lfs());
+        drop(mapped);
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn magics_near_end_of_dump() {
+        // A truncated CD and LF header in the last few bytes of a dump which isn't a whole number
+        // of pages: we should find the magics, fail to parse them and carry on regardless.
+        let archive = ::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        let lf = ::parser::parse_lf(&archive).unwrap().1;
+        let mut dump = vec![0u8; 0x3f8];
+        dump.extend_from_slice(b"PK\x01\x02\x14\x00\x14\x00");
+        dump.extend_from_slice(&archive[..20]);
+        let mut fs = FragSys::from_bytes(dump, 0x400);
+
+        assert!(fs.find_cds().is_empty());
+        assert_eq!(fs.find_lfs(), vec![0x400]);
+        assert_eq!(fs.lf_at(0x400), None);
+        assert_eq!(fs.lf_at(0x500), None);
+        assert_eq!(fs.find_lf(&lf), None);
+
+        // The last page runs off the end of the dump, and renders zero-padded
+        let pages = fs.pages.clone();
+        assert_eq!(pages.len(), 2);
+        let empty = ::testgen::stored_archive(&[], 0);
+        let mut zf = bare_zip(::parser::parse_eocd(&empty).unwrap().1, pages);
+        let rendered = zf.render_pages(&fs.data, 0x400);
+        assert_eq!(rendered.len(), 0x800);
+        assert_eq!(&rendered[0x400..0x414], &archive[..20]);
+        assert!(zf.refresh_entries(&fs.data, 0x400).is_empty());
     }
-    findings
 }
@@ -0,0 +1,61 @@
+//! IBM Code Page 437, the character set zip filenames are in unless the UTF-8 flag says otherwise.
+//!
+//! Plenty of tools write UTF-8 without setting the flag anyway, but since every byte is valid
+//! CP437 decoding as such at least never fails, which is what matters for getting the rest of the
+//! header parsed.
+
+/// The upper half of CP437; the lower half is taken as plain ASCII, as everyone does in practice.
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Decode CP437 `bytes`
+pub fn decode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| if byte < 0x80 {
+            byte as char
+        } else {
+            HIGH[byte as usize - 0x80]
+        })
+        .collect()
+}
+
+/// Encode `text` as CP437, with `?` standing in for anything it can't represent
+pub fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| if (c as u32) < 0x80 {
+            c as u8
+        } else {
+            HIGH.iter()
+                .position(|&high| high == c)
+                .map(|i| (i + 0x80) as u8)
+                .unwrap_or(b'?')
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cp437::*;
+
+    #[test]
+    fn decode_high_half() {
+        assert_eq!(decode(b"\x81ber.txt"), "über.txt");
+        assert_eq!(decode(b"caf\x82/\xe1"), "café/ß");
+    }
+
+    #[test]
+    fn encode_roundtrip() {
+        let raw: Vec<u8> = (0..256).map(|byte| byte as u8).collect();
+        assert_eq!(encode(&decode(&raw)), raw);
+        assert_eq!(encode("日本"), b"??".to_vec());
+    }
+}
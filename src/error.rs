@@ -0,0 +1,81 @@
+//! Errors for the parts of the crate which can tell you more than "something went wrong".
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use analysis::ClusteringError;
+use chunks::CompressionMethod;
+
+#[derive(Debug)]
+/// Something went wrong trying to get data back out of a dump
+pub enum ZipDefragError {
+    /// An I/O error, or something we've not got a more specific variant for yet
+    Io(io::Error),
+    /// There's an EOCD magic, but what follows it doesn't parse as one, or describes an archive
+    /// which couldn't be laid out in the dump
+    EocdParse,
+    /// There's a CD magic at `offset`, but what follows it doesn't parse as one
+    CdParse {
+        /// Where the magic is, in whatever buffer we were parsing
+        offset: usize,
+    },
+    /// Clustering the headers into archives didn't work out
+    ClusteringFailed(ClusteringError),
+    /// A header or record runs into data we haven't got (the end of the buffer, or a page we've
+    /// not placed), so it might well parse once we've found more of the dump
+    Incomplete,
+    /// The entry is compressed with a method we recognise but can't decompress
+    UnsupportedMethod(CompressionMethod),
+    /// The entry is encrypted, so all we can offer is its raw bytes
+    Encrypted,
+}
+
+impl fmt::Display for ZipDefragError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ZipDefragError::Io(ref e) => write!(f, "{}", e),
+            ZipDefragError::EocdParse => write!(f, "failed to parse EOCD"),
+            ZipDefragError::CdParse { offset } => write!(f, "failed to parse CD at {}", offset),
+            ZipDefragError::ClusteringFailed(ref e) => write!(f, "clustering failed: {:?}", e),
+            ZipDefragError::Incomplete => write!(f, "ran into missing data"),
+            ZipDefragError::UnsupportedMethod(method) => {
+                write!(f, "unsupported compression method: {}", method)
+            }
+            ZipDefragError::Encrypted => write!(f, "entry is encrypted"),
+        }
+    }
+}
+
+impl Error for ZipDefragError {
+    fn description(&self) -> &str {
+        match *self {
+            ZipDefragError::Io(ref e) => e.description(),
+            ZipDefragError::EocdParse => "failed to parse EOCD",
+            ZipDefragError::CdParse { .. } => "failed to parse CD",
+            ZipDefragError::ClusteringFailed(_) => "clustering failed",
+            ZipDefragError::Incomplete => "ran into missing data",
+            ZipDefragError::UnsupportedMethod(_) => "unsupported compression method",
+            ZipDefragError::Encrypted => "entry is encrypted",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ZipDefragError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ZipDefragError {
+    fn from(e: io::Error) -> Self {
+        ZipDefragError::Io(e)
+    }
+}
+
+impl From<ClusteringError> for ZipDefragError {
+    fn from(e: ClusteringError) -> Self {
+        ZipDefragError::ClusteringFailed(e)
+    }
+}
@@ -0,0 +1,543 @@
+//! Decompression of entries from reconstructed archives, coping as best we can with the holes left
+//! by pages we couldn't place, plus a quick path for pulling single entries straight out of a dump.
+
+use std::cmp;
+use std::io::prelude::*;
+use std::io::{self, Error, ErrorKind};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
+
+use chunks::{CD, CompressionMethod, DATA_DESCRIPTOR, FragSys, LF_FIXED_SZ, Page, ZipFile};
+use error::ZipDefragError;
+use parser::parse_dd_with;
+
+/// Output a decoder restarted after a hole has to produce before we believe it found its way back
+/// onto a block boundary rather than just decoding noise for a bit.
+const RESYNC_MIN_OUTPUT: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// What we made of an entry on checking it against its CD
+pub enum EntryStatus {
+    /// Decompressed to the right length with the right CRC, so it's almost certainly intact
+    Valid,
+    /// Decompressed to the right length, but the CRC is off
+    CrcMismatch,
+    /// Decompressed, but not to the uncompressed size the CD promised
+    SizeMismatch,
+    /// The data is missing, won't inflate, or uses a method we can't decompress
+    Undecodable,
+    /// The entry is encrypted, so there's no checking it without the key
+    Encrypted,
+}
+
+/// Inflate as much of a raw deflate stream as will go, returning whatever came out before the
+/// decoder gave up.
+fn inflate_partial(input: &[u8]) -> Vec<u8> {
+    let mut decoder = DeflateDecoder::new(input);
+    let mut output = Vec::new();
+    let mut buf = [0u8; 0x1000];
+    loop {
+        match decoder.read(&mut buf) {
+            Ok(0) | Err(_) => return output,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+/// Inflate a whole raw deflate stream, failing if it's cut short or corrupt.
+///
+/// The output isn't preallocated from the uncompressed size the headers give: that comes from
+/// whatever CD or LF we found, and a mangled one can claim 4GiB.
+fn inflate(stream: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    DeflateDecoder::new(stream).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Decompress an entry's compressed `stream`, whole.
+fn decompress(cd: &CD, stream: &[u8]) -> Result<Vec<u8>, ZipDefragError> {
+    match cd.compression_method() {
+        CompressionMethod::Stored => Ok(stream.to_vec()),
+        CompressionMethod::Deflated => Ok(inflate(stream)?),
+        method => Err(ZipDefragError::UnsupportedMethod(method)),
+    }
+}
+
+/// CRC32 and length of an entry's uncompressed data, given its compressed `stream`. Stored
+/// entries are checksummed in place, since there's nothing to decompress.
+fn checksum(cd: &CD, stream: &[u8]) -> Result<(u32, u64), ZipDefragError> {
+    let mut crc = Crc::new();
+    let len = match cd.compression_method() {
+        CompressionMethod::Stored => {
+            crc.update(stream);
+            stream.len()
+        }
+        _ => {
+            let output = decompress(cd, stream)?;
+            crc.update(&output);
+            output.len()
+        }
+    };
+    Ok((crc.sum(), len as u64))
+}
+
+impl ZipFile {
+    /// Byte ranges within `range` of the rendered archive that fall on pages we never found,
+    /// merging adjacent holes.
+    fn holes(&self, range: &Range<usize>, pagesz: usize) -> Vec<Range<usize>> {
+        let mut holes: Vec<Range<usize>> = vec![];
+        if range.start >= range.end {
+            return holes;
+        }
+        for slot in range.start / pagesz..(range.end - 1) / pagesz + 1 {
+            if let Some(&Page::Assigned(_)) = self.pages.get(slot) {
+                continue;
+            }
+            let start = ::std::cmp::max(slot * pagesz, range.start);
+            let end = ::std::cmp::min((slot + 1) * pagesz, range.end);
+            if let Some(last) = holes.last_mut() {
+                if last.end == start {
+                    last.end = end;
+                    continue;
+                }
+            }
+            holes.push(start..end);
+        }
+        holes
+    }
+
+    /// Decompress an entry, skipping over any holes in its compressed data rather than giving up
+    /// at the first one.
+    ///
+    /// A standard inflate dies as soon as it hits a missing page. Instead we inflate up to each
+    /// hole, then restart the decoder at successive byte offsets after it until one produces a
+    /// believable amount of output, which is how we find our way back onto a block boundary
+    /// (stored blocks are always byte aligned, and other blocks are by chance often enough to be
+    /// worth trying). Anything referring back into the lost window is lost with it.
+    ///
+    /// Returns the recovered bytes along with the ranges of the compressed stream which couldn't
+    /// be recovered. Stored entries come back whole with their holes zero-filled; entries using
+    /// any other method come back empty and entirely unrecovered.
+    pub fn inflate_with_resync(&self, cd: &CD, data: &[u8], pagesz: usize)
+        -> (Vec<u8>, Vec<Range<usize>>)
+    {
+        let rendered = self.render_pages(data, pagesz);
+        let range = self.entry_data_range(cd, &rendered);
+        if range.end > rendered.len() {
+            return (Vec::new(), vec![0..range.end - range.start]);
+        }
+        let stream = &rendered[range.clone()];
+        let holes = self.holes(&range, pagesz)
+            .into_iter()
+            .map(|hole| hole.start - range.start..hole.end - range.start)
+            .collect::<Vec<_>>();
+
+        match cd.compression_method() {
+            CompressionMethod::Stored => return (stream.to_vec(), holes),
+            CompressionMethod::Deflated => {}
+            _ => return (Vec::new(), vec![0..stream.len()]),
+        }
+
+        let mut output = Vec::new();
+        let mut lost = Vec::new();
+        let mut seg_start = 0;
+        let mut lost_from = None;
+
+        // Each hole is followed by a segment of stream we have, up to the next one
+        let mut bounds = holes.iter().map(|hole| (hole.start, hole.end)).collect::<Vec<_>>();
+        bounds.push((stream.len(), stream.len()));
+
+        for (hole_start, hole_end) in bounds {
+            let segment = seg_start..hole_start;
+            match lost_from {
+                None => output.extend(inflate_partial(&stream[segment])),
+                Some(lost_start) => {
+                    let resynced = (segment.start..segment.end)
+                        .map(|ptr| (ptr, inflate_partial(&stream[ptr..segment.end])))
+                        .find(|&(_, ref out)| out.len() >= RESYNC_MIN_OUTPUT);
+                    match resynced {
+                        Some((ptr, out)) => {
+                            debug!("Resynchronised {} at {} after hole from {}",
+                                   cd.filename, ptr, lost_start);
+                            lost.push(lost_start..ptr);
+                            output.extend(out);
+                        }
+                        None => lost.push(lost_start..segment.end),
+                    }
+                }
+            }
+            lost_from = Some(hole_start);
+            seg_start = hole_end;
+        }
+
+        (output, lost)
+    }
+
+    /// Decompress an entry whose data we've recovered in full.
+    ///
+    /// Fails with `UnsupportedMethod` (naming the method) for anything that isn't stored or
+    /// deflated, and with an I/O error if any of the entry's data is missing or won't inflate.
+    pub fn extract_entry(&self, cd: &CD, data: &[u8], pagesz: usize)
+        -> Result<Vec<u8>, ZipDefragError>
+    {
+        self.extract_rendered(cd, &self.render_pages(data, pagesz), pagesz)
+    }
+
+    /// Check an entry against its CD by decompressing it from the `rendered` archive, which is the
+    /// hard evidence that we've put its pages in the right order rather than just lined up some
+    /// magics.
+    ///
+    /// Unlike `extract_entry` this doesn't care where the holes are; if a missing page means the
+    /// data doesn't check out then it won't.
+    pub fn verify_entry(&self, cd: &CD, rendered: &[u8]) -> EntryStatus {
+        if cd.is_encrypted() {
+            return EntryStatus::Encrypted;
+        }
+        let range = self.entry_data_range(cd, rendered);
+        if range.start > range.end || range.end > rendered.len() {
+            return EntryStatus::Undecodable;
+        }
+        match checksum(cd, &rendered[range]) {
+            Ok((_, len)) if len != cd.effective_sizes().1 => EntryStatus::SizeMismatch,
+            Ok((crc, _)) if crc != cd.dd.crc32 => EntryStatus::CrcMismatch,
+            Ok(_) => EntryStatus::Valid,
+            Err(_) => EntryStatus::Undecodable,
+        }
+    }
+
+    /// Decompress every (non-directory) cached entry, pairing each name with how it went.
+    pub fn extract_all(&self, data: &[u8], pagesz: usize)
+        -> Vec<(String, Result<Vec<u8>, ZipDefragError>)>
+    {
+        self.extract_all_with_progress(data, pagesz, |_, _| {}, &AtomicBool::new(false))
+    }
+
+    /// As `extract_all`, calling `progress(filename, entries_done)` after each entry and giving
+    /// up between entries once `cancel` is set, in which case whatever was extracted before then
+    /// is returned.
+    pub fn extract_all_with_progress<F>(
+        &self,
+        data: &[u8],
+        pagesz: usize,
+        mut progress: F,
+        cancel: &AtomicBool,
+    ) -> Vec<(String, Result<Vec<u8>, ZipDefragError>)>
+    where
+        F: FnMut(&str, usize),
+    {
+        let rendered = self.render_pages(data, pagesz);
+        let mut results = vec![];
+        for cd in self.cached_entries().iter().filter(|cd| !cd.filename.ends_with('/')) {
+            if cancel.load(Ordering::Relaxed) {
+                info!("Extraction cancelled after {} entries", results.len());
+                break;
+            }
+            results.push((cd.filename.clone(), self.extract_rendered(cd, &rendered, pagesz)));
+            progress(&cd.filename, results.len());
+        }
+        results
+    }
+
+    /// Check the CRC32 of every (non-directory) cached entry against its CD, pairing each name
+    /// with whether it matched, or why we couldn't tell.
+    ///
+    /// Stored entries are checksummed straight off the rendered archive, since there's nothing to
+    /// decompress; everything else has to be extracted first.
+    pub fn verify_crcs(&self, data: &[u8], pagesz: usize)
+        -> Vec<(String, Result<bool, ZipDefragError>)>
+    {
+        let rendered = self.render_pages(data, pagesz);
+        self.cached_entries()
+            .iter()
+            .filter(|cd| !cd.filename.ends_with('/'))
+            .map(|cd| {
+                let crc = self.crc_rendered(cd, &rendered, pagesz);
+                (cd.filename.clone(), crc.map(|crc| crc == cd.dd.crc32))
+            })
+            .collect()
+    }
+
+    /// CRC32 of an entry's uncompressed data in the already `rendered` archive
+    fn crc_rendered(&self, cd: &CD, rendered: &[u8], pagesz: usize)
+        -> Result<u32, ZipDefragError>
+    {
+        checksum(cd, self.complete_stream(cd, rendered, pagesz)?).map(|(crc, _)| crc)
+    }
+
+    /// An entry's compressed data in the already `rendered` archive, so long as we have all of it
+    fn complete_stream<'a>(&self, cd: &CD, rendered: &'a [u8], pagesz: usize)
+        -> Result<&'a [u8], ZipDefragError>
+    {
+        if cd.is_encrypted() {
+            return Err(ZipDefragError::Encrypted);
+        }
+        let range = self.entry_data_range(cd, rendered);
+        if range.start > range.end || range.end > rendered.len() ||
+            !self.holes(&range, pagesz).is_empty()
+        {
+            return Err(ZipDefragError::Io(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("{} has missing pages", cd.filename),
+            )));
+        }
+        Ok(&rendered[range])
+    }
+
+    /// Decompress an entry from the already `rendered` archive
+    fn extract_rendered(&self, cd: &CD, rendered: &[u8], pagesz: usize)
+        -> Result<Vec<u8>, ZipDefragError>
+    {
+        decompress(cd, self.complete_stream(cd, rendered, pagesz)?)
+    }
+}
+
+impl FragSys {
+    /// Length of compressed data starting at `start` in the dump, going by the first data
+    /// descriptor after it which agrees about where it is.
+    fn descriptor_len_after(&self, start: usize) -> Option<usize> {
+        let magic = &self.magics().dd;
+        if start > self.data.len() {
+            return None;
+        }
+        self.data[start..]
+            .windows(magic.len())
+            .enumerate()
+            .filter(|&(_, window)| window == magic.as_slice())
+            .filter_map(|(len, _)| match parse_dd_with(&self.data[start + len..], magic) {
+                ::nom::IResult::Done(_, dd) if dd.z_sz as usize == len => Some(len),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Extract the single entry whose local file header is at `lf_addr`, without reconstructing
+    /// anything, returning its name and contents.
+    ///
+    /// Handy for when another tool has already pointed at the header. The compressed data is
+    /// assumed to follow on contiguously in the dump, its length coming from the header or, for
+    /// streamed entries, the first data descriptor to agree with it. The `pagesz` pages it spans
+    /// are taken out of the pool, since they're spoken for.
+    ///
+    /// Fails with `Incomplete` if the data runs off the end of the dump, and with
+    /// `UnsupportedMethod` for anything that isn't stored or deflated.
+    pub fn extract_entry_at(&mut self, lf_addr: usize, pagesz: usize)
+        -> Result<(String, Vec<u8>), ZipDefragError>
+    {
+        let lf = match self.lf_at(lf_addr) {
+            Some(lf) => lf,
+            None => {
+                return Err(ZipDefragError::Io(Error::new(
+                    ErrorKind::Other,
+                    format!("No local file header at {:#x}", lf_addr),
+                )))
+            }
+        };
+        let start = lf_addr + LF_FIXED_SZ + lf.fn_len as usize + lf.ef_len as usize;
+        let z_sz = if lf.gp_flags.contains(DATA_DESCRIPTOR) {
+            match self.descriptor_len_after(start) {
+                Some(len) => len,
+                None => {
+                    return Err(ZipDefragError::Io(Error::new(
+                        ErrorKind::Other,
+                        format!("No data descriptor found for {}", lf.filename),
+                    )))
+                }
+            }
+        } else {
+            lf.dd.z_sz as usize
+        };
+        let end = start + z_sz;
+        if end > self.data.len() {
+            debug!("{} runs off the end of the dump", lf.filename);
+            return Err(ZipDefragError::Incomplete);
+        }
+
+        // Pages start at the grid's origin rather than the start of the dump, and anything ahead
+        // of it isn't on a page at all
+        let origin = self.start_offset();
+        let mut addr = cmp::max(lf_addr, origin);
+        while addr < end {
+            let _ = self.get_pg_for_addr(addr);
+            addr = origin + ((addr - origin) / pagesz + 1) * pagesz;
+        }
+
+        let stream = &self.data[start..end];
+        let contents = match CompressionMethod::from(lf.method) {
+            CompressionMethod::Stored => stream.to_vec(),
+            CompressionMethod::Deflated => inflate(stream)?,
+            method => return Err(ZipDefragError::UnsupportedMethod(method)),
+        };
+        Ok((lf.filename, contents))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use chunks::{EF_ZIP64, ExtraField, FragSys, Page};
+    use testgen::{deflated_archive, reconstructed, stored_archive};
+    use extract::*;
+
+    #[test]
+    fn stored_crcs_match() {
+        // Starting partway into a page, and spanning a few of them
+        let files = [("a.txt", &b"hello"[..]), ("b/c.txt", &b"stored, not deflated"[..])];
+        let (fs, zips) = reconstructed(&stored_archive(&files, 1_500_000_000));
+        assert_eq!(zips.len(), 1);
+
+        let verified = zips[0].verify_crcs(&fs.data, 0x40);
+        assert_eq!(verified.len(), 2);
+        for (name, result) in verified {
+            assert!(result.unwrap(), "CRC mismatch for {}", name);
+        }
+        assert_eq!(zips[0].extract_entry(&zips[0].cached_entries()[1], &fs.data, 0x40).unwrap(),
+                   b"stored, not deflated".to_vec());
+    }
+
+    #[test]
+    fn verify_deflated_entry() {
+        let contents = b"deflate me, deflate me, deflate me, deflate me, deflate me".to_vec();
+        let files = [("deflated.txt", &contents[..])];
+        let (fs, zips) = reconstructed(&deflated_archive(&files, 1_500_000_000));
+        assert_eq!(zips.len(), 1);
+
+        let zip = &zips[0];
+        let rendered = zip.render_pages(&fs.data, 0x40);
+        let cd = zip.cached_entries()[0].clone();
+        assert_eq!(cd.method, 8);
+        assert_eq!(zip.verify_entry(&cd, &rendered), EntryStatus::Valid);
+
+        let mut wrong_crc = cd.clone();
+        wrong_crc.dd.crc32 ^= 1;
+        assert_eq!(zip.verify_entry(&wrong_crc, &rendered), EntryStatus::CrcMismatch);
+
+        let mut wrong_size = cd.clone();
+        wrong_size.dd.u_sz += 1;
+        assert_eq!(zip.verify_entry(&wrong_size, &rendered), EntryStatus::SizeMismatch);
+
+        let mut wrong_method = cd.clone();
+        wrong_method.method = 12;
+        assert_eq!(zip.verify_entry(&wrong_method, &rendered), EntryStatus::Undecodable);
+
+        // A corrupt size mustn't have us trying to allocate it
+        let mut huge = cd.clone();
+        huge.dd.u_sz = 0xffff_fff0;
+        assert_eq!(zip.verify_entry(&huge, &rendered), EntryStatus::SizeMismatch);
+        assert!(zip.extract_entry(&huge, &fs.data, 0x40).is_ok());
+    }
+
+    #[test]
+    fn verify_zip64_sizes() {
+        let files = [("a.txt", &b"hello"[..])];
+        let (fs, zips) = reconstructed(&stored_archive(&files, 1_500_000_000));
+        let zip = &zips[0];
+        let rendered = zip.render_pages(&fs.data, 0x40);
+
+        // The uncompressed size left to a Zip64 extra field
+        let mut cd = zip.cached_entries()[0].clone();
+        cd.dd.u_sz = u32::max_value();
+        cd.extra = vec![ExtraField { id: EF_ZIP64, len: 8, data: vec![5, 0, 0, 0, 0, 0, 0, 0] }];
+        assert_eq!(zip.verify_entry(&cd, &rendered), EntryStatus::Valid);
+        assert_eq!(zip.crc_rendered(&cd, &rendered, 0x40).unwrap(), cd.dd.crc32);
+    }
+
+    #[test]
+    fn extract_entry_at_offset_grid() {
+        // Pages start 0x20 into the dump, and the entry runs from there to 0xa5, five bytes into
+        // the third page of the grid, which stepping from 0 would have skipped straight past
+        let files = [("a.txt", &[b'x'; 0x62][..]), ("b.txt", &b"world"[..])];
+        let mut dump = vec![0u8; 0x20];
+        dump.extend(stored_archive(&files, 1_500_000_000));
+        let mut fs = FragSys::from_bytes_aligned(dump, 0x40, 0x20);
+        let pages = fs.remaining_pages().len();
+
+        let (name, contents) = fs.extract_entry_at(0x20, 0x40).unwrap();
+        assert_eq!((name.as_str(), contents), ("a.txt", vec![b'x'; 0x62]));
+        assert_eq!(fs.remaining_pages().len(), pages - 3);
+        assert!(fs.remaining_pages().iter().all(|page| !page.contains(0xa4)));
+        assert!(fs.remaining_pages().iter().any(|page| page.contains(0xe0)));
+    }
+
+    #[test]
+    fn extract_entry_at_unsupported_method() {
+        let mut dump = stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000);
+        // Method 12 is bzip2
+        dump[8] = 12;
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        match fs.extract_entry_at(0, 0x40) {
+            Err(ZipDefragError::UnsupportedMethod(method)) => {
+                assert_eq!(method, CompressionMethod::from(12))
+            }
+            _ => panic!("expected UnsupportedMethod"),
+        }
+    }
+
+    #[test]
+    fn extract_all_reports_progress_and_cancels() {
+        let files = [("a.txt", &b"hello"[..]), ("b/", &b""[..]), ("b/c.txt", &b"world"[..]),
+                     ("d.txt", &b"!"[..])];
+        let (fs, zips) = reconstructed(&stored_archive(&files, 1_500_000_000));
+        let zip = &zips[0];
+
+        // Directories are skipped, and don't count towards progress
+        let mut seen = vec![];
+        let results = zip.extract_all_with_progress(&fs.data, 0x40, |name, done| {
+            seen.push((name.to_string(), done))
+        }, &AtomicBool::new(false));
+        assert_eq!(seen, vec![("a.txt".to_string(), 1), ("b/c.txt".to_string(), 2),
+                              ("d.txt".to_string(), 3)]);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[1].0, "b/c.txt");
+        assert_eq!(results[1].1.as_ref().unwrap(), b"world");
+
+        // Cancelling partway through keeps what was extracted up to then
+        let cancel = AtomicBool::new(false);
+        let results = zip.extract_all_with_progress(&fs.data, 0x40, |_, done| {
+            if done == 2 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        }, &cancel);
+        assert_eq!(results.iter().map(|r| r.0.as_str()).collect::<Vec<_>>(),
+                   vec!["a.txt", "b/c.txt"]);
+
+        // Nothing at all if it's cancelled before we start
+        let results = zip.extract_all_with_progress(&fs.data, 0x40, |_, _| {},
+                                                    &AtomicBool::new(true));
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn inflate_resyncs_after_missing_page() {
+        // A deflate stream of stored blocks, whose contents all look like deflate blocks of the
+        // reserved type, so the only places the decoder can pick back up are block boundaries
+        let blocks = (0..8).map(|i| vec![0x06 + 8 * i as u8; 0x40]).collect::<Vec<_>>();
+        let mut stream = vec![];
+        for (i, block) in blocks.iter().enumerate() {
+            stream.push(if i == blocks.len() - 1 { 1 } else { 0 });
+            stream.extend_from_slice(&[0x40, 0x00, 0xbf, 0xff]);
+            stream.extend_from_slice(block);
+        }
+        let archive = stored_archive(&[("a.bin", &stream[..])], 1_500_000_000);
+        let (fs, mut zips) = reconstructed(&archive);
+        let zip = &mut zips[0];
+        let mut cd = zip.cached_entries()[0].clone();
+        cd.method = 8;
+
+        let (output, lost) = zip.inflate_with_resync(&cd, &fs.data, 0x40);
+        assert_eq!(output, blocks.concat());
+        assert!(lost.is_empty());
+
+        // The stream starts at 0x33, so losing the page at 0xc0 takes 0x8d..0xcd of it: the end
+        // of the third block's header and most of its contents. The fourth block starts at 0xcf
+        zip.assign_page(3, Page::Unassigned).unwrap();
+        let (output, lost) = zip.inflate_with_resync(&cd, &fs.data, 0x40);
+        let mut expected = blocks[..2].concat();
+        expected.extend(blocks[3..].concat());
+        assert_eq!(output, expected);
+        assert_eq!(lost, vec![0x8d..0xcf]);
+    }
+}
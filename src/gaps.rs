@@ -0,0 +1,325 @@
+//! Gap-filling passes for placing pages left over in the `FragSys` pool into the `Unassigned`
+//! slots of a `ZipFile`.
+//!
+//! Each candidate page is scored against each gap using the built-in signals we know about, plus
+//! a user-supplied scorer for anyone who knows something about their firmware family that we
+//! don't (e.g. "pages in this archive always start with a known tag"). The best candidate for a
+//! slot is placed only if its combined score is positive, so a scorer with no opinion leaves it
+//! to the built-in signals alone.
+//!
+//! Note that with `neutral_score` `fill_gaps` still places pages: `reconstruct` runs it on every
+//! archive, so any leftover page running contiguously on from an assigned neighbour ends up in
+//! the gap next to it. The built-in signals are that continuity, and entropy, which only ever
+//! counts against a candidate (a page of text is an unlikely fit between two of deflated data,
+//! but matching entropy is far too common to place a page on by itself). Checking candidates
+//! against an entry's CRC32 costs a decompression apiece, so that's left to the separate
+//! `fill_single_page_gap` pass.
+
+use std::cmp::{self, Ordering};
+
+use chunks::{CD, FragSys, Page, ZipFile};
+use extract::EntryStatus;
+
+/// Score awarded for each neighbouring slot a candidate page runs contiguously with in the dump.
+const CONTINUITY_SCORE: f64 = 1.0;
+
+/// Score taken off a candidate page per bit per byte its entropy is away from its neighbours'.
+/// Even the biggest gap there can be (8 bits per byte) only cancels out one side's continuity.
+const ENTROPY_MISMATCH_SCORE: f64 = CONTINUITY_SCORE / 8.0;
+
+/// The default scorer, which has no opinion about anything.
+pub fn neutral_score(_zf: &ZipFile, _slot: usize, _page: &Page, _data: &[u8]) -> f64 {
+    0.0
+}
+
+/// Score a candidate page by whether it picks up where the page before `slot` leaves off in the
+/// dump, and whether it leaves off where the page after `slot` picks up.
+///
+/// File systems tend to write runs of pages contiguously when they can, so a candidate which
+/// stitches two assigned neighbours together is a pretty good bet.
+pub fn continuity_score(zf: &ZipFile, slot: usize, page: &Page) -> f64 {
+    let candidate = match *page {
+        Page::Assigned(ref range) => range,
+        Page::Unassigned | Page::Synthesized(_) => return 0.0,
+    };
+    let mut score = 0.0;
+    if slot > 0 {
+        if let Some(&Page::Assigned(ref prev)) = zf.pages.get(slot - 1) {
+            if prev.end == candidate.start {
+                score += CONTINUITY_SCORE;
+            }
+        }
+    }
+    if let Some(&Page::Assigned(ref next)) = zf.pages.get(slot + 1) {
+        if candidate.end == next.start {
+            score += CONTINUITY_SCORE;
+        }
+    }
+    score
+}
+
+/// Penalise a candidate page by how far its entropy is from whichever assigned neighbour of
+/// `slot` it's closest to, or not at all if neither neighbour is assigned.
+///
+/// Going by the closer of the two allows for a gap where one entry's data gives way to the next,
+/// or to the central directory, with the entropy changing along with it.
+pub fn entropy_score(zf: &ZipFile, slot: usize, page: &Page, data: &[u8]) -> f64 {
+    match *page {
+        Page::Assigned(_) => {}
+        Page::Unassigned | Page::Synthesized(_) => return 0.0,
+    }
+    let candidate = page.entropy(data);
+    let neighbours = [slot.checked_sub(1), Some(slot + 1)];
+    let nearest = neighbours
+        .iter()
+        .filter_map(|&neighbour| neighbour.and_then(|n| zf.pages.get(n)))
+        .filter(|neighbour| match **neighbour {
+            Page::Assigned(_) => true,
+            _ => false,
+        })
+        .map(|neighbour| (neighbour.entropy(data) - candidate).abs())
+        .fold(None, |nearest: Option<f64>, diff| Some(nearest.map_or(diff, |n| n.min(diff))));
+    nearest.map_or(0.0, |diff| -diff * ENTROPY_MISMATCH_SCORE)
+}
+
+/// Combined score from all of the built-in signals for a candidate page in `slot`:
+/// `continuity_score` and `entropy_score`.
+fn builtin_score(zf: &ZipFile, slot: usize, page: &Page, data: &[u8]) -> f64 {
+    continuity_score(zf, slot, page) + entropy_score(zf, slot, page, data)
+}
+
+impl ZipFile {
+    /// Try to fill each `Unassigned` slot with the best-scoring page left in the `FragSys` pool,
+    /// removing placed pages from the pool.
+    ///
+    /// `scorer` is called as `scorer(zip_file, slot, candidate, dump_data)` and its result is
+    /// added to the built-in signals. Pass `gaps::neutral_score` for the built-in behaviour.
+    ///
+    /// Returns the number of slots filled.
+    pub fn fill_gaps<F>(&mut self, fs: &mut FragSys, scorer: F) -> usize
+    where
+        F: Fn(&ZipFile, usize, &Page, &[u8]) -> f64,
+    {
+        let mut filled = 0;
+        for slot in 0..self.pages.len() {
+            match self.pages[slot] {
+                Page::Unassigned => {}
+                _ => continue,
+            }
+
+            let best = {
+                let zf = &*self;
+                let data = &fs.data[..];
+                fs.pages
+                    .iter()
+                    .enumerate()
+                    .map(|(i, page)| {
+                        (i, builtin_score(zf, slot, page, data) + scorer(zf, slot, page, data))
+                    })
+                    .filter(|&(_, score)| score > 0.0)
+                    .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+            };
+
+            if let Some((i, score)) = best {
+                let page = fs.pages.swap_remove(i);
+                debug!("Filling slot {} with {:?} (score {})", slot, page, score);
+                self.pages[slot] = page;
+                filled += 1;
+            }
+        }
+        filled
+    }
+
+    /// Fill a single missing page in `cd`'s data by brute force: try each page left in the
+    /// `FragSys` pool in the gap, and keep the first one the entry decompresses to its CRC32
+    /// with.
+    ///
+    /// Every candidate costs a full decompression, so this only has a go where there's exactly
+    /// one `Unassigned` slot in the entry's data, with assigned pages either side of it. Anything
+    /// with more holes than that would need every combination trying. Returns whether the gap
+    /// was filled. Encrypted entries never check out, so aren't tried at all.
+    pub fn fill_single_page_gap(&mut self, fs: &mut FragSys, cd: &CD) -> bool {
+        if cd.is_encrypted() {
+            return false;
+        }
+        let pagesz = fs.page_sz();
+        let mut rendered = self.render_pages(&fs.data, pagesz);
+        let range = self.entry_data_range(cd, &rendered);
+        if range.start >= range.end || range.end > rendered.len() {
+            return false;
+        }
+
+        let gaps = (range.start / pagesz..(range.end - 1) / pagesz + 1)
+            .filter(|&slot| match self.pages[slot] {
+                Page::Unassigned => true,
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        if gaps.len() != 1 {
+            return false;
+        }
+        let slot = gaps[0];
+        let between_assigned = {
+            let assigned = |slot: usize| match self.pages.get(slot) {
+                Some(&Page::Assigned(_)) => true,
+                _ => false,
+            };
+            slot > 0 && assigned(slot - 1) && assigned(slot + 1)
+        };
+        if !between_assigned {
+            return false;
+        }
+
+        let found = {
+            let zf = &*self;
+            let data = &fs.data[..];
+            fs.pages.iter().position(|page| {
+                let bytes = match *page {
+                    Page::Assigned(ref bytes) => bytes,
+                    Page::Unassigned | Page::Synthesized(_) => return false,
+                };
+                // As in `render_pages`, a page running off the end of the dump is zero filled
+                let end = cmp::min(bytes.end, data.len());
+                let start = cmp::min(bytes.start, end);
+                let len = cmp::min(end - start, pagesz);
+                {
+                    let target = &mut rendered[slot * pagesz..(slot + 1) * pagesz];
+                    target[..len].copy_from_slice(&data[start..start + len]);
+                    for byte in &mut target[len..] {
+                        *byte = 0;
+                    }
+                }
+                zf.verify_entry(cd, &rendered) == EntryStatus::Valid
+            })
+        };
+
+        match found {
+            Some(i) => {
+                let page = fs.pages.swap_remove(i);
+                debug!("Filling slot {} with {:?}, which gives {} the right CRC32", slot, page,
+                       cd.filename);
+                self.pages[slot] = page;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chunks::{FragSys, Page, ZipFile};
+    use extract::EntryStatus;
+    use options::DefragOptions;
+    use reconstruct;
+    use testgen::stored_archive;
+    use gaps::*;
+
+    #[test]
+    fn continuity_places_the_right_page() {
+        let contents = (0..0x100).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+        let mut dump = vec![0u8; 0x10];
+        dump.extend(stored_archive(&[("a.bin", &contents[..])], 1_500_000_000));
+        let archive_pages = dump.len() / 0x40 + 1;
+        dump.resize(archive_pages * 0x40, 0);
+        // Leftovers which don't run on from anything in the archive
+        dump.extend(vec![0xaa; 0x100]);
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let mut zips = reconstruct(&mut fs, &DefragOptions::default());
+        let zip = &mut zips[0];
+
+        // Knock out a page in the middle of the data, and it's the one page in the pool which
+        // runs on from the page before and into the page after
+        let missing = zip.assign_page(2, Page::Unassigned).unwrap();
+        fs.return_page(missing);
+        assert!(zip.fill_gaps(&mut fs, neutral_score) >= 1);
+        match zip.pages[2] {
+            Page::Assigned(ref bytes) => assert_eq!(*bytes, 0x80..0xc0),
+            ref page => panic!("slot 2 left as {:?}", page),
+        }
+
+        // A scorer which knows better can talk the pass out of it
+        let missing = zip.assign_page(2, Page::Unassigned).unwrap();
+        fs.return_page(missing);
+        let distrustful = |_: &ZipFile, _: usize, page: &Page, _: &[u8]| {
+            if page.contains(0x80) { -10.0 } else { 0.0 }
+        };
+        zip.fill_gaps(&mut fs, distrustful);
+        match zip.pages[2] {
+            Page::Unassigned => {}
+            ref page => panic!("slot 2 filled with {:?}", page),
+        }
+    }
+
+    #[test]
+    fn entropy_picks_between_candidates() {
+        let contents = (0..0x100).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+        let mut dump = vec![0u8; 0x10];
+        dump.extend(stored_archive(&[("a.bin", &contents[..])], 1_500_000_000));
+        let archive_pages = dump.len() / 0x40 + 1;
+        dump.resize(archive_pages * 0x40, 0);
+        // Neither runs on from anything, but only one looks anything like the data around it
+        dump.extend(vec![0xaa; 0x40]);
+        let lookalike = dump.len();
+        dump.extend((0..0x40).map(|i| (i * 37 % 251) as u8 ^ 1));
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let mut zips = reconstruct(&mut fs, &DefragOptions::default());
+        let zip = &mut zips[0];
+
+        // Lose a page in the middle of the data altogether
+        zip.assign_page(2, Page::Unassigned).unwrap();
+        let flat = fs.pages.iter().find(|page| page.contains(lookalike - 1)).unwrap().clone();
+        let similar = fs.pages.iter().find(|page| page.contains(lookalike)).unwrap().clone();
+        assert!(entropy_score(zip, 2, &flat, &fs.data) < -0.5);
+        assert!(entropy_score(zip, 2, &similar, &fs.data).abs() < 1e-9);
+
+        // Without continuity to go on, the built-in signals wouldn't place either
+        assert!(builtin_score(zip, 2, &flat, &fs.data) <= 0.0);
+        assert!(builtin_score(zip, 2, &similar, &fs.data) <= 0.0);
+
+        // With a scorer happy to take any page, entropy decides which
+        let indifferent = |_: &ZipFile, _: usize, _: &Page, _: &[u8]| 0.5;
+        assert!(zip.fill_gaps(&mut fs, indifferent) >= 1);
+        match zip.pages[2] {
+            Page::Assigned(ref bytes) => assert_eq!(bytes.start, lookalike),
+            ref page => panic!("slot 2 left as {:?}", page),
+        }
+    }
+
+    #[test]
+    fn single_page_gap_found_by_crc() {
+        let contents = (0..0x100).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+        let mut dump = vec![0u8; 0x10];
+        dump.extend(stored_archive(&[("a.bin", &contents[..])], 1_500_000_000));
+        let archive_pages = dump.len() / 0x40 + 1;
+        dump.resize(archive_pages * 0x40, 0);
+        // Decoys which look much like the real data, but aren't it
+        for pg in 0..4 {
+            dump.extend((0..0x40).map(|i| ((i + pg * 0x40) * 31 % 251) as u8 ^ 1));
+        }
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let mut zips = reconstruct(&mut fs, &DefragOptions::default());
+        assert_eq!(zips.len(), 1);
+        let zip = &mut zips[0];
+        let cd = zip.cached_entries()[0].clone();
+
+        // Knock out a page in the middle of the data and hide it amongst the decoys
+        let missing = zip.assign_page(2, Page::Unassigned).unwrap();
+        let decoy = fs.take_page(0).unwrap();
+        fs.return_page(missing);
+        fs.return_page(decoy);
+        let pool = fs.pages.len();
+
+        assert!(zip.fill_single_page_gap(&mut fs, &cd));
+        assert_eq!(fs.pages.len(), pool - 1);
+        match zip.pages[2] {
+            Page::Assigned(ref bytes) => assert_eq!(*bytes, 0x80..0xc0),
+            ref page => panic!("slot 2 left as {:?}", page),
+        }
+        let rendered = zip.render_pages(&fs.data, 0x40);
+        assert_eq!(zip.verify_entry(&cd, &rendered), EntryStatus::Valid);
+
+        // Nothing left to fill
+        assert!(!zip.fill_single_page_gap(&mut fs, &cd));
+    }
+}
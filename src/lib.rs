@@ -1,6 +1,11 @@
 //! A crate containing a bunch of hacks to model a fragmented firmware dump as a jigsaw puzzle
 //! using header magics as clues to help us piece the pages back together in the correct order in
 //! order to reconstruct and correctly decompress Zip File contained therein
+//!
+//! The modelling, parsing and clustering only ever need the dump as bytes, so `FragSys::from_bytes`
+//! is the way in wherever the bytes come from. Anything touching the filesystem or other I/O
+//! (`from_file`, `from_reader`, `rip_a_zip` and friends) sits behind the default `std` feature, so
+//! turn that off if you're embedding this somewhere without one.
 #![feature(range_contains)]
 #![cfg_attr(feature="clippy", feature(plugin))]
 #![cfg_attr(feature="clippy", plugin(clippy))]
@@ -22,154 +27,742 @@
 extern crate bitflags;
 extern crate chrono;
 extern crate cogset;
+extern crate flate2;
 #[macro_use]
 extern crate log;
+extern crate memchr;
+#[cfg(feature = "std")]
+extern crate memmap2;
 #[macro_use]
 extern crate nom;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "serialize")]
+extern crate serde;
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "serialize")]
+extern crate serde_json;
 
+use std::cmp::Ordering;
+#[cfg(feature = "std")]
 use std::fs::File;
-use std::io::prelude::*;
-
+#[cfg(feature = "std")]
 use std::io::Error;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
-use chunks::{FragSys, CDInstance, LF};
-use analysis::Instance;
+use chunks::{FragSys, CDInstance, LF, LFInstance, LfMatchResult, LfMismatch, PageLookupError,
+             ZipFile};
+use analysis::{Cluster, Instance};
+use error::ZipDefragError;
+#[cfg(feature = "std")]
+use extract::EntryStatus;
+use options::{ClusterKind, DefragOptions};
+#[cfg(feature = "std")]
+use report::{ArchiveSummary, RipReport};
 
 pub mod parser;
+pub mod cp437;
 pub mod chunks;
 pub mod analysis;
+pub mod gaps;
+pub mod extract;
+pub mod options;
+pub mod error;
+pub mod manifest;
+pub mod page_size;
+pub mod orphans;
+pub mod report;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testgen;
+
+/// Load a dump from `file` into a `FragSys`, paged at `page_sz` or, if that's `None`, whatever
+/// page size the dump looks to have been written with.
+#[cfg(feature = "std")]
+pub fn load_dump(file: &mut File, page_sz: Option<usize>) -> Result<FragSys, Error> {
+    match page_sz {
+        Some(ps) => FragSys::from_file(file, ps),
+        None => Ok(FragSys::from_file(file, page_size::DEFAULT_PAGE_SIZE)?.with_detected_page_sz()),
+    }
+}
 
 /// Primo function where yon magic happens.
-pub fn rip_a_zip(file: &mut File, page_sz: Option<usize>) -> Result<&str, Error> {
-    // 0. First of all we're going to want to load a model for the dump (with the data)
-    let ps = match page_sz {
-        Some(x) => x,
-        None => 0x400_usize,
+///
+/// Each archive found gets written to `out_dir`, and what was recovered is reported back. With the
+/// `serialize` feature a JSON `Manifest` of the reconstruction goes alongside them.
+#[cfg(feature = "std")]
+pub fn rip_a_zip(file: &mut File, page_sz: Option<usize>, out_dir: &Path)
+    -> Result<RipReport, ZipDefragError>
+{
+    rip_a_zip_with_options(file, page_sz, out_dir, &DefragOptions::default())
+}
+
+/// As `rip_a_zip`, with control over the reconstruction and how the output gets named.
+#[cfg(feature = "std")]
+pub fn rip_a_zip_with_options(
+    file: &mut File,
+    page_sz: Option<usize>,
+    out_dir: &Path,
+    opts: &DefragOptions,
+) -> Result<RipReport, ZipDefragError> {
+    let mut builder = RipBuilder::new().output_dir(out_dir).options(opts.clone());
+    if let Some(ps) = page_sz {
+        builder = builder.page_size(ps);
+    }
+    builder.run(file)
+}
+
+/// `ZipFile::confidence` below which we warn that an archive's reconstruction is suspect
+#[cfg(feature = "std")]
+const LOW_CONFIDENCE: f64 = 0.5;
+
+#[derive(Clone, Debug)]
+/// Configuration for a run of the whole pipeline, from loading a dump to writing out what we got
+/// from it, for when `rip_a_zip`'s defaults won't do.
+///
+/// ```no_run
+/// # use std::fs::File;
+/// # use zipdefrag::RipBuilder;
+/// let report = RipBuilder::new()
+///     .page_size(0x800)
+///     .output_dir("recovered")
+///     .verify_crc(true)
+///     .run(&mut File::open("dump.bin").unwrap());
+/// ```
+#[cfg(feature = "std")]
+pub struct RipBuilder {
+    /// Page size to load the dump at, or `None` to detect one
+    page_sz: Option<usize>,
+    /// Where the recovered archives get written
+    out_dir: PathBuf,
+    /// Options for the reconstruction itself
+    opts: DefragOptions,
+    /// Whether to check recovered entries against their CRC32s
+    verify_crc: bool,
+    /// Byte to fill missing pages with in the output
+    gap_fill_byte: u8,
+}
+
+#[cfg(feature = "std")]
+impl Default for RipBuilder {
+    fn default() -> Self {
+        RipBuilder {
+            page_sz: None,
+            out_dir: PathBuf::from("."),
+            opts: DefragOptions::default(),
+            verify_crc: false,
+            gap_fill_byte: 0,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl RipBuilder {
+    /// A builder with the same defaults as `rip_a_zip`: page size detected, output to the current
+    /// directory, kmeans clustering, no CRC checks and zero filled gaps.
+    pub fn new() -> Self {
+        RipBuilder::default()
+    }
+
+    /// Page the dump at `page_sz` rather than detecting it.
+    pub fn page_size(mut self, page_sz: usize) -> Self {
+        self.page_sz = Some(page_sz);
+        self
+    }
+
+    /// Write the recovered archives to `out_dir`.
+    pub fn output_dir<P: AsRef<Path>>(mut self, out_dir: P) -> Self {
+        self.out_dir = out_dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Replace the reconstruction options wholesale, including anything set with `clustering`
+    /// and friends beforehand.
+    pub fn options(mut self, opts: DefragOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Cluster central directory headers into archives with `kind`.
+    pub fn clustering(mut self, kind: ClusterKind) -> Self {
+        self.opts.clustering = kind;
+        self
+    }
+
+    /// Have kmeans find `k` clusters of central directory headers, however many EOCDs there are.
+    pub fn cluster_k(mut self, k: usize) -> Self {
+        self.opts.cluster_k = Some(k);
+        self
+    }
+
+    /// Try kmeans with each k within `spread` of the number of EOCDs, keeping whichever best fits
+    /// the entry counts they expect. Ignored if `cluster_k` is set.
+    pub fn cluster_k_spread(mut self, spread: usize) -> Self {
+        self.opts.cluster_k_spread = spread;
+        self
+    }
+
+    /// Whether to decompress each recovered entry and check it against its CRC32, reporting the
+    /// count in each `ArchiveSummary`. Slow on big dumps, so off by default.
+    pub fn verify_crc(mut self, verify: bool) -> Self {
+        self.verify_crc = verify;
+        self
+    }
+
+    /// Fill pages we never found with `fill` in the output, rather than zeros.
+    pub fn gap_fill_byte(mut self, fill: u8) -> Self {
+        self.gap_fill_byte = fill;
+        self
+    }
+
+    /// Load the dump from `file`, reconstruct it and write out the results.
+    pub fn run(&self, file: &mut File) -> Result<RipReport, ZipDefragError> {
+        // 0. First of all we're going to want to load a model for the dump (with the data)
+        let mut fs = load_dump(file, self.page_sz)?;
+        let ps = fs.page_sz();
+
+        let (zips, cluster_k) = reconstruct_with_cluster_k(&mut fs, &self.opts)?;
+        let names = self.opts.output_names(&zips);
+        let mut report = RipReport {
+            zip_files: zips.len(),
+            cluster_k: cluster_k,
+            ..RipReport::default()
+        };
+        for (i, zip) in zips.iter().enumerate() {
+            let mut summary = ArchiveSummary::of(zip, fs.data.len(), ps);
+            summary.confidence = zip.confidence(&fs.data, ps);
+            if summary.confidence < LOW_CONFIDENCE {
+                warn!("Archive {} only has a confidence of {:.2}, treat it with suspicion", i,
+                      summary.confidence);
+            }
+            if !summary.encrypted_entries.is_empty() {
+                warn!("{} entries of archive {} are encrypted, raw bytes only: {:?}",
+                      summary.encrypted_entries.len(), i, summary.encrypted_entries);
+            }
+            if summary.masked_records {
+                warn!("Archive {} has masked headers, so its CD fields may have been scrubbed", i);
+            }
+            if self.verify_crc {
+                let rendered = zip.render_pages(&fs.data, ps);
+                summary.entries_verified = Some(
+                    zip.cached_entries()
+                        .iter()
+                        .filter(|cd| zip.verify_entry(cd, &rendered) == EntryStatus::Valid)
+                        .count(),
+                );
+            }
+
+            let path = self.out_dir.join(&names[i]);
+            zip.write_to_with_fill(&fs.data, ps, self.gap_fill_byte, &mut File::create(&path)?)?;
+            report.archives.push(summary);
+            report.outputs.push(path);
+        }
+        #[cfg(feature = "serialize")]
+        ::report::Manifest::new(&fs, &zips).write_json(&self.out_dir.join("manifest.json"))?;
+        Ok(report)
+    }
+}
+
+/// Group central directory headers with whichever archive's EOCD follows them most closely in the
+/// dump (or is nearest, if none follow), for when clustering has let us down.
+///
+/// A central directory usually runs straight into its EOCD, so this gets the easy cases right,
+/// but a fragmented one will happily end up split between neighbouring archives.
+fn fallback_partition(cds: &[CDInstance], zips: &[ZipFile]) -> Vec<Cluster<CDInstance>> {
+    let mut partitions: Vec<Vec<CDInstance>> = zips.iter().map(|_| vec![]).collect();
+    for cd in cds {
+        let nearest = zips.iter()
+            .enumerate()
+            .min_by_key(|&(_, zip)| if zip.eocd_ptr() >= cd.ptr() {
+                (0, zip.eocd_ptr() - cd.ptr())
+            } else {
+                (1, cd.ptr() - zip.eocd_ptr())
+            });
+        if let Some((i, _)) = nearest {
+            partitions[i].push(cd.clone());
+        }
+    }
+    partitions
+        .into_iter()
+        .filter(|partition| !partition.is_empty())
+        .map(|partition| Cluster::new(&partition))
+        .collect()
+}
+
+/// Cluster the local headers found in the dump by metadata, handing each cluster to the archive
+/// whose entries it best agrees with so that archive can place the pages holding them.
+///
+/// When archives are interleaved page by page, offsets alone can't say which of two identical
+/// headers is whose, but the headers of one archive tend to share timestamps and versions.
+fn seed_from_lf_clusters(fs: &mut FragSys, zips: &mut [ZipFile], opts: &DefragOptions) {
+    if zips.len() < 2 {
+        return;
+    }
+    let ps = fs.page_sz();
+    for zip in zips.iter_mut() {
+        zip.refresh_entries(&fs.data, ps);
+    }
+
+    let lfs = fs.find_lf_instances();
+    if lfs.len() < zips.len() {
+        return;
+    }
+    let clusters = match LFInstance::cluster_with(&lfs, zips.len(), &opts.cluster_params) {
+        Ok((clusters, _)) => clusters,
+        Err(e) => {
+            debug!("Couldn't cluster local headers: {:?}", e);
+            return;
+        }
     };
 
-    let mut fs = FragSys::from_file(file, ps)?;
+    for cluster in clusters {
+        if let Some((i, affinity)) = zips.iter()
+            .map(|zip| zip.lf_affinity(&cluster))
+            .enumerate()
+            .max_by_key(|&(_, affinity)| affinity)
+        {
+            if affinity > 0 {
+                let placed = zips[i].seed_from_lfs(fs, &cluster, ps);
+                debug!("Seeded {} LF pages for {} from a cluster of {}", placed, i, cluster.len());
+            }
+        }
+    }
+}
+
+/// Warn about archives `detect_overlaps` reckons are after the same pages and, with
+/// `DefragOptions::drop_overlapping`, drop the less confident of each pair, putting whatever pages
+/// it had taken back in the pool.
+///
+/// Two archives after the same pages is usually one of them being a false positive EOCD inside
+/// the other, and this wants doing before anything gets clustered or assigned, or the false
+/// positive will have had pages the real archive needed. That means confidence comes down to
+/// what's on the pages holding each EOCD, so where it's even we keep the archive spanning more
+/// of the dump, which is the one with the other inside it.
+fn drop_overlapping(fs: &mut FragSys, zips: Vec<ZipFile>, opts: &DefragOptions) -> Vec<ZipFile> {
+    let ps = fs.page_sz();
+    let mut dropped = vec![false; zips.len()];
+    for (a, b) in ::chunks::detect_overlaps(&zips, ps) {
+        warn!("Archives {} and {} overlap ({:?} and {:?})", a, b, zips[a].dump_span(),
+              zips[b].dump_span());
+        if !opts.drop_overlapping || dropped[a] || dropped[b] {
+            continue;
+        }
+        let (conf_a, conf_b) = (zips[a].confidence(&fs.data, ps),
+                                zips[b].confidence(&fs.data, ps));
+        let (span_a, span_b) = (zips[a].dump_span(), zips[b].dump_span());
+        let drop = match conf_a.partial_cmp(&conf_b) {
+            Some(Ordering::Less) => a,
+            Some(Ordering::Equal) if span_a.end - span_a.start < span_b.end - span_b.start => a,
+            _ => b,
+        };
+        info!("Dropping archive {} ({:.2} vs {:.2})", drop, conf_a, conf_b);
+        dropped[drop] = true;
+    }
+
+    let mut kept = Vec::with_capacity(zips.len());
+    for (zip, gone) in zips.into_iter().zip(dropped) {
+        if gone {
+            for page in zip.pages().to_vec() {
+                fs.return_page(page);
+            }
+        } else {
+            kept.push(zip);
+        }
+    }
+    kept
+}
+
+/// Run the reconstruction passes over a `FragSys`, returning models of the zip files found with as
+/// many of their pages assigned as we could manage.
+///
+/// Pages assigned to a zip file are pulled from the `FragSys` pool as we go. Archives whose
+/// declared sizes turn out not to fit their page span are dropped, and if clustering fails (with
+/// no `DefragOptions::fallback_partition` to fall back on) nothing comes back at all.
+pub fn reconstruct(fs: &mut FragSys, opts: &DefragOptions) -> Vec<ZipFile> {
+    match reconstruct_with_cluster_k(fs, opts) {
+        Ok((zips, _)) => zips,
+        Err(e) => {
+            error!("{}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// As `reconstruct`, also handing back how many clusters kmeans split the central directory
+/// headers into, which can differ from the number of EOCDs with `DefragOptions::cluster_k` or
+/// `cluster_k_spread` set. That's `None` with DBSCAN, or with no EOCDs to cluster for.
+///
+/// Fails with `ClusteringFailed` if the central directory headers couldn't be clustered and
+/// `DefragOptions::fallback_partition` isn't set.
+pub fn reconstruct_with_cluster_k(fs: &mut FragSys, opts: &DefragOptions)
+    -> Result<(Vec<ZipFile>, Option<usize>), ZipDefragError>
+{
+    let ps = fs.page_sz();
+    fs.set_magics(opts.magics.clone());
+    fs.set_fuzzy_eocd(opts.fuzzy_eocd);
+    fs.set_fuzzy_lf(opts.fuzzy_lf);
 
     // 1. Then for each ptr in the listing we should parse it and propagate a new zip file object.
     //    Use the `EOCD` `CD` offset and `CD` size to compute the offset into the first page of the
     //    file and also the number of pages in total. Also use the new `ZipFile` model to set up an
-    //    ordered page list.
-    let mut zip_files = fs.find_zips();
+    //    ordered page list. For Zip64 archives `ZipFile::new` prefers the locator's offset for
+    //    the Zip64 record for this, falling back on the Zip64 record's `CD` offset and size.
+    let prefix_len = opts.prefix_len(fs);
+    let zip_files = fs.find_zips_with_prefix(prefix_len);
+    let mut zip_files = drop_overlapping(fs, zip_files, opts);
+    if zip_files.is_empty() {
+        // No archive for any CDs to go to, so no sense clustering them
+        return Ok((Vec::new(), None));
+    }
 
     // 2. Locate all available `CD` Headers in the raw dump
 
     let unclassified_cd_listing = fs.find_cds();
 
-    // 3. Classify `CD` headers using the kmeans2 algorithm
-
-    if let Ok(classified_cd_listing) =
-        CDInstance::cluster(&unclassified_cd_listing, zip_files.len())
-    {
-        // 4. For each partition of `CD` headers order them by least `LF` pointer
-        //
-        //    Note: The following is distinctly crufty, unrustic and Just Gets Stuff Done for the
-        //    PoC.
-
-        let sorted_cd_clusters = classified_cd_listing
-            .into_iter()
-            .map(|cluster| {
-                let mut iter = cluster.into_iter();
-                iter.as_mut_slice().sort_unstable_by(|a, b| {
-                    a.header().lf_offset.cmp(&b.header().lf_offset)
-                });
-                let sorted = ::analysis::Cluster::new(iter.as_slice());
-                debug!("Returned clusters:\n{:?}", &sorted);
-                sorted
+    // 3. Classify `CD` headers using the kmeans2 algorithm (or DBSCAN, if asked), with as many
+    //    clusters as there are EOCDs unless told or able to find better
+
+    let mut cluster_k = None;
+    let clustered = match opts.clustering {
+        ClusterKind::KMeans => {
+            let chosen = match opts.cluster_k {
+                Some(k) => CDInstance::cluster_with(&unclassified_cd_listing, k,
+                                                    &opts.cluster_params)
+                    .map(|(clusters, _)| (k, clusters)),
+                None => ::analysis::choose_cluster_k(&unclassified_cd_listing, &zip_files,
+                                                     opts.cluster_k_spread, &opts.cluster_params),
+            };
+            chosen.map(|(k, clusters)| {
+                if k != zip_files.len() {
+                    info!("Clustered CDs with k = {} for {} EOCDs", k, zip_files.len());
+                }
+                cluster_k = Some(k);
+                clusters
             })
-            .collect::<Vec<_>>();
-
-        // 5. Map k partition sizes to nearest `ZipFile` file count to identify correct EOCD   }
-        //    (Optionally, use parsed `CD`s and last `LF` ptr to match)
-
-        for cluster in sorted_cd_clusters {
-            // Pretty awful heuristic for matching here which will be outright buggy in some
-            // obvious cases. Should be moved into a separate function on collection of zip
-            // files and clusters, returning a zip of tuples in order to move past PoC
-            //
-            // In fact it's buggy and unnecessary -- rather than doing this heuristically by trying
-            // to minimise the differences between CD counts and zip file tot entries, we could
-            // just render each cluster's CD pages to a continuous buffer, and reparse these in
-            // order to get an accurate count, as well as checking whether a ZipFile EOCD is at the
-            // tail of each, or alternatively using the offset into the page of the first CD along
-            // with the calculated expected offset as a confidence identifier. Any one of these
-            // would be a pretty good confirmation, tbh, although more confidence the better in
-            // terms of opportunistic parsing and having stronger affirmation/rebuttal of our
-            // working hypotheses while solving this stuff. On the one hand, if we make a good
-            // guess, it benefits us nothing to continue checking it makes sense, but on the other
-            // hand, the faster we eliminate bad guesses the more information we have to go on for
-            // making good guesses. Puzzle solving/optimisation is hard.
-
-            if let Some(zf) = zip_files.iter_mut()
-                    .min_by(|z1,z2| {
-                        let d1 = 
-                            (i32::from(z1.eocd.tot_entries) - cluster.iter().count() as i32).pow(2);
-                        let d2 = (i32::from(z2.eocd.tot_entries) - cluster.iter().count() as i32).pow(2);
-                        d1.cmp(&d2)
-                    }) {
-                let cd_pg_idx = zf.get_cd_start_pg_idx(fs.page_sz());
-                let mut cd_pgs = vec![];
-                for instance in cluster {
-                    if let Some(page) = fs.get_pg_for_addr(instance.ptr()) {
-                        cd_pgs.push(page)
+        }
+        ClusterKind::Dbscan { epsilon, min_points } => {
+            ::analysis::cluster_dbscan(&unclassified_cd_listing, epsilon, min_points)
+                .map(|(clusters, noise)| {
+                    if !noise.is_empty() {
+                        warn!("{} CDs fit no cluster, leaving them out", noise.len());
                     }
-                }
+                    clusters
+                })
+        }
+    };
+    let classified_cd_listing = match clustered {
+        Ok(clusters) => clusters,
+        Err(e) => if opts.fallback_partition {
+            warn!("Clustering failed ({:?}), FALLING BACK to grouping CDs by nearest EOCD: \
+                   treat the archives recovered with suspicion", e);
+            fallback_partition(&unclassified_cd_listing, &zip_files)
+        } else {
+            return Err(e.into());
+        },
+    };
+
+    // 4. For each partition of `CD` headers order them by least `LF` pointer
+    //
+    //    Note: The following is distinctly crufty, unrustic and Just Gets Stuff Done for the
+    //    PoC.
 
-                // 6. Use CD locations to map `CD` pages into known `CD` `Page` range for
-                //    `ZipFile` page buffer, removing the pages from the pool left in the `FragSys`
+    let sorted_cd_clusters = classified_cd_listing
+        .into_iter()
+        .map(|cluster| {
+            let mut iter = cluster.into_iter();
+            iter.as_mut_slice().sort_unstable_by(|a, b| {
+                a.header().lf_offset.cmp(&b.header().lf_offset)
+            });
+            let sorted = ::analysis::Cluster::new(iter.as_slice());
+            debug!("Returned clusters:\n{:?}", &sorted);
+            sorted
+        })
+        .collect::<Vec<_>>();
 
-                let cd_pg_end = cd_pgs.len() + cd_pg_idx;
-                debug!("Writing {} CD Pages starting at page {}", cd_pgs.len(), cd_pg_idx);
-                zf.assign_pages(cd_pg_idx, cd_pgs);
+    // 5. Map k partition sizes to nearest `ZipFile` file count to identify correct EOCD   }
+    //    (Optionally, use parsed `CD`s and last `LF` ptr to match)
+
+    // Rather than going by counts at all we could render each cluster's CD pages to a
+    // continuous buffer and reparse them for an accurate count, or check for a ZipFile's EOCD
+    // at the tail of each, or compare the offset into the page of the first CD with the
+    // expected one. Any of these would be a pretty good confirmation, and the faster we
+    // eliminate bad guesses the more information we have to go on for making good ones.
+    // Puzzle solving/optimisation is hard.
+    let matches = ::analysis::match_clusters_to_zips(&sorted_cd_clusters, &zip_files);
+    if matches.len() < sorted_cd_clusters.len() {
+        debug!("{} CD clusters left without an archive to go to",
+               sorted_cd_clusters.len() - matches.len());
+    }
+    for (c, z) in matches {
+        let cluster = &sorted_cd_clusters[c];
+        let zf = &mut zip_files[z];
+        let cd_pg_idx = zf.get_cd_start_pg_idx(fs.page_sz());
+        let mut cd_pgs = vec![];
+        for instance in cluster {
+            match fs.get_pg_for_addr(instance.ptr()) {
+                Ok(page) => cd_pgs.push(page),
+                // Already taken for an earlier CD on the same page
+                Err(PageLookupError::NotFound) => {}
+                Err(PageLookupError::Ambiguous(candidates)) => {
+                    warn!("CD at {} is on {} overlapping pages ({:?}), leaving them be",
+                          instance.ptr(), candidates.len(), candidates);
+                }
             }
         }
 
-        // 7. Reparse CD Pages for each zip file (in order to recover page-boundary CD
-        //    headers)
+        // 6. Use CD locations to map `CD` pages into known `CD` `Page` range for
+        //    `ZipFile` page buffer, removing the pages from the pool left in the `FragSys`
 
-        for (i,zip) in zip_files.iter_mut().enumerate() {
-            debug!("Reparsing cd headers for {}", i);
-            let reparsed_central_directory = zip.find_cds(&fs.data);
+        let cd_pg_end = cd_pgs.len() + cd_pg_idx;
+        debug!("Writing {} CD Pages starting at page {}", cd_pgs.len(), cd_pg_idx);
+        for displaced in zf.assign_pages(cd_pg_idx, cd_pgs) {
+            fs.return_page(displaced);
+        }
+        zf.recompute_layout(ps);
+    }
+
+    // 6b. Where there's more than one archive, cluster the local headers too and seed each
+    //     archive with the LF pages from the cluster which best agrees with its entries
+    seed_from_lf_clusters(fs, &mut zip_files, opts);
+
+    // 7. Reparse CD Pages for each zip file (in order to recover page-boundary CD
+    //    headers)
+
+    let mut consistent = Vec::with_capacity(zip_files.len());
+    for (i,zip) in zip_files.iter_mut().enumerate() {
+        debug!("Reparsing cd headers for {}", i);
+        let reparsed_central_directory = zip.refresh_entries(&fs.data, ps);
 
-            debug!("Found {} cds", reparsed_central_directory.len());
-            for cd in reparsed_central_directory {
-                let lfh = LF::from(cd.header());
-                let lfp = fs.find_lfs();
-                if let Some(ptr) = fs.find_lf(&lfh, &lfp) {
-                    if let Some(page) = fs.get_pg_for_addr(ptr) {
+        // Checked against the EOCD's page span before the CDs get a say in it, or a corrupt
+        // compressed size would stretch the span to fit itself
+        debug!("Found {} cds", reparsed_central_directory.len());
+        consistent.push(zip.size_budget_consistent(ps));
+        if !consistent[i] {
+            warn!("Declared sizes for {} don't fit its page span, skipping", i);
+            continue;
+        }
+        zip.recompute_layout(ps);
+
+        let mut lf_missing = vec![];
+        for cd in reparsed_central_directory {
+            if !cd.header().is_on_this_disk(&zip.eocd) {
+                info!("{} lives on disk {} of a spanned set, not searching for it",
+                      cd.header().filename, cd.header().dsk_no_s);
+                continue;
+            }
+            let lfh = LF::from(cd.header());
+            let result = fs.match_lf(&lfh);
+            if let LfMatchResult::FilenameMismatch(ptr) = result {
+                warn!("Local header at {} for {} has a different filename: {:?}", ptr,
+                      cd.header().filename, fs.lf_at(ptr).map(|lf| lf.filename));
+            }
+            match result {
+                LfMatchResult::Matched(ptr) | LfMatchResult::FilenameMismatch(ptr) => {
+                    let mismatches = fs.lf_at(ptr)
+                        .map(|lf| cd.header().lf_mismatches(&lf))
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|&mismatch| mismatch != LfMismatch::Filename)
+                        .collect::<Vec<_>>();
+                    if !mismatches.is_empty() {
+                        debug!("Rejecting local header at {} for {}, mismatched on {:?}",
+                               ptr, cd.header().filename, mismatches);
+                        lf_missing.push(cd);
+                        continue;
+                    }
+                    if let Ok(page) = fs.get_pg_for_addr(ptr) {
                         debug!("Found file data for {:?} at page {:?}", cd, page);
                         let idx = zip.get_pg_idx_for_offs(cd.header().lf_offset as usize, ps);
-                        zip.assign_page(idx, page);
+                        if let Some(displaced) = zip.assign_page(idx, page) {
+                            debug!("Returning displaced page {:?} to the pool", displaced);
+                            fs.return_page(displaced);
+                        }
+                    }
+                }
+                // Close enough without being exact, so its timestamp and friends are
+                // expected to disagree and aren't worth checking
+                LfMatchResult::Fuzzy { ptr, score } => {
+                    debug!("Fuzzy matched local header at {} for {}, scoring {}", ptr,
+                           cd.header().filename, score);
+                    if let Ok(page) = fs.get_pg_for_addr(ptr) {
+                        let idx = zip.get_pg_idx_for_offs(cd.header().lf_offset as usize, ps);
+                        if let Some(displaced) = zip.assign_page(idx, page) {
+                            debug!("Returning displaced page {:?} to the pool", displaced);
+                            fs.return_page(displaced);
+                        }
                     }
                 }
+                // Most likely shares a page with a header we've already placed
+                LfMatchResult::PageUnavailable => {}
+                result => {
+                    debug!("No local header for {}: {:?}", cd.header().filename, result);
+                    lf_missing.push(cd);
+                }
             }
-            let output = zip.render_pages(&fs.data, ps);
-            let mut file = File::create(format!("{}.zip",i))?;
-            file.write_all(&output);
-        }
-
-        // 8. For each zip file, iterate over each CD in order searching for uniquely
-        //    identifiable LF headers which can also be found in the dump (importantly
-        //    matching for time and date and so on), mapping pages for each into the zip
-        //    file.
-        //
-        // 9. Perform 8, except for Data Descriptors in cases where they are flagged.
-        //
-        // 10. For each zip file, find the smallest gap in the LF headers, use CRC32 and
-        //     size data to search for, moving pages to the correct location in the ZipFile
-        //     list. Restrict this effort to easier cases (1/2 missing pages).
-        //
-        // 11. Use Shannon Entropy computation to filter remaining pages for high entropy pages
-        //     (more likely to be compressed data).
-        //
-        // 12. Repeat 10 for harder cases.
-        //
-        // 13. Dump some output. Possibly just return a bunch of boxed `ZipFile`s for the main
-        //     to write to disk or sommat
-
-    }
-    Ok("We Did it!")
+        }
+        zip.recompute_layout(ps);
+
+        // Place whatever we can of the remaining pool into the gaps left over. Even with no
+        // scorer of our own this moves pages: anything running on from an assigned neighbour
+        // gets placed on continuity, unless its entropy is way off its neighbours'
+        let filled = zip.fill_gaps(fs, gaps::neutral_score);
+        debug!("Filled {} gaps for {} by page scoring", filled, i);
+
+        // Entries down to a single missing page can have it brute forced against their CRC32
+        let entries = zip.cached_entries().to_vec();
+        let filled = entries
+            .iter()
+            .filter(|cd| zip.fill_single_page_gap(fs, cd))
+            .count();
+        debug!("Filled {} single page gaps for {} by CRC32", filled, i);
+
+        // As a last resort, rebuild any local file headers we never found from their CDs
+        for cd in lf_missing {
+            zip.synthesize_missing_lf(cd.header(), ps);
+        }
+        zip.recompute_layout(ps);
+        info!("Archive {}: {}", i, zip.coverage());
+    }
+
+    // 8. For each zip file, iterate over each CD in order searching for uniquely
+    //    identifiable LF headers which can also be found in the dump (importantly
+    //    matching for time and date and so on), mapping pages for each into the zip
+    //    file.
+    //
+    // 9. Perform 8, except for Data Descriptors in cases where they are flagged.
+    //
+    // 10. For each zip file, find the smallest gap in the LF headers, use CRC32 and
+    //     size data to search for, moving pages to the correct location in the ZipFile
+    //     list. Restrict this effort to easier cases (1/2 missing pages).
+    //
+    // 11. Use Shannon Entropy computation to filter remaining pages for high entropy pages
+    //     (more likely to be compressed data).
+    //
+    // 12. Repeat 10 for harder cases.
+    //
+    // 13. Hand back the `ZipFile`s for the caller to render, write or inspect
+
+    let zip_files = zip_files
+        .into_iter()
+        .zip(consistent)
+        .filter_map(|(zip, ok)| if ok { Some(zip) } else { None })
+        .collect();
+    Ok((zip_files, cluster_k))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn rip_builder_defaults() {
+        let builder = RipBuilder::new();
+        assert_eq!(builder.page_sz, None);
+        assert_eq!(builder.out_dir, PathBuf::from("."));
+        assert_eq!(builder.opts.clustering, ClusterKind::KMeans);
+        assert!(!builder.verify_crc);
+        assert_eq!(builder.gap_fill_byte, 0);
+        assert_eq!((builder.opts.cluster_k, builder.opts.cluster_k_spread), (None, 0));
+
+        let builder = builder
+            .cluster_k(3)
+            .cluster_k_spread(1)
+            .page_size(0x800)
+            .output_dir("out")
+            .clustering(ClusterKind::Dbscan { epsilon: 3600.0, min_points: 2 })
+            .verify_crc(true)
+            .gap_fill_byte(0xcc);
+        assert_eq!(builder.page_sz, Some(0x800));
+        assert_eq!(builder.out_dir, PathBuf::from("out"));
+        assert_eq!(builder.opts.clustering, ClusterKind::Dbscan { epsilon: 3600.0, min_points: 2 });
+        assert!(builder.verify_crc);
+        assert_eq!(builder.gap_fill_byte, 0xcc);
+        assert_eq!((builder.opts.cluster_k, builder.opts.cluster_k_spread), (Some(3), 1));
+    }
+
+    #[test]
+    fn overlap_dropped_before_assignment() {
+        // An EOCD for a one entry archive sat in the middle of a stored file, which would take
+        // the page it's on for itself if it were let
+        let fake = ::chunks::EOCD {
+            dsk_no: 0,
+            dsk_w_cd: 0,
+            dsk_entries: 1,
+            tot_entries: 1,
+            cd_sz: 46,
+            cd_offset: 0,
+            cmt_len: 0,
+            zip_cmt: String::new(),
+        };
+        let mut data = vec![b'x'; 0x80];
+        data.extend(fake.unparse());
+        data.extend(vec![b'x'; 0x80]);
+        let archive = ::testgen::stored_archive(&[("a.txt", &data[..])], 1_500_000_000);
+        let mut dump = vec![0u8; 0x10];
+        dump.extend_from_slice(&archive);
+
+        let mut fs = FragSys::from_bytes(dump.clone(), 0x40);
+        assert_eq!(fs.find_zips().len(), 2);
+
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let opts = DefragOptions { drop_overlapping: true, ..DefragOptions::default() };
+        let zips = reconstruct(&mut fs, &opts);
+        assert_eq!(zips.len(), 1);
+        assert!(zips[0].byte_completion(0x40) > 0.999);
+    }
+
+    #[test]
+    fn fallback_partition_groups_by_following_eocd() {
+        let small = (0..2).map(|i| (format!("s{}", i), vec![i as u8; 8])).collect::<Vec<_>>();
+        let big = (0..10).map(|i| (format!("b{}", i), vec![i as u8; 8])).collect::<Vec<_>>();
+        let files = |entries: &[(String, Vec<u8>)]| {
+            entries.iter().map(|&(ref name, ref data)| (&name[..], &data[..])).collect::<Vec<_>>()
+        };
+        let mut dump = vec![0u8; 0x10];
+        dump.extend(::testgen::stored_archive(&files(&small), 1_500_000_000));
+        // Start the second archive on a page of its own
+        let pages = dump.len() / 0x40 + 1;
+        dump.resize(pages * 0x40, 0);
+        dump.extend(::testgen::stored_archive(&files(&big), 1_600_000_000));
+
+        // DBSCAN won't have a zero epsilon, so clustering fails every time
+        let opts = DefragOptions {
+            clustering: ClusterKind::Dbscan { epsilon: 0.0, min_points: 2 },
+            ..DefragOptions::default()
+        };
+        let mut fs = FragSys::from_bytes(dump.clone(), 0x40);
+        match reconstruct_with_cluster_k(&mut fs, &opts) {
+            Err(ZipDefragError::ClusteringFailed(_)) => {}
+            Err(e) => panic!("clustering failed with {:?}", e),
+            Ok((zips, _)) => panic!("clustering succeeded with {} archives", zips.len()),
+        }
+
+        // Each archive's CDs run straight into its own EOCD, so sit nearest before it
+        let mut fs = FragSys::from_bytes(dump.clone(), 0x40);
+        let zips = fs.find_zips();
+        let cds = fs.find_cds();
+        let partitions = fallback_partition(&cds, &zips);
+        assert_eq!(partitions.iter().map(|p| p.len()).collect::<Vec<_>>(), vec![2, 10]);
+        for (partition, zip) in partitions.iter().zip(&zips) {
+            assert!(partition.iter().all(|cd| cd.ptr() < zip.eocd_ptr()));
+        }
+        assert!(partitions[1].iter().all(|cd| cd.ptr() > zips[0].eocd_ptr()));
+
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let opts = DefragOptions { fallback_partition: true, ..opts };
+        let zips = reconstruct(&mut fs, &opts);
+        let names = |entries: &[::chunks::CD]| {
+            entries.iter().map(|cd| cd.filename.clone()).collect::<Vec<_>>()
+        };
+        let expected = |entries: &[(String, Vec<u8>)]| {
+            entries.iter().map(|&(ref name, _)| name.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(zips.len(), 2);
+        assert_eq!(names(zips[0].cached_entries()), expected(&small));
+        assert_eq!(names(zips[1].cached_entries()), expected(&big));
+        assert!(zips.iter().all(|zip| zip.byte_completion(0x40) > 0.999));
+    }
 }
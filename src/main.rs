@@ -9,11 +9,62 @@ extern crate env_logger;
 use std::env;
 use std::fs::File;
 use std::iter::Iterator;
+use std::path::Path;
 use std::process::exit;
 use zipdefrag::*;
+use zipdefrag::chunks::FragSys;
+use zipdefrag::extract::EntryStatus;
+use zipdefrag::options::DefragOptions;
 
 fn usage(filename: &str) {
-    println!("Usage: {} [filedump.bin]", filename);
+    println!("Usage: {} <command> [filedump.bin]", filename);
+    println!();
+    println!("Commands:");
+    println!("    list       Survey the dump and summarise the archives found");
+    println!("    extract    Reconstruct the archives and write them out as N.zip");
+    println!("    verify     Reconstruct the archives and report on them without writing");
+}
+
+/// Print a summary of each archive whose EOCD we can find, without reconstructing anything.
+fn list(fs: &mut FragSys) {
+    let zips = fs.find_zips();
+    println!("{} archive(s) found", zips.len());
+    for (i, zip) in zips.iter().enumerate() {
+        println!(
+            "{}: {} entries, {} byte central directory at offset {:#x}{}",
+            i,
+            zip.tot_entries(),
+            zip.cd_sz(),
+            zip.cd_offset(),
+            if zip.eocd.zip_cmt.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", zip.eocd.zip_cmt)
+            }
+        );
+    }
+}
+
+/// Reconstruct each archive and report how much of it we recovered.
+fn verify(fs: &mut FragSys) {
+    let zips = reconstruct(fs, &DefragOptions::default());
+    println!("{} archive(s) reconstructed", zips.len());
+    for (i, zip) in zips.iter().enumerate() {
+        let rendered = zip.render_pages(&fs.data, fs.page_sz());
+        let valid = zip.cached_entries()
+            .iter()
+            .filter(|cd| zip.verify_entry(cd, &rendered) == EntryStatus::Valid)
+            .count();
+        println!(
+            "{}: {}/{} central directory entries recovered ({} verified), {} local header(s) \
+             synthesized",
+            i,
+            zip.cached_entries().len(),
+            zip.tot_entries(),
+            valid,
+            zip.synthesized_lfs().len()
+        );
+    }
 }
 
 fn main() {
@@ -21,22 +72,61 @@ fn main() {
 
     let mut args = env::args();
 
-    let _executable = match args.next() {
+    let executable = match args.next() {
         Some(exec) => exec,
         None => "".to_owned(),
     };
-    match args.next() {
-        Some(dump) => {
-            if let Ok(mut df) = File::open(dump) {
-                rip_a_zip(&mut df, Some(0x400));
+    let (command, dump) = match (args.next(), args.next()) {
+        (Some(command), Some(dump)) => (command, dump),
+        _ => {
+            println!("Not enough args");
+            usage(&executable);
+            exit(1);
+        }
+    };
+
+    let mut df = match File::open(dump) {
+        Ok(df) => df,
+        Err(_) => {
+            println!("Couldn't open file");
+            exit(1);
+        }
+    };
+
+    match command.as_str() {
+        "extract" => {
+            match rip_a_zip(&mut df, None, Path::new(".")) {
+                Ok(report) => for (path, archive) in report.outputs.iter().zip(&report.archives) {
+                    println!("{}: {} entries, {} of {} pages recovered", path.display(),
+                             archive.entries, archive.pages_assigned,
+                             archive.pages_assigned + archive.pages_synthesized +
+                                 archive.pages_missing);
+                },
+                Err(e) => {
+                    println!("Extraction failed: {}", e);
+                    exit(1);
+                }
+            }
+        }
+        "list" | "verify" => {
+            let mut fs = match load_dump(&mut df, None) {
+                Ok(fs) => fs,
+                Err(e) => {
+                    println!("Couldn't load dump: {}", e);
+                    exit(1);
+                }
+            };
+            if command == "list" {
+                list(&mut fs);
             } else {
-                println!("Couldn't open file");
+                verify(&mut fs);
             }
-            exit(0);
         }
-        None => {
-            println!("Not enough args");
+        _ => {
+            println!("Unknown command: {}", command);
+            usage(&executable);
             exit(1);
         }
-    };
+    }
+    exit(0);
 }
@@ -0,0 +1,110 @@
+//! Checking what we recovered against a list of what we expected to find, for answering "did we get
+//! everything we needed?" without trawling through the output by hand.
+
+use chunks::ZipFile;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Outcome of comparing the entries of some recovered archives against an expected manifest
+pub struct ManifestReport {
+    /// Expected files found amongst the recovered entries
+    pub recovered: Vec<String>,
+    /// Expected files we found no entry for
+    pub missing: Vec<String>,
+    /// Recovered entries which weren't on the manifest
+    pub unexpected: Vec<String>,
+}
+
+/// Normalise a path for comparison, papering over the usual differences in how the same path gets
+/// written down: backslashes, leading `./` or `/`, and doubled separators.
+fn normalise(path: &str) -> String {
+    path.replace('\\', "/")
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether two normalised paths refer to the same file: either they're the same, or one is the
+/// other with some leading directories chopped off (e.g. a manifest written relative to a
+/// different root).
+fn paths_match(a: &str, b: &str) -> bool {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    !short.is_empty() &&
+        (long == short ||
+             (long.ends_with(short) && long[..long.len() - short.len()].ends_with('/')))
+}
+
+/// Compare the entries recovered across `zips` against the `expected` file names.
+///
+/// Directory entries are ignored. Each recovered entry is only counted once, against the first
+/// expected name it matches.
+pub fn correlate_manifest(expected: &[String], zips: &[ZipFile]) -> ManifestReport {
+    let mut found = zips.iter()
+        .flat_map(|zip| zip.cached_entries().iter())
+        .filter(|cd| !cd.filename.ends_with('/'))
+        .map(|cd| (cd.filename.clone(), normalise(&cd.filename), false))
+        .collect::<Vec<_>>();
+
+    let mut report = ManifestReport::default();
+    for name in expected {
+        let wanted = normalise(name);
+        match found.iter_mut().find(|entry| !entry.2 && paths_match(&entry.1, &wanted)) {
+            Some(entry) => {
+                entry.2 = true;
+                report.recovered.push(name.clone());
+            }
+            None => report.missing.push(name.clone()),
+        }
+    }
+    report.unexpected = found
+        .into_iter()
+        .filter(|entry| !entry.2)
+        .map(|entry| entry.0)
+        .collect();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use manifest::*;
+
+    #[test]
+    fn paths_normalised() {
+        assert_eq!(normalise("./a\\b.txt"), "a/b.txt");
+        assert_eq!(normalise("/a//b.txt"), "a/b.txt");
+        assert_eq!(normalise("a/./b.txt"), "a/b.txt");
+        assert_eq!(normalise("./"), "");
+    }
+
+    #[test]
+    fn paths_match_on_boundaries() {
+        assert!(paths_match("a/b.txt", "a/b.txt"));
+        // Either way round
+        assert!(paths_match("root/a/b.txt", "a/b.txt"));
+        assert!(paths_match("a/b.txt", "root/a/b.txt"));
+        // Only where a directory has been chopped off, not part of a name
+        assert!(!paths_match("xb.txt", "b.txt"));
+        assert!(!paths_match("a/xb.txt", "b.txt"));
+        assert!(!paths_match("", ""));
+    }
+
+    #[test]
+    fn manifest_correlated() {
+        let files = [("dir/", &b""[..]), ("a/b.txt", &b"hello"[..]),
+                     ("root/c/d.txt", &b"world"[..]), ("xb.txt", &b"!"[..]), ("f.txt", &b"?"[..])];
+        let (_, zips) = ::testgen::reconstructed(&::testgen::stored_archive(&files, 1_500_000_000));
+        assert_eq!(zips.len(), 1);
+
+        // The first `f.txt` takes the only entry of that name, and `b.txt` finds `a/b.txt` already
+        // taken and `xb.txt` no match
+        let expected = ["./a\\b.txt", "c/d.txt", "b.txt", "f.txt", "f.txt"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let report = correlate_manifest(&expected, &zips);
+        assert_eq!(report.recovered, vec!["./a\\b.txt", "c/d.txt", "f.txt"]);
+        assert_eq!(report.missing, vec!["b.txt", "f.txt"]);
+        // Nothing for the directory entry either way
+        assert_eq!(report.unexpected, vec!["xb.txt"]);
+    }
+}
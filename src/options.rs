@@ -0,0 +1,191 @@
+//! Knobs for tuning how the reconstruction pipeline goes about its business.
+
+use analysis::ClusterParams;
+use chunks::{FragSys, FuzzyEocd, Magics, ZipFile};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Which algorithm to partition central directory headers into archives with
+pub enum ClusterKind {
+    /// kmeans, with as many clusters as there are EOCDs unless `DefragOptions::cluster_k` or
+    /// `cluster_k_spread` say otherwise
+    KMeans,
+    /// DBSCAN with the given `epsilon` and `min_points` (see `analysis::cluster_dbscan`), which
+    /// works out the number of clusters for itself and leaves out anything which doesn't fit
+    Dbscan {
+        /// Neighbourhood radius, in the units of the header vectors
+        epsilon: f64,
+        /// Neighbours needed within `epsilon` for a core point, itself included
+        min_points: usize,
+    },
+}
+
+impl Default for ClusterKind {
+    fn default() -> Self {
+        ClusterKind::KMeans
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Options for `reconstruct`
+pub struct DefragOptions {
+    /// Length of a container header (e.g. a vendor magic and length field) wrapping each archive
+    /// ahead of its first local file header.
+    ///
+    /// The zip's own offsets don't account for the prefix, so without it every offset lands short
+    /// by that many bytes. Note the prefix only moves the archive as a whole relative to the page
+    /// grid: `prefix_len % page_sz` is absorbed into where the container starts within its first
+    /// page, while `prefix_len / page_sz` whole pages of container header get added to the front
+    /// of the archive's page list.
+    ///
+    /// When `None`, the prefix is detected from `container_magic` if that's given, and assumed to
+    /// be absent otherwise.
+    pub archive_prefix_len: Option<usize>,
+    /// Magic bytes at the start of the container header, used to detect `archive_prefix_len`
+    pub container_magic: Option<Vec<u8>>,
+    /// Header signatures to search for, for archives with the PK signatures swapped out. Defaults
+    /// to the standard ones.
+    pub magics: Magics,
+    /// Whether to accept EOCDs with a few bits of their magic flipped, and how plausible the rest
+    /// of them must look if so. Off by default.
+    pub fuzzy_eocd: Option<FuzzyEocd>,
+    /// Whether to place an entry's local header page on the strength of a header which only
+    /// agrees with its CD on the filename, method and CRC32 (see `LF::fuzzy_score`), when none
+    /// matches exactly. Off by default.
+    pub fuzzy_lf: bool,
+    /// Algorithm for clustering central directory headers into archives
+    pub clustering: ClusterKind,
+    /// Parameters for clustering central directory headers into archives with kmeans
+    pub cluster_params: ClusterParams,
+    /// Number of clusters for kmeans to find, overriding the number of EOCDs, for when you know
+    /// better than EOCD detection how many archives are in the dump
+    pub cluster_k: Option<usize>,
+    /// How far either side of the number of EOCDs to look for a better k for kmeans, when
+    /// `cluster_k` isn't given (see `analysis::choose_cluster_k`). Defaults to 0, which is to
+    /// trust the EOCDs.
+    pub cluster_k_spread: usize,
+    /// Whether to carry on when clustering fails, grouping central directory headers with the
+    /// nearest EOCD following them in the dump instead. The grouping's much cruder, but a
+    /// degraded result beats none at all.
+    pub fallback_partition: bool,
+    /// Template for naming output archives, with `{index}`, `{comment}` (the EOCD comment) and
+    /// `{first_entry}` (the top-level directory or name of the first entry) placeholders.
+    ///
+    /// Defaults to `{index}.zip`.
+    pub output_name_template: Option<String>,
+    /// Whether to drop the less confident of any two archives `detect_overlaps` reckons are
+    /// after the same pages, rather than just warning about them. Off by default, as a
+    /// fragmented dump can put two perfectly good archives' EOCDs close enough to trip it.
+    pub drop_overlapping: bool,
+}
+
+/// Output name template used when none is given
+const DEFAULT_OUTPUT_NAME: &str = "{index}.zip";
+
+/// Make a value from an archive safe to drop into a file name, keeping only characters which
+/// won't get us into trouble on any file system worth mentioning.
+fn sanitize(value: &str) -> String {
+    let cleaned = value
+        .chars()
+        .map(|c| if (c as u32) < 0x80 && c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+            c
+        } else {
+            '_'
+        })
+        .collect::<String>();
+    cleaned.trim_left_matches('.').to_owned()
+}
+
+/// `name` with `-index` put in ahead of its extension, if it has one
+fn with_index(name: &str, index: usize) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}-{}{}", &name[..dot], index, &name[dot..]),
+        _ => format!("{}-{}", name, index),
+    }
+}
+
+impl DefragOptions {
+    /// The file name to write the `index`th recovered archive to.
+    ///
+    /// Placeholders with nothing to go in them (no comment, no entries recovered) are filled with
+    /// the index instead, so names never end up empty. Two archives can still get the same name
+    /// this way, though, so use `output_names` when naming more than one.
+    pub fn output_name(&self, index: usize, zip: &ZipFile) -> String {
+        let template = self.output_name_template
+            .as_ref()
+            .map(|template| template.as_str())
+            .unwrap_or(DEFAULT_OUTPUT_NAME);
+        let index = index.to_string();
+        let or_index = |value: String| if value.is_empty() { index.clone() } else { value };
+
+        let comment = or_index(sanitize(&zip.eocd.zip_cmt));
+        let first_entry = or_index(
+            zip.cached_entries()
+                .first()
+                .and_then(|cd| cd.filename.split('/').find(|part| !part.is_empty()))
+                .map(sanitize)
+                .unwrap_or_default(),
+        );
+        template
+            .replace("{index}", &index)
+            .replace("{comment}", &comment)
+            .replace("{first_entry}", &first_entry)
+    }
+
+    /// The file names to write each of `zips` to, as `output_name` gives them but with the index
+    /// put in ahead of the extension of any already taken by an earlier archive.
+    ///
+    /// `{first_entry}` is the same for any two JARs (`META-INF`), and firmware images tend to
+    /// share a comment, so without this the later archive would overwrite the earlier.
+    pub fn output_names(&self, zips: &[ZipFile]) -> Vec<String> {
+        let mut names: Vec<String> = Vec::with_capacity(zips.len());
+        for (i, zip) in zips.iter().enumerate() {
+            let mut name = self.output_name(i, zip);
+            while names.contains(&name) {
+                name = with_index(&name, i);
+            }
+            names.push(name);
+        }
+        names
+    }
+
+    /// The container prefix length to use for archives in `fs`
+    pub fn prefix_len(&self, fs: &FragSys) -> usize {
+        match self.archive_prefix_len {
+            Some(len) => len,
+            None => self.container_magic
+                .as_ref()
+                .and_then(|magic| fs.detect_archive_prefix(magic))
+                .unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_names_unique() {
+        // Two archives with the same comment, and the same layout
+        let mut dump = vec![0u8; 0x10];
+        dump.extend(::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000));
+        dump.extend(vec![0u8; 0x40]);
+        dump.extend(::testgen::stored_archive(&[("a.txt", &b"world"[..])], 1_500_000_000));
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let mut zips = fs.find_zips();
+        assert_eq!(zips.len(), 2);
+        for zip in &mut zips {
+            zip.eocd.zip_cmt = "firmware".to_owned();
+        }
+
+        let opts = DefragOptions {
+            output_name_template: Some("{comment}.zip".to_owned()),
+            ..DefragOptions::default()
+        };
+        assert_eq!(opts.output_name(1, &zips[1]), "firmware.zip");
+        assert_eq!(opts.output_names(&zips), vec!["firmware.zip", "firmware-1.zip"]);
+
+        // With nothing to collide there's nothing to change
+        assert_eq!(DefragOptions::default().output_names(&zips), vec!["0.zip", "1.zip"]);
+    }
+}
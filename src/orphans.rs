@@ -0,0 +1,85 @@
+//! Summing up what's left in the pool once reconstruction's done with a dump.
+//!
+//! Whatever no archive claimed could be a missed archive, something that was never a zip in the
+//! first place, or fragments of an archive we've lost the rest of. A quick look at how random the
+//! leftovers are and whether any header magics turn up in them usually says which.
+
+use std::cmp;
+
+use chunks::{scan_bytes, FragSys, Magic, Page};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Summary of the pages left in a `FragSys` pool that no archive was assigned
+pub struct OrphanReport {
+    /// Number of pages left over
+    pub pages: usize,
+    /// Total bytes in those pages
+    pub bytes: usize,
+    /// Number of pages by entropy, bucketed by whole bits per byte (so `entropy_histogram[7]` is
+    /// pages of 7 to 8 bits per byte, which is compressed or encrypted data)
+    pub entropy_histogram: [usize; 8],
+    /// Header magics found in the leftover pages, by dump offset. Any EOCDs or CDs in here
+    /// suggest an archive the EOCD scan missed.
+    pub magics: Vec<(usize, Magic)>,
+}
+
+impl FragSys {
+    /// Summarise the pages still in the pool, i.e. those no recovered archive accounted for.
+    pub fn orphan_page_report(&self) -> OrphanReport {
+        let mut report = OrphanReport::default();
+        for page in &self.pages {
+            let range = match *page {
+                Page::Assigned(ref range) => range,
+                Page::Unassigned | Page::Synthesized(_) => continue,
+            };
+            // The last page of the dump may well be short
+            let end = cmp::min(range.end, self.data.len());
+            let start = cmp::min(range.start, end);
+            let bytes = &self.data[start..end];
+
+            report.pages += 1;
+            report.bytes += bytes.len();
+            let bucket = cmp::min(page.entropy(&self.data) as usize, 7);
+            report.entropy_histogram[bucket] += 1;
+
+            for &magic in &[Magic::EOCD, Magic::CD, Magic::LF, Magic::DD] {
+                let pattern = self.magics().get(magic);
+                if pattern.is_empty() {
+                    continue;
+                }
+                report.magics
+                    .extend(scan_bytes(bytes, pattern, 1).into_iter().map(|i| (start + i, magic)));
+            }
+        }
+        report.magics.sort_by_key(|&(ptr, _)| ptr);
+        if report.magics.iter().any(|&(_, magic)| magic == Magic::EOCD || magic == Magic::CD) {
+            info!("Leftover pages still hold directory magics, there may be a missed archive");
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chunks::{FragSys, Magic};
+    use options::DefragOptions;
+    use reconstruct;
+
+    #[test]
+    fn orphaned_lf_reported() {
+        // On a page of its own, the local header and data of an entry from an archive whose
+        // central directory and EOCD are long gone, then a whole archive
+        let lost = ::testgen::stored_archive(&[("b.txt", &b"orphan"[..])], 1_500_000_000);
+        let mut dump = lost[..30 + 5 + 6].to_vec();
+        dump.extend(vec![0u8; 0x40 - dump.len() + 0x10]);
+        dump.extend(::testgen::stored_archive(&[("a.txt", &b"hello"[..])], 1_500_000_000));
+
+        let mut fs = FragSys::from_bytes(dump, 0x40);
+        let zips = reconstruct(&mut fs, &DefragOptions::default());
+        assert_eq!(zips.len(), 1);
+
+        let report = fs.orphan_page_report();
+        assert_eq!((report.pages, report.bytes), (1, 0x40));
+        assert_eq!(report.magics, vec![(0, Magic::LF)]);
+    }
+}
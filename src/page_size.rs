@@ -0,0 +1,260 @@
+//! Heuristics for working out the page size of a dump when nobody's told us.
+//!
+//! Two signals go into it. Header magics land at consistent offsets within their pages at the
+//! right page size, which is strong evidence when there are plenty of them. And page boundaries in
+//! a fragmented dump are where unrelated data butts up against each other, so the bytes either
+//! side of a true boundary differ by more than neighbouring bytes usually do, which needs no
+//! headers at all. A candidate twice the true size shows the same boundary effect but half the
+//! magic consistency, and one half the size shows the same magic consistency but half the boundary
+//! effect, so the two together peak at the right size.
+
+use std::collections::HashMap;
+
+use chunks::FragSys;
+use options::DefragOptions;
+use reconstruct;
+
+/// Page size to fall back on when there's nothing to detect one from
+pub const DEFAULT_PAGE_SIZE: usize = 0x400;
+
+/// Page sizes worth trying when the caller has no better idea
+pub const CANDIDATE_PAGE_SIZES: &[usize] = &[0x200, 0x400, 0x800, 0x1000, 0x2000, 0x4000];
+
+/// Number of magics at which the alignment signal gets full weight; with fewer it's trusted
+/// proportionally less.
+const MAGICS_FOR_FULL_WEIGHT: usize = 16;
+
+/// Scale a set of scores so the best is 1.0, leaving them all at 0.0 if none are positive.
+fn normalise(scores: &[f64]) -> Vec<f64> {
+    let best = scores.iter().cloned().fold(0.0, f64::max);
+    scores
+        .iter()
+        .map(|&score| if best > 0.0 { score / best } else { 0.0 })
+        .collect()
+}
+
+impl FragSys {
+    /// Re-page the dump at whatever page size `detect_page_size` settles on from amongst
+    /// `CANDIDATE_PAGE_SIZES`, staying put if nothing stands out.
+    pub fn with_detected_page_sz(self) -> Self {
+        match self.detect_page_size(CANDIDATE_PAGE_SIZES) {
+            Some(page_sz) if page_sz != self.page_sz() => {
+                info!("Detected a page size of {:#x}", page_sz);
+                self.with_page_sz(page_sz)
+            }
+            Some(_) => self,
+            None => {
+                warn!("Couldn't detect a page size, sticking with {:#x}", self.page_sz());
+                self
+            }
+        }
+    }
+
+    /// For each of `candidates`, the fraction of LF and CD magics sharing the most common offset
+    /// within a page of that size, along with the number of magics it's based on.
+    pub fn alignment_scores(&self, candidates: &[usize]) -> (Vec<f64>, usize) {
+        let mut ptrs = self.find_lfs();
+        ptrs.extend(self.find_cd_ptrs());
+        let scores = candidates
+            .iter()
+            .map(|&pagesz| {
+                let mut offsets: HashMap<usize, usize> = HashMap::new();
+                for ptr in &ptrs {
+                    *offsets.entry(ptr % pagesz).or_insert(0) += 1;
+                }
+                match offsets.values().max() {
+                    Some(&peak) => peak as f64 / ptrs.len() as f64,
+                    None => 0.0,
+                }
+            })
+            .collect();
+        (scores, ptrs.len())
+    }
+
+    /// How much more the bytes either side of a boundary between pages of `pagesz` differ than
+    /// neighbouring bytes do on average, as a ratio less one (so no effect at all is 0.0).
+    fn boundary_excess(&self, pagesz: usize, overall: f64) -> f64 {
+        let data = &self.data;
+        if pagesz == 0 || overall == 0.0 || data.len() <= pagesz {
+            return 0.0;
+        }
+        let boundaries = (data.len() - 1) / pagesz;
+        let across = (1..boundaries + 1)
+            .map(|k| (i32::from(data[k * pagesz]) - i32::from(data[k * pagesz - 1])).abs() as f64)
+            .sum::<f64>() / boundaries as f64;
+        (across / overall - 1.0).max(0.0)
+    }
+
+    /// For each of `candidates`, how much stronger the boundary effect is at that page size than
+    /// at half of it.
+    ///
+    /// Every multiple of the true page size shows the full effect, so it's the gain over the half
+    /// size that singles out the true one: half the true size only has every other boundary on a
+    /// real one, and double the true size gains nothing over the true size itself.
+    pub fn period_scores(&self, candidates: &[usize]) -> Vec<f64> {
+        let data = &self.data;
+        if data.len() < 2 {
+            return vec![0.0; candidates.len()];
+        }
+        let overall = (1..data.len())
+            .map(|i| (i32::from(data[i]) - i32::from(data[i - 1])).abs() as f64)
+            .sum::<f64>() / (data.len() - 1) as f64;
+
+        candidates
+            .iter()
+            .map(|&pagesz| {
+                (self.boundary_excess(pagesz, overall) -
+                     self.boundary_excess(pagesz / 2, overall))
+                    .max(0.0)
+            })
+            .collect()
+    }
+
+    /// Guess the page size of the dump from amongst `candidates`, or `None` if nothing stands out.
+    ///
+    /// The magic alignment and boundary discontinuity signals are each scaled so the best
+    /// candidate scores 1.0 and then summed, with the alignment signal trusted less where there are
+    /// only a few magics to go on. Ties go to the larger page size, since any alignment to a page
+    /// size holds for its divisors too.
+    pub fn detect_page_size(&self, candidates: &[usize]) -> Option<usize> {
+        let mut candidates = candidates.to_vec();
+        candidates.sort();
+        let candidates = &candidates[..];
+
+        let (alignment, magics) = self.alignment_scores(candidates);
+        let weight = (magics as f64 / MAGICS_FOR_FULL_WEIGHT as f64).min(1.0);
+        let alignment = normalise(&alignment);
+        let period = normalise(&self.period_scores(candidates));
+
+        let mut best: Option<(usize, f64)> = None;
+        for (i, &pagesz) in candidates.iter().enumerate() {
+            let score = weight * alignment[i] + period[i];
+            debug!("Page size {:#x}: alignment {}, period {}, combined {}",
+                   pagesz, alignment[i], period[i], score);
+            if score > 0.0 && best.map_or(true, |(_, best_score)| score >= best_score) {
+                best = Some((pagesz, score));
+            }
+        }
+        best.map(|(pagesz, _)| pagesz)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// How reconstruction went at one candidate page size, for comparing candidates in a sweep
+pub struct PageSizeTrial {
+    /// Page size tried
+    pub page_sz: usize,
+    /// Number of distinct parseable EOCDs found
+    pub eocds: usize,
+    /// Number of parseable CDs found
+    pub cds: usize,
+    /// Number of archives reconstructed
+    pub clusters: usize,
+    /// Mean `ZipFile::byte_completion` across the reconstructed archives
+    pub byte_completion: f64,
+    /// Mean `ZipFile::confidence` across the reconstructed archives
+    pub confidence: f64,
+    /// Number of entries which extract with a CRC matching their CD
+    pub verified_entries: usize,
+}
+
+/// Mean of `scores`, or 0.0 when there aren't any.
+fn mean<I: Iterator<Item = f64>>(scores: I) -> f64 {
+    let (total, count) = scores.fold((0.0, 0usize), |(total, count), score| {
+        (total + score, count + 1)
+    });
+    if count == 0 { 0.0 } else { total / count as f64 }
+}
+
+/// Reconstruct `data` at each of `candidates` in turn, reporting how each went.
+///
+/// A clear winner on `verified_entries` is a safe choice; two sizes coming out close suggests
+/// the dump isn't fragmented uniformly and deserves a closer look.
+pub fn sweep_page_sizes(data: &[u8], candidates: &[usize], opts: &DefragOptions)
+    -> Vec<PageSizeTrial>
+{
+    candidates
+        .iter()
+        .map(|&page_sz| {
+            let mut fs = FragSys::from_bytes(data.to_vec(), page_sz);
+            fs.set_magics(opts.magics.clone());
+            let eocds = fs.find_eocd_groups().len();
+            let cds = fs.find_cds().len();
+            let zips = reconstruct(&mut fs, opts);
+
+            let byte_completion = mean(zips.iter().map(|zip| zip.byte_completion(page_sz)));
+            let confidence = mean(zips.iter().map(|zip| zip.confidence(&fs.data, page_sz)));
+            let verified_entries = zips.iter()
+                .map(|zip| {
+                    zip.verify_crcs(&fs.data, page_sz)
+                        .into_iter()
+                        .filter(|&(_, ref result)| match *result {
+                            Ok(matched) => matched,
+                            Err(_) => false,
+                        })
+                        .count()
+                })
+                .sum();
+
+            let trial = PageSizeTrial {
+                page_sz: page_sz,
+                eocds: eocds,
+                cds: cds,
+                clusters: zips.len(),
+                byte_completion: byte_completion,
+                confidence: confidence,
+                verified_entries: verified_entries,
+            };
+            info!("{:?}", trial);
+            trial
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use page_size::*;
+
+    #[test]
+    fn detects_0x800_alignment() {
+        // 32 pages of 0x800, each a different filler byte with a local header magic 0x10 in
+        let mut data = vec![];
+        for pg in 0..32 {
+            let mut page = vec![(pg * 37 + 11) as u8; 0x800];
+            page[0x10..0x14].copy_from_slice(b"PK\x03\x04");
+            data.extend(page);
+        }
+        let fs = FragSys::from_bytes(data, DEFAULT_PAGE_SIZE);
+        assert_eq!(fs.detect_page_size(CANDIDATE_PAGE_SIZES), Some(0x800));
+
+        let fs = fs.with_detected_page_sz();
+        assert_eq!(fs.page_sz(), 0x800);
+        assert_eq!(fs.pages.len(), 32);
+    }
+
+    #[test]
+    fn sweep_prefers_true_page_size() {
+        // Four entries of exactly one 0x200 page each, then the directory, with each pair of
+        // entry pages swapped. At 0x200 that's just a shuffle; at 0x400 every page comes out with
+        // its halves the wrong way round.
+        let names = ["a.txt", "b.txt", "c.txt", "d.txt"];
+        let contents = (0..4).map(|i| vec![b'a' + i as u8; 0x200 - 30 - 5]).collect::<Vec<_>>();
+        let files = names.iter()
+            .zip(&contents)
+            .map(|(&name, data)| (name, &data[..]))
+            .collect::<Vec<_>>();
+        let archive = ::testgen::stored_archive(&files, 1_500_000_000);
+        let mut dump = vec![];
+        for &pg in &[1, 0, 3, 2] {
+            dump.extend_from_slice(&archive[pg * 0x200..(pg + 1) * 0x200]);
+        }
+        dump.extend_from_slice(&archive[0x800..]);
+
+        let trials = sweep_page_sizes(&dump, &[0x200, 0x400], &DefragOptions::default());
+        assert_eq!(trials.iter().map(|trial| trial.page_sz).collect::<Vec<_>>(),
+                   vec![0x200, 0x400]);
+        assert_eq!(trials[0].verified_entries, 4);
+        assert!(trials[1].verified_entries < trials[0].verified_entries);
+        assert!(trials[0].confidence > 0.0 && trials[0].confidence <= 1.0);
+    }
+}
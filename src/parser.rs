@@ -1,14 +1,40 @@
 //! Module containing the various nom adapters for parsing Zip file header chunks into the
 //! appropriate data structures.
 
-use nom::{le_u16, le_u32};
+use nom::{le_u16, le_u32, le_u64, IResult, Needed};
 use chrono;
-use chunks::{EOCD, CD, LF, DD, ZipFlags};
+use chunks::{EOCD, EOCD64, EOCD64Locator, CD, LF, DD, DD64, ExtraField, Unix1, ZipFlags, UTF};
+use cp437;
 
-named!(#[doc = "Try to parse an `EOCD` End of Central Directory header"],
-       pub parse_eocd<&[u8],EOCD>,
+/// Try to parse an `EOCD` End of Central Directory header
+pub fn parse_eocd(input: &[u8]) -> IResult<&[u8], EOCD> {
+    parse_eocd_with(input, b"PK\x05\x06")
+}
+
+/// Try to parse an `EOCD` header introduced by a non-standard `magic`
+pub fn parse_eocd_with<'a>(input: &'a [u8], magic: &[u8]) -> IResult<&'a [u8], EOCD> {
+    preceded!(input, tag!(magic), parse_eocd_fields)
+}
+
+/// Parse the fields of an `EOCD` End of Central Directory header following its magic
+///
+/// The comment is attached best-effort: if it runs off the end of the input, or isn't valid
+/// UTF-8 (most likely because it fell on a page we lost), we keep what we can of it rather than
+/// throw away a perfectly good set of offsets.
+pub fn parse_eocd_fields(input: &[u8]) -> IResult<&[u8], EOCD> {
+    match parse_eocd_fixed(input) {
+        IResult::Done(rest, mut eocd) => {
+            let len = ::std::cmp::min(eocd.cmt_len as usize, rest.len());
+            eocd.zip_cmt = String::from_utf8_lossy(&rest[..len]).into_owned();
+            IResult::Done(&rest[len..], eocd)
+        }
+        other => other,
+    }
+}
+
+named!(#[doc = "Parse the fixed fields of an `EOCD` following its magic, leaving the comment empty"],
+       pub parse_eocd_fixed<&[u8],EOCD>,
        do_parse!(
-           tag!("PK\x05\x06")  >>
            dsk_no:      le_u16 >>
            dsk_w_cd:    le_u16 >>
            dsk_entries: le_u16 >>
@@ -16,7 +42,6 @@ named!(#[doc = "Try to parse an `EOCD` End of Central Directory header"],
            cd_sz:       le_u32 >>
            cd_offset:   le_u32 >>
            cmt_len:     le_u16 >>
-           zip_cmt: take_str!(cmt_len)>>
            (EOCD{
                dsk_no:      dsk_no,
                dsk_w_cd:    dsk_w_cd,
@@ -25,7 +50,54 @@ named!(#[doc = "Try to parse an `EOCD` End of Central Directory header"],
                cd_sz:       cd_sz,
                cd_offset:   cd_offset,
                cmt_len:     cmt_len,
-               zip_cmt:     String::from(zip_cmt),
+               zip_cmt:     String::new(),
+           })
+           )
+       );
+
+/// Try to parse an `EOCD64` Zip64 End of Central Directory record
+pub fn parse_eocd64(input: &[u8]) -> IResult<&[u8], EOCD64> {
+    preceded!(input, tag!(&b"PK\x06\x06"[..]), parse_eocd64_fields)
+}
+
+named!(#[doc = "Parse the fixed fields of an `EOCD64` following its magic, skipping any extensible \
+                data"],
+       pub parse_eocd64_fields<&[u8],EOCD64>,
+       do_parse!(
+           rec_sz:      le_u64 >>
+           v_made_by:   le_u16 >>
+           v_needed:    le_u16 >>
+           dsk_no:      le_u32 >>
+           dsk_w_cd:    le_u32 >>
+           dsk_entries: le_u64 >>
+           tot_entries: le_u64 >>
+           cd_sz:       le_u64 >>
+           cd_offset:   le_u64 >>
+           (EOCD64{
+               rec_sz:      rec_sz,
+               v_made_by:   v_made_by,
+               v_needed:    v_needed,
+               dsk_no:      dsk_no,
+               dsk_w_cd:    dsk_w_cd,
+               dsk_entries: dsk_entries,
+               tot_entries: tot_entries,
+               cd_sz:       cd_sz,
+               cd_offset:   cd_offset,
+           })
+           )
+       );
+
+named!(#[doc = "Parse a Zip64 End of Central Directory locator"],
+       pub parse_eocd64_locator<&[u8],EOCD64Locator>,
+       do_parse!(
+           tag!(&b"PK\x06\x07"[..]) >>
+           disk_with_eocd64: le_u32 >>
+           eocd64_offset:    le_u64 >>
+           total_disks:      le_u32 >>
+           (EOCD64Locator{
+               disk_with_eocd64: disk_with_eocd64,
+               eocd64_offset:    eocd64_offset,
+               total_disks:      total_disks,
            })
            )
        );
@@ -58,29 +130,81 @@ named!(#[doc = "Parse an MS-DOS formatted date to YMD tuple. Damn you Bill"],
         )
     );
 
+/// Convert parsed DOS (HMS, YMD) tuples to epoch, if they make a real date. The field bounds
+/// checks let through things like the 30th of February, and chrono would panic on those.
+fn dosdatetime_to_epoch(td: ((u32,u32,u32),(i32,u32,u32))) -> Option<u32> {
+    let (t, d) = td;
+    chrono::NaiveDate::from_ymd_opt(d.0,d.1,d.2)
+        .and_then(|date| date.and_hms_opt(t.0,t.1,t.2))
+        .map(|datetime| datetime.timestamp() as u32)
+}
+
 named!(#[doc = "Parse an MS-DOS formatted datetime and convert to epoch"],
        pub parse_dosdatetime<&[u8],u32>,
-       do_parse!(
-           t: parse_dostime            >>
-           d: parse_dosdate            >>
-            (
-                chrono::NaiveDate::from_ymd(d.0,d.1,d.2)
-                    .and_hms(t.0,t.1,t.2)
-                    .timestamp() as u32
-            )
+       map_opt!(
+           do_parse!(
+               t: parse_dostime        >>
+               d: parse_dosdate        >>
+               ((t, d))
+           ),
+           dosdatetime_to_epoch
        )
     );
 
-named!(#[doc = "Parse a `CD` Central Directory header"],
-       pub parse_cd<&[u8],CD>,
+/// Parse an MS-DOS formatted datetime as `parse_dosdatetime` does, but rather than failing on one
+/// that isn't a real date and time, yield a timestamp of 0 and `false` for its validity.
+///
+/// Zeroed and corrupt timestamps are common enough in synthesized archives that failing the
+/// header over them loses entries whose filenames and offsets are otherwise perfectly good.
+pub fn parse_dosdatetime_lenient(input: &[u8]) -> IResult<&[u8], (u32, bool)> {
+    if input.len() < 4 {
+        return IResult::Incomplete(Needed::Size(4));
+    }
+    match parse_dosdatetime(input) {
+        IResult::Done(rest, timestamp) => IResult::Done(rest, (timestamp, true)),
+        _ => IResult::Done(&input[4..], (0, false)),
+    }
+}
+
+/// Copy the 4 bytes of a DOS date and time out of the input, for writing back verbatim
+fn raw_dos_datetime(raw: &[u8]) -> [u8; 4] {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(raw);
+    bytes
+}
+
+/// Decode a raw filename as UTF-8 if the `gp_flags` say so, or as CP437 otherwise.
+///
+/// Flagged names which turn out not to be valid UTF-8 are decoded lossily rather than losing the
+/// whole header over them, which is why the headers hang on to the raw bytes as well.
+pub fn decode_filename(raw: &[u8], gp_flags: u16) -> String {
+    if ZipFlags::from_bits_truncate(gp_flags).contains(UTF) {
+        String::from_utf8_lossy(raw).into_owned()
+    } else {
+        cp437::decode(raw)
+    }
+}
+
+/// Parse a `CD` Central Directory header
+pub fn parse_cd(input: &[u8]) -> IResult<&[u8], CD> {
+    parse_cd_with(input, b"PK\x01\x02")
+}
+
+/// Parse a `CD` Central Directory header introduced by a non-standard `magic`
+pub fn parse_cd_with<'a>(input: &'a [u8], magic: &[u8]) -> IResult<&'a [u8], CD> {
+    preceded!(input, tag!(magic), parse_cd_fields)
+}
+
+named!(#[doc = "Parse the fields of a `CD` Central Directory header following its magic"],
+       pub parse_cd_fields<&[u8],CD>,
        do_parse!(
-           tag!(b"PK\x01\x02")           >>
            v_made_by:  le_u16            >> // Version data ought to be raw bytes but
                                             // too lazy to write type specific parser
            v_needed:   le_u16            >>
            gp_flags:   le_u16            >>
            method:     le_u16            >>
-           timestamp:  parse_dosdatetime     >> // Dos time data parsed to unix epoch w00t w00t
+           raw_dt:     peek!(take!(4))   >>
+           timestamp:  parse_dosdatetime_lenient >> // Dos time data parsed to unix epoch w00t w00t
            dd:         parse_dd          >>
            fn_len:     le_u16            >>
            ef_len:     le_u16            >>
@@ -89,13 +213,17 @@ named!(#[doc = "Parse a `CD` Central Directory header"],
            int_attr:   le_u16            >>
            ext_attr:   le_u32            >>
            lf_offset:  le_u32            >>
-           filename:   take_str!(fn_len)   >>
+           filename:   take!(fn_len)     >>
+           extra:      call!(parse_extra_fields, ef_len) >>
+           comment:    take!(fc_len)     >>
            (CD {
                v_made_by:  v_made_by,
                v_needed:   v_needed,
                gp_flags:   ZipFlags::from_bits_truncate(gp_flags),
                method:     method,
-               timestamp:  timestamp,
+               timestamp:  timestamp.0,
+               timestamp_valid: timestamp.1,
+               raw_datetime: Some(raw_dos_datetime(raw_dt)),
                dd:         dd,
                fn_len:     fn_len,
                ef_len:     ef_len,
@@ -104,42 +232,123 @@ named!(#[doc = "Parse a `CD` Central Directory header"],
                int_attr:   int_attr,
                ext_attr:   ext_attr,
                lf_offset:  lf_offset,
-               filename:   String::from(filename),
+               filename:   decode_filename(filename, gp_flags),
+               raw_filename: Some(filename.to_vec()),
+               extra:      extra,
+               file_comment: decode_filename(comment, gp_flags),
+               raw_comment: Some(comment.to_vec()),
                 })
             )
        );
 
-named!(#[doc = "Parse a `LF` local file header"],
-       pub parse_lf<&[u8],LF>,
+/// Parse a `LF` local file header
+pub fn parse_lf(input: &[u8]) -> IResult<&[u8], LF> {
+    parse_lf_with(input, b"PK\x03\x04")
+}
+
+/// Parse a `LF` local file header introduced by a non-standard `magic`
+pub fn parse_lf_with<'a>(input: &'a [u8], magic: &[u8]) -> IResult<&'a [u8], LF> {
+    preceded!(input, tag!(magic), parse_lf_fields)
+}
+
+named!(#[doc = "Parse the fields of a `LF` local file header following its magic"],
+       pub parse_lf_fields<&[u8],LF>,
        do_parse!(
-           tag!(b"PK\x03\x04")          >>
            v_needed:  le_u16            >>
            gp_flag:   le_u16            >>
            method:    le_u16            >>
-           timestamp: parse_dosdatetime     >>
+           raw_dt:    peek!(take!(4))   >>
+           timestamp: parse_dosdatetime_lenient >>
            dd:        parse_dd          >>
            fn_len:    le_u16            >>
            ef_len:    le_u16            >>
-           filename:  take_str!(fn_len) >>
+           filename:  take!(fn_len)     >>
+           extra:     call!(parse_extra_fields, ef_len) >>
            (LF{
                v_needed: v_needed,
                gp_flags: ZipFlags::from_bits_truncate(gp_flag),
                method: method,
-               timestamp: timestamp,
+               timestamp: timestamp.0,
+               raw_datetime: Some(raw_dos_datetime(raw_dt)),
                dd: dd,                      // Need to do wrap this in a result for
                                             // case where DD elsewhere
                fn_len: fn_len,
                ef_len: ef_len,
-               filename: String::from(filename),
+               filename: decode_filename(filename, gp_flag),
+               raw_filename: Some(filename.to_vec()),
+               extra: extra,
            }))
        );
 
-named!(#[doc = "Parse a Data Descriptor"],
-       pub parse_dd<&[u8],DD>,
+named!(#[doc = "Parse a single extra field record (header ID, length and raw payload)"],
+       pub parse_extra_field<&[u8],ExtraField>,
+       do_parse!(
+           id:   le_u16     >>
+           len:  le_u16     >>
+           data: take!(len) >>
+           (ExtraField{
+               id:   id,
+               len:  len,
+               data: Vec::from(data),
+           }))
+       );
+
+named!(#[doc = "Parse a block of extra field records, stopping at the first one that doesn't fit"],
+       extra_field_records<&[u8],Vec<ExtraField> >,
+       many0!(complete!(parse_extra_field))
+    );
+
+/// Parse the `ef_len` bytes of extra field following a header's filename into its records.
+///
+/// This is best-effort: a mangled extra field shouldn't cost us the header it's attached to, so
+/// anything unparseable is just dropped, though the whole `ef_len` bytes are always consumed.
+pub fn parse_extra_fields(input: &[u8], ef_len: u16) -> IResult<&[u8], Vec<ExtraField>> {
+    map!(input, take!(ef_len), |raw| match extra_field_records(raw) {
+        IResult::Done(_, fields) => fields,
+        _ => Vec::new(),
+    })
+}
+
+named!(#[doc = "Parse the payload of an Info-ZIP Unix1 (`0x5855`) extra field"],
+       pub parse_unix1<&[u8],Unix1>,
+       do_parse!(
+           atime: le_u32                        >>
+           mtime: le_u32                        >>
+           ids:   opt!(complete!(take!(4)))     >> // uid/gid only appear in local headers
+           (Unix1{
+               atime: atime,
+               mtime: mtime,
+               ids:   ids.map(Vec::from),
+           }))
+       );
+
+named!(#[doc = "Parse a central directory digital signature record, returning the signature data"],
+       pub parse_cd_signature<&[u8],&[u8]>,
+       do_parse!(
+           tag!("PK\x05\x05")  >>
+           sz:   le_u16         >>
+           data: take!(sz)      >>
+           (data)
+       )
+    );
+
+/// Parse a Data Descriptor
+pub fn parse_dd(input: &[u8]) -> IResult<&[u8], DD> {
+    parse_dd_with(input, b"PK\x07\x08")
+}
+
+/// Parse a Data Descriptor, optionally introduced by a non-standard `magic`
+pub fn parse_dd_with<'a>(input: &'a [u8], magic: &[u8]) -> IResult<&'a [u8], DD> {
+    preceded!(input,
+              opt!(tag!(magic)), // Sometimes we're looking for this data
+                                 // as a separate header, sometimes it's
+                                 // buried in an LFH/CD
+              parse_dd_fields)
+}
+
+named!(#[doc = "Parse the fields of a Data Descriptor"],
+       pub parse_dd_fields<&[u8],DD>,
        do_parse!(
-            opt!(tag!(b"PK\x07\x08")) >> // Sometimes we're looking for this data
-                                         // as a separate header, sometimes it's
-                                         // buried in an LFH/CD
             crc: le_u32   >>
             z_sz: le_u32  >>
             u_sz: le_u32  >>
@@ -151,9 +360,40 @@ named!(#[doc = "Parse a Data Descriptor"],
         )
     );
 
+/// Parse a Zip64 Data Descriptor, optionally introduced by a non-standard `magic`
+pub fn parse_dd64_with<'a>(input: &'a [u8], magic: &[u8]) -> IResult<&'a [u8], DD64> {
+    preceded!(input, opt!(tag!(magic)), parse_dd64_fields)
+}
+
+named!(#[doc = "Parse the fields of a Zip64 Data Descriptor (with 8 byte sizes)"],
+       pub parse_dd64_fields<&[u8],DD64>,
+       do_parse!(
+            crc: le_u32   >>
+            z_sz: le_u64  >>
+            u_sz: le_u64  >>
+            (DD64{
+                crc32: crc,
+                z_sz:  z_sz,
+                u_sz:  u_sz,
+            })
+        )
+    );
+
+/// Parse the Data Descriptor trailing an entry's data, with 8 byte sizes if the entry is `zip64`
+pub fn parse_trailing_dd_with<'a>(input: &'a [u8], magic: &[u8], zip64: bool)
+    -> IResult<&'a [u8], DD64>
+{
+    if zip64 {
+        parse_dd64_with(input, magic)
+    } else {
+        map!(input, call!(parse_dd_with, magic), DD64::from)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use parser::*;
+    use chunks::{dostime_to_bytes, EF_EXTENDED_TIMESTAMP};
 
     #[test]
     fn dostimestamp() {
@@ -166,6 +406,23 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn dostimestamp_impossible_date() {
+        // Midnight on the 30th of February 1980
+        let raw_dostimestamp = b"\x00\x00\x5e\x00";
+        assert!(parse_dosdatetime(raw_dostimestamp).is_err());
+    }
+
+    #[test]
+    fn dostimestamp_roundtrip() {
+        let raw_dostimestamp = b"\x69\x8c\x9d\x48";
+        let (_, parsed) = parse_dosdatetime(raw_dostimestamp).unwrap();
+        assert_eq!(&dostime_to_bytes(parsed), raw_dostimestamp);
+
+        // Odd seconds can't be represented, and truncate down like the zip writer would
+        assert_eq!(&dostime_to_bytes(parsed + 1), raw_dostimestamp);
+    }
+
     #[test]
     fn eocd_test() {
         let raw_eocd = b"PK\x05\x06\x00\x00\x00\x00\x9c\x03\x9c\x03\xbf\
@@ -178,6 +435,50 @@ mod tests {
         assert_eq!(parsed.tot_entries, 924); // Zip file has 924 records
     }
 
+    #[test]
+    fn eocd64_test() {
+        // 70000 entries in a 5.6MB central directory 6GB into the archive
+        let raw_eocd64 = b"PK\x06\x06\x2c\x00\x00\x00\x00\x00\x00\x00\x2d\x03\x2d\x00\
+                           \x00\x00\x00\x00\x00\x00\x00\x00\x70\x11\x01\x00\x00\x00\
+                           \x00\x00\x70\x11\x01\x00\x00\x00\x00\x00\xf0\x6f\x55\x00\
+                           \x00\x00\x00\x00\x00\x00\x00\x80\x01\x00\x00\x00";
+
+        let (rest, parsed) = parse_eocd64(raw_eocd64).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed.rec_sz, 44);
+        assert_eq!(parsed.tot_entries, 70000);
+        assert_eq!(parsed.cd_sz, 0x556ff0);
+        assert_eq!(parsed.cd_offset, 0x1_8000_0000);
+    }
+
+    #[test]
+    fn eocd64_locator_test() {
+        // A locator pointing 6GB in, followed by an EOCD of sentinels
+        let raw = b"PK\x06\x07\x00\x00\x00\x00\x38\x70\x55\x80\x01\x00\x00\x00\
+                    \x01\x00\x00\x00PK\x05\x06\x00\x00\x00\x00\xff\xff\xff\xff\
+                    \xff\xff\xff\xff\xff\xff\xff\xff\x00\x00";
+
+        let (rest, locator) = parse_eocd64_locator(raw).unwrap();
+        assert_eq!(locator.disk_with_eocd64, 0);
+        assert_eq!(locator.eocd64_offset, 0x1_8055_7038);
+        assert_eq!(locator.total_disks, 1);
+
+        let (_, eocd) = parse_eocd(rest).unwrap();
+        assert!(eocd.has_zip64_sentinels());
+    }
+
+    #[test]
+    fn eocd_truncated_comment() {
+        // 16 byte comment of which we only have 5, the last of them not UTF-8
+        let raw_eocd = b"PK\x05\x06\x00\x00\x00\x00\x9c\x03\x9c\x03\xbf\
+                         \xdb\x00\x00\nm\t\x00\x10\x00bui\xffd";
+
+        let (_, parsed) = parse_eocd(raw_eocd).unwrap();
+        assert_eq!(parsed.tot_entries, 924);
+        assert_eq!(parsed.cmt_len, 16);
+        assert_eq!(parsed.zip_cmt, "bui\u{fffd}d");
+    }
+
     #[test]
     fn cd_headertest() {
         //macro_rules! nom_res {
@@ -194,6 +495,161 @@ mod tests {
         assert_eq!(parsed.filename, "b.class".to_string());
     }
 
+    #[test]
+    fn cd_unparse_verbatim_test() {
+        // cd_headertest's CD
+        let raw_cd = b"PK\x01\x02\x14\x00\x14\x00\x08\x08\x08\x00i\x8c\
+                       \x9dH\x1f\xcd]z/\x11\x00\x00,'\x00\x00\x07\x00\
+                       \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                       \xd1\x02\x00\x00b.classPK\x01\x02\x14\x00\x14";
+
+        let (rest, parsed) = parse_cd(raw_cd).unwrap();
+        assert_eq!(parsed.raw_datetime, Some(*b"i\x8c\x9dH"));
+        assert_eq!(&parsed.unparse()[..], &raw_cd[..raw_cd.len() - rest.len()]);
+
+        // And through to the local header
+        let lf = LF::from(&parsed).unparse();
+        assert_eq!(&lf[10..14], b"i\x8c\x9dH");
+        assert_eq!(parse_lf(&lf).unwrap().1.raw_datetime, parsed.raw_datetime);
+    }
+
+    #[test]
+    fn cd_unparse_invalid_utf8_test() {
+        // cd_unparse_verbatim_test's CD, which is flagged as UTF-8, with a byte of its name that
+        // isn't and a comment that isn't either
+        let raw_cd = b"PK\x01\x02\x14\x00\x14\x00\x08\x08\x08\x00i\x8c\
+                       \x9dH\x1f\xcd]z/\x11\x00\x00,'\x00\x00\x07\x00\
+                       \x00\x00\x02\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                       \xd1\x02\x00\x00b\xffclass\xfe!";
+
+        let (_, parsed) = parse_cd(raw_cd).unwrap();
+        assert_eq!(parsed.filename, "b\u{fffd}class");
+        assert_eq!(parsed.file_comment, "\u{fffd}!");
+        assert_eq!(&parsed.unparse()[..], &raw_cd[..]);
+
+        let lf = LF::from(&parsed).unparse();
+        assert_eq!(&lf[26..28], b"\x07\x00");
+        assert_eq!(&lf[30..], b"b\xffclass");
+    }
+
+    #[test]
+    fn cd_unix1_test() {
+        // cd_headertest's CD with a 12 byte Unix1 extra field tacked on
+        let raw_cd = b"PK\x01\x02\x14\x00\x14\x00\x08\x08\x08\x00i\x8c\
+                       \x9dH\x1f\xcd]z/\x11\x00\x00,'\x00\x00\x07\x00\
+                       \x0c\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                       \xd1\x02\x00\x00b.classUX\x08\x00\xce\xa8\x23\x57\
+                       \xce\xa8\x23\x57";
+
+        let (_, parsed) = parse_cd(raw_cd).unwrap();
+        let unix1 = parsed.unix1().unwrap();
+        assert_eq!(unix1.mtime, 0x5723_a8ce);
+        assert_eq!(unix1.ids, None);
+        assert_eq!(parsed.unix_mtime(), Some(0x5723_a8ce));
+    }
+
+    #[test]
+    fn cd_extended_timestamp_test() {
+        // cd_headertest's CD with a 9 byte extended timestamp extra field, mtime only
+        let raw_cd = b"PK\x01\x02\x14\x00\x14\x00\x08\x08\x08\x00i\x8c\
+                       \x9dH\x1f\xcd]z/\x11\x00\x00,'\x00\x00\x07\x00\
+                       \x09\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                       \xd1\x02\x00\x00b.classUT\x05\x00\x03\xce\xa8\x23\x57";
+
+        let (rest, parsed) = parse_cd(raw_cd).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed.filename, "b.class".to_string());
+        assert_eq!(parsed.extra,
+                   vec![ExtraField {
+                            id: EF_EXTENDED_TIMESTAMP,
+                            len: 5,
+                            data: vec![0x03, 0xce, 0xa8, 0x23, 0x57],
+                        }]);
+        assert_eq!(parsed.unix1(), None);
+    }
+
+    #[test]
+    fn cd_file_comment_test() {
+        // cd_unix1_test's CD with a comment, then the start of the next CD
+        let raw_cd = b"PK\x01\x02\x14\x00\x14\x00\x08\x08\x08\x00i\x8c\
+                       \x9dH\x1f\xcd]z/\x11\x00\x00,'\x00\x00\x07\x00\
+                       \x0c\x00\x0b\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                       \xd1\x02\x00\x00b.classUX\x08\x00\xce\xa8\x23\x57\
+                       \xce\xa8\x23\x57a class fooPK\x01\x02\x14\x00\x14";
+
+        let (rest, parsed) = parse_cd(raw_cd).unwrap();
+        assert_eq!(parsed.filename, "b.class".to_string());
+        assert_eq!(parsed.file_comment, "a class foo".to_string());
+        assert_eq!(parsed.unix_mtime(), Some(0x5723_a8ce));
+        assert_eq!(rest, &b"PK\x01\x02\x14\x00\x14"[..]);
+        assert_eq!(parsed.unparse(), raw_cd[..raw_cd.len() - rest.len()].to_vec());
+    }
+
+    #[test]
+    fn cd_zeroed_timestamp_test() {
+        // cd_unparse_roundtrip's CD with the time and date zeroed out
+        let raw_cd = b"PK\x01\x02\x14\x00\x14\x00\x08\x08\x08\x00\x00\x00\
+                       \x00\x00\x1f\xcd]z/\x11\x00\x00,'\x00\x00\x07\x00\
+                       \x0c\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                       \xd1\x02\x00\x00b.classUX\x08\x00\xce\xa8\x23\x57\
+                       \xce\xa8\x23\x57";
+        assert!(parse_dosdatetime(&raw_cd[12..16]).is_err());
+
+        let (rest, parsed) = parse_cd(raw_cd).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed.filename, "b.class".to_string());
+        assert_eq!(parsed.lf_offset, 0x2d1);
+        assert_eq!(parsed.timestamp, 0);
+        assert!(!parsed.timestamp_valid);
+
+        // Its local header has to parse just the same, or we'll never place its data
+        let (_, lf) = parse_lf(&LF::from(&parsed).unparse()).unwrap();
+        assert_eq!(lf.timestamp, 0);
+        assert_eq!(&lf.raw_datetime.unwrap(), &raw_cd[12..16]);
+
+        let (_, parsed) = parse_cd(&raw_cd[..12]
+            .iter()
+            .chain(b"i\x8c\x9dH")
+            .chain(&raw_cd[16..])
+            .cloned()
+            .collect::<Vec<_>>())
+            .unwrap();
+        assert!(parsed.timestamp_valid);
+    }
+
+    #[test]
+    fn cd_unparse_roundtrip() {
+        let raw_cd = b"PK\x01\x02\x14\x00\x14\x00\x08\x08\x08\x00i\x8c\
+                       \x9dH\x1f\xcd]z/\x11\x00\x00,'\x00\x00\x07\x00\
+                       \x0c\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+                       \xd1\x02\x00\x00b.classUX\x08\x00\xce\xa8\x23\x57\
+                       \xce\xa8\x23\x57";
+
+        let (_, parsed) = parse_cd(raw_cd).unwrap();
+        assert_eq!(parsed.unparse(), raw_cd.to_vec());
+    }
+
+    #[test]
+    fn dd64_test() {
+        let raw_dd = b"PK\x07\x08\x1f\xcd]z\x00\x00\x00\x00\x01\x00\x00\x00\
+                       \x2c\x27\x00\x00\x01\x00\x00\x00";
+
+        let (rest, parsed) = parse_trailing_dd_with(raw_dd, b"PK\x07\x08", true).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed.crc32, 0x7a5d_cd1f);
+        assert_eq!(parsed.z_sz, 0x1_0000_0000);
+        assert_eq!(parsed.u_sz, 0x1_0000_272c);
+    }
+
+    #[test]
+    fn cd_signature_test() {
+        let raw_sig = b"PK\x05\x05\x03\x00sigPK\x05\x06";
+
+        let (rest, parsed) = parse_cd_signature(raw_sig).unwrap();
+        assert_eq!(parsed, b"sig");
+        assert_eq!(rest, b"PK\x05\x06");
+    }
+
     #[test]
     fn lf_headertest() {
         let raw_lf = b"PK\x03\x04\n\x00\x00\x08\x08\x00N\x83EIRa\xe7Sh\
@@ -204,4 +660,15 @@ mod tests {
         assert_eq!(parsed.filename, "bc.class".to_string());
 
     }
+
+    #[test]
+    fn lf_cp437_filename() {
+        // As above, but without the UTF-8 flag and with a CP437 'ü' in the name
+        let raw_lf = b"PK\x03\x04\n\x00\x00\x00\x08\x00N\x83EIRa\xe7Sh\
+                       \x00\x00\x00u\x00\x00\x00\x08\x00\x00\x00\x81c.cla\
+                       ss";
+        let (_, parsed) = parse_lf(raw_lf).unwrap();
+        assert_eq!(parsed.filename, "üc.class");
+        assert_eq!(decode_filename(b"\x81c.class", 0x0800), "\u{fffd}c.class");
+    }
 }
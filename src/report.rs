@@ -0,0 +1,235 @@
+//! What a run of `rip_a_zip` got up to, for callers who'd rather check than read the logs.
+
+use std::cmp;
+use std::fmt;
+#[cfg(all(feature = "std", feature = "serialize"))]
+use std::fs::File;
+#[cfg(all(feature = "std", feature = "serialize"))]
+use std::io::{Error, ErrorKind};
+#[cfg(all(feature = "std", feature = "serialize"))]
+use std::path::Path;
+use std::path::PathBuf;
+
+use chunks::{CD, EOCD, FragSys, Page, ZipFile};
+#[cfg(all(feature = "std", feature = "serialize"))]
+use serde_json;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Summary of one reconstructed archive
+pub struct ArchiveSummary {
+    /// Number of central directory entries recovered
+    pub entries: usize,
+    /// Bytes of the dump assigned to the archive
+    pub bytes_recovered: usize,
+    /// Number of pages found in the dump
+    pub pages_assigned: usize,
+    /// Number of pages we rebuilt from what we knew rather than found
+    pub pages_synthesized: usize,
+    /// Number of pages we never found
+    pub pages_missing: usize,
+    /// Number of entries which decompressed to the CRC32 in their CD, if we checked
+    pub entries_verified: Option<usize>,
+    /// Names of the entries which are encrypted, so only their raw bytes were recovered and
+    /// nothing about them was checked
+    pub encrypted_entries: Vec<String>,
+    /// Whether any entry says its headers were masked, so the fields we reconstructed from may
+    /// have been scrubbed
+    pub masked_records: bool,
+    /// How much we believe the reconstruction, as per `ZipFile::confidence`
+    pub confidence: f64,
+}
+
+impl ArchiveSummary {
+    /// Summarise `zip`, whose `pagesz` pages come from a dump `data_len` bytes long.
+    ///
+    /// An assigned slot counts for as many pages as its range covers, so this comes out the same
+    /// after `ZipFile::coalesce_pages` as before.
+    pub fn of(zip: &ZipFile, data_len: usize, pagesz: usize) -> Self {
+        let mut summary = ArchiveSummary {
+            entries: zip.cached_entries().len(),
+            encrypted_entries: zip.cached_entries()
+                .iter()
+                .filter(|cd| cd.is_encrypted())
+                .map(|cd| cd.filename.clone())
+                .collect(),
+            masked_records: zip.cached_entries().iter().any(|cd| cd.has_masked_records()),
+            ..ArchiveSummary::default()
+        };
+        for page in &zip.pages {
+            match *page {
+                Page::Assigned(ref range) => {
+                    summary.pages_assigned += (range.len() + pagesz - 1) / pagesz;
+                    summary.bytes_recovered += cmp::min(range.end, data_len)
+                        .saturating_sub(range.start);
+                }
+                Page::Synthesized(_) => summary.pages_synthesized += 1,
+                Page::Unassigned => summary.pages_missing += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// Number of missing page indexes `Coverage` will list before it gives up and just says there
+/// are more
+const COVERAGE_LIST_MAX: usize = 8;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Which of an archive's pages we've got, for seeing at a glance what a partial recovery lacks
+pub struct Coverage {
+    /// Number of pages the archive spans
+    pub total_pages: usize,
+    /// Number of pages found in the dump
+    pub assigned_pages: usize,
+    /// Indexes of the pages still `Unassigned`
+    pub missing: Vec<usize>,
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{} pages", self.assigned_pages, self.total_pages)?;
+        if self.missing.is_empty() {
+            return Ok(());
+        }
+        let listed = self.missing
+            .iter()
+            .take(COVERAGE_LIST_MAX)
+            .map(|idx| idx.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let more = if self.missing.len() > COVERAGE_LIST_MAX { ", ..." } else { "" };
+        write!(f, " ({} missing: [{}{}])", self.missing.len(), listed, more)
+    }
+}
+
+impl ZipFile {
+    /// Which pages of the archive we have and which we're still missing. Synthesized pages count
+    /// as neither.
+    pub fn coverage(&self) -> Coverage {
+        let mut coverage = Coverage {
+            total_pages: self.pages.len(),
+            ..Coverage::default()
+        };
+        for (idx, page) in self.pages.iter().enumerate() {
+            match *page {
+                Page::Assigned(_) => coverage.assigned_pages += 1,
+                Page::Unassigned => coverage.missing.push(idx),
+                Page::Synthesized(_) => {}
+            }
+        }
+        coverage
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// Outcome of a `rip_a_zip` run
+pub struct RipReport {
+    /// Number of archives identified in the dump
+    pub zip_files: usize,
+    /// Number of clusters the central directory headers were split into with kmeans, or `None`
+    /// for DBSCAN or when clustering failed
+    pub cluster_k: Option<usize>,
+    /// Summary of each archive, in output order
+    pub archives: Vec<ArchiveSummary>,
+    /// Where each archive was written
+    pub outputs: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+/// A record of what a reconstruction decided, for diffing one run against another or handing to
+/// other tooling without having to scrape the logs
+pub struct Manifest {
+    /// Page size the dump was reconstructed at
+    pub page_sz: usize,
+    /// Offsets of every EOCD found in the dump, whether or not it made it into an archive
+    pub eocd_offsets: Vec<usize>,
+    /// Each reconstructed archive, in output order
+    pub archives: Vec<ArchiveManifest>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+/// One archive's worth of a `Manifest`
+pub struct ArchiveManifest {
+    /// Offset of the archive's EOCD in the dump
+    pub eocd_offset: usize,
+    /// The EOCD itself
+    pub eocd: EOCD,
+    /// The central directory entries which ended up in this archive, i.e. the CD cluster it won
+    pub entries: Vec<CD>,
+    /// Where each page of the archive came from
+    pub pages: Vec<Page>,
+    /// `ZipFile::byte_completion` at the end of the reconstruction
+    pub byte_completion: f64,
+    /// LF offsets of the entries whose local headers we had to rebuild
+    pub synthesized_lfs: Vec<u32>,
+}
+
+impl Manifest {
+    /// Record the reconstruction of `zips` from `fs`.
+    pub fn new(fs: &FragSys, zips: &[ZipFile]) -> Self {
+        let page_sz = fs.page_sz();
+        Manifest {
+            page_sz: page_sz,
+            eocd_offsets: fs.find_eocd_groups().into_iter().flat_map(|group| group).collect(),
+            archives: zips.iter()
+                .map(|zip| ArchiveManifest {
+                    eocd_offset: zip.eocd_ptr(),
+                    eocd: zip.eocd.clone(),
+                    entries: zip.cached_entries().to_vec(),
+                    pages: zip.pages.clone(),
+                    byte_completion: zip.byte_completion(page_sz),
+                    synthesized_lfs: zip.synthesized_lfs().to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Write the manifest out to `path` as JSON.
+    #[cfg(all(feature = "std", feature = "serialize"))]
+    pub fn write_json(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer_pretty(File::create(path)?, self)
+            .map_err(|e| Error::new(ErrorKind::Other, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "serialize")]
+    use serde_json;
+
+    use testgen::{reconstructed, stored_archive};
+    use report::*;
+
+    #[test]
+    fn summary_survives_coalescing() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let (fs, mut zips) = reconstructed(&stored_archive(&files, 1_500_000_000));
+        assert_eq!(zips.len(), 1);
+
+        let before = ArchiveSummary::of(&zips[0], fs.data.len(), 0x40);
+        assert!(before.pages_assigned > 1);
+        zips[0].coalesce_pages();
+        assert!(zips[0].pages().len() < before.pages_assigned + before.pages_missing);
+        assert_eq!(ArchiveSummary::of(&zips[0], fs.data.len(), 0x40), before);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn manifest_round_trip() {
+        let files = [("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])];
+        let (fs, zips) = reconstructed(&stored_archive(&files, 1_500_000_000));
+        let manifest = Manifest::new(&fs, &zips);
+        assert_eq!(manifest.archives.len(), 1);
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.contains(r#"{"Assigned":{"start":0,"end":64}}"#));
+
+        let parsed: Manifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.eocd_offsets, manifest.eocd_offsets);
+        assert_eq!(parsed.archives[0].eocd, manifest.archives[0].eocd);
+        assert_eq!(parsed.archives[0].entries, manifest.archives[0].entries);
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+    }
+}
@@ -0,0 +1,238 @@
+//! Generators for fragmented dumps with known ground truth, for testing reconstruction end to end.
+//!
+//! Give `fragment` a few archives and a page size and it'll chop them into pages, shuffle the lot
+//! together the way an unhelpful file system might, and tell you where every page ended up. Only
+//! built for tests, or with the `test-util` feature for anyone wanting to use it from outside.
+
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{Error, Read};
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use flate2::{Compression, Crc};
+use flate2::write::DeflateEncoder;
+
+use analysis::XorShift;
+use chunks::{CD, DD, EOCD, FragSys, LF, ZipFile, ZipFlags};
+use options::DefragOptions;
+
+#[derive(Debug)]
+/// A synthetic fragmented dump along with the page order it was made from
+pub struct FragmentedDump {
+    /// The dump itself
+    pub data: Vec<u8>,
+    /// Page size the dump was fragmented at
+    pub page_sz: usize,
+    /// For each input archive, the index of the dump page holding each of its pages in order
+    pub order: Vec<Vec<usize>>,
+}
+
+impl FragmentedDump {
+    /// Put the `archive`th archive back together from the ground truth page order. Its final page
+    /// comes back zero-padded.
+    pub fn reassemble(&self, archive: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.order[archive].len() * self.page_sz);
+        for &pg in &self.order[archive] {
+            out.extend_from_slice(&self.data[pg * self.page_sz..(pg + 1) * self.page_sz]);
+        }
+        out
+    }
+}
+
+/// Chop `archives` into `page_sz` pages (zero padding the last page of each) and shuffle them all
+/// together into one dump. The same `seed` always gives the same dump.
+pub fn fragment(archives: &[Vec<u8>], page_sz: usize, seed: u64) -> FragmentedDump {
+    // (archive, page within archive) for every page, in dump order once shuffled
+    let mut pages = vec![];
+    for (i, archive) in archives.iter().enumerate() {
+        for pg in 0..(archive.len() + page_sz - 1) / page_sz {
+            pages.push((i, pg));
+        }
+    }
+
+    // Fisher-Yates
+    let mut rng = XorShift::new(seed);
+    for i in (1..pages.len()).rev() {
+        let j = (rng.next() % (i as u64 + 1)) as usize;
+        pages.swap(i, j);
+    }
+
+    let mut data = vec![0u8; pages.len() * page_sz];
+    let mut order = archives
+        .iter()
+        .map(|archive| vec![0; (archive.len() + page_sz - 1) / page_sz])
+        .collect::<Vec<_>>();
+    for (dump_pg, &(i, pg)) in pages.iter().enumerate() {
+        let start = pg * page_sz;
+        let end = ::std::cmp::min(start + page_sz, archives[i].len());
+        data[dump_pg * page_sz..dump_pg * page_sz + end - start]
+            .copy_from_slice(&archives[i][start..end]);
+        order[i][pg] = dump_pg;
+    }
+
+    FragmentedDump {
+        data: data,
+        page_sz: page_sz,
+        order: order,
+    }
+}
+
+/// As `fragment`, reading the archives from `.zip` files on disk.
+#[cfg(feature = "std")]
+pub fn fragment_files<P: AsRef<Path>>(paths: &[P], page_sz: usize, seed: u64)
+    -> Result<FragmentedDump, Error>
+{
+    let mut archives = Vec::with_capacity(paths.len());
+    for path in paths {
+        let mut archive = vec![];
+        File::open(path)?.read_to_end(&mut archive)?;
+        archives.push(archive);
+    }
+    Ok(fragment(&archives, page_sz, seed))
+}
+
+/// Build a minimal archive of stored `files`, all stamped with `timestamp`, for when there isn't
+/// a real one to hand.
+pub fn stored_archive(files: &[(&str, &[u8])], timestamp: u32) -> Vec<u8> {
+    build_archive(files, timestamp, false)
+}
+
+/// As `stored_archive`, but with the `files` deflated.
+pub fn deflated_archive(files: &[(&str, &[u8])], timestamp: u32) -> Vec<u8> {
+    build_archive(files, timestamp, true)
+}
+
+/// As `stored_archive`, but with the central directory's size and offset left to a Zip64 record
+/// and locator, and nothing but sentinels in the EOCD.
+pub fn zip64_archive(files: &[(&str, &[u8])], timestamp: u32) -> Vec<u8> {
+    let mut archive = build_archive(files, timestamp, false);
+    // build_archive always ends in an EOCD with no comment
+    let eocd_start = archive.len() - 22;
+    let eocd = ::parser::parse_eocd(&archive[eocd_start..]).unwrap().1;
+    archive.truncate(eocd_start);
+
+    let eocd64_offset = archive.len() as u64;
+    let entries = u64::from(eocd.tot_entries);
+    archive.extend_from_slice(b"PK\x06\x06");
+    for &(value, n) in &[(44, 8), (45, 2), (45, 2), (0, 4), (0, 4), (entries, 8), (entries, 8),
+                         (u64::from(eocd.cd_sz), 8), (u64::from(eocd.cd_offset), 8)] {
+        push_le(&mut archive, value, n);
+    }
+    archive.extend_from_slice(b"PK\x06\x07");
+    for &(value, n) in &[(0, 4), (eocd64_offset, 8), (1, 4)] {
+        push_le(&mut archive, value, n);
+    }
+    archive.extend(EOCD {
+        dsk_entries: u16::max_value(),
+        tot_entries: u16::max_value(),
+        cd_sz: u32::max_value(),
+        cd_offset: u32::max_value(),
+        ..eocd
+    }.unparse());
+    archive
+}
+
+/// Append the low `n` bytes of `value` to `buf`, little endian
+fn push_le(buf: &mut Vec<u8>, value: u64, n: usize) {
+    buf.extend((0..n).map(|i| (value >> (8 * i)) as u8));
+}
+
+/// Put `archive` 0x10 bytes into a dump of 0x40 byte pages, so that it starts partway into a page
+/// and spans a few of them, and reconstruct it with the default options.
+pub fn reconstructed(archive: &[u8]) -> (FragSys, Vec<ZipFile>) {
+    let mut dump = vec![0u8; 0x10];
+    dump.extend_from_slice(archive);
+    let mut fs = FragSys::from_bytes(dump, 0x40);
+    let zips = ::reconstruct(&mut fs, &DefragOptions::default());
+    (fs, zips)
+}
+
+/// Build an archive of `files`, deflating them if `deflate` is set.
+fn build_archive(files: &[(&str, &[u8])], timestamp: u32, deflate: bool) -> Vec<u8> {
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for &(name, contents) in files {
+        let mut crc = Crc::new();
+        crc.update(contents);
+        let data = if deflate {
+            let mut encoder = DeflateEncoder::new(vec![], Compression::Default);
+            encoder.write_all(contents).expect("writing to a Vec can't fail");
+            encoder.finish().expect("writing to a Vec can't fail")
+        } else {
+            contents.to_vec()
+        };
+        let cd = CD {
+            v_made_by: 20,
+            v_needed: if deflate { 20 } else { 10 },
+            gp_flags: ZipFlags::empty(),
+            method: if deflate { 8 } else { 0 },
+            timestamp: timestamp,
+            timestamp_valid: true,
+            raw_datetime: None,
+            dd: DD {
+                crc32: crc.sum(),
+                z_sz: data.len() as u32,
+                u_sz: contents.len() as u32,
+            },
+            fn_len: name.len() as u16,
+            ef_len: 0,
+            fc_len: 0,
+            dsk_no_s: 0,
+            int_attr: 0,
+            ext_attr: 0,
+            lf_offset: archive.len() as u32,
+            filename: name.to_owned(),
+            raw_filename: None,
+            extra: vec![],
+            file_comment: String::new(),
+            raw_comment: None,
+        };
+        archive.extend_from_slice(&LF::from(&cd).unparse());
+        archive.extend_from_slice(&data);
+        directory.extend_from_slice(&cd.unparse());
+    }
+
+    let eocd = EOCD {
+        dsk_no: 0,
+        dsk_w_cd: 0,
+        dsk_entries: files.len() as u16,
+        tot_entries: files.len() as u16,
+        cd_sz: directory.len() as u32,
+        cd_offset: archive.len() as u32,
+        cmt_len: 0,
+        zip_cmt: String::new(),
+    };
+    archive.extend_from_slice(&directory);
+    archive.extend_from_slice(&eocd.unparse());
+    archive
+}
+
+#[cfg(test)]
+mod tests {
+    use testgen::*;
+    use parser::parse_eocd;
+
+    #[test]
+    fn fragment_ground_truth() {
+        let a: Vec<u8> = (0..0x1234).map(|i| i as u8).collect();
+        let b: Vec<u8> = (0..0x777).map(|i| (i * 7) as u8).collect();
+        let dump = fragment(&[a.clone(), b.clone()], 0x100, 42);
+
+        assert_eq!(dump.data.len(), (0x13 + 0x8) * 0x100);
+        assert_eq!(&dump.reassemble(0)[..a.len()], a.as_slice());
+        assert_eq!(&dump.reassemble(1)[..b.len()], b.as_slice());
+    }
+
+    #[test]
+    fn stored_archive_eocd() {
+        let archive = stored_archive(&[("a.txt", &b"hello"[..]), ("b.txt", &b"world"[..])],
+                                     1_500_000_000);
+
+        let (_, eocd) = parse_eocd(&archive[archive.len() - 22..]).unwrap();
+        assert_eq!(eocd.tot_entries, 2);
+        assert_eq!(eocd.cd_offset as usize + eocd.cd_sz as usize, archive.len() - 22);
+    }
+}